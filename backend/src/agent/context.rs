@@ -0,0 +1,191 @@
+//! Page snapshot the client sends along with an agent request.
+//!
+//! `interactive_elements` and `page_content` go straight into the preamble
+//! (`{{page_context}}`) so the model can act without a
+//! `get_interactive_elements` round trip. Both are cut to
+//! `agent.context_tokens`; elements are kept first because the model needs
+//! their Ref IDs to click or type.
+
+use crate::dtos::agent::{AgentRequest, InteractiveElementDto};
+use crate::i18n::PageContextStrings;
+use crate::tools::output::InteractiveElement;
+
+/// Rough characters-per-token ratio used for budgeting.
+pub const CHARS_PER_TOKEN: usize = 4;
+/// Longer element names are cut; they are labels, not content.
+const MAX_ELEMENT_NAME_CHARS: usize = 80;
+/// Share of the budget elements may take when page text is sent too.
+const ELEMENT_SHARE_PERCENT: usize = 60;
+
+/// One `- Ref {id}: {name} ({role})` line per element.
+pub fn format_interactive_elements(elements: &[InteractiveElementDto]) -> String {
+    elements
+        .iter()
+        .map(format_element)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_element(element: &InteractiveElementDto) -> String {
+    let name = element
+        .name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (name, _) = truncate_chars(&name, MAX_ELEMENT_NAME_CHARS);
+    format!("- Ref {}: {} ({})", element.id, name, element.role)
+}
+
+/// Cuts `text` to at most `max` characters on a char boundary.
+fn truncate_chars(text: &str, max: usize) -> (&str, bool) {
+    match text.char_indices().nth(max) {
+        Some((index, _)) => (&text[..index], true),
+        None => (text, false),
+    }
+}
+
+impl From<&InteractiveElementDto> for InteractiveElement {
+    fn from(element: &InteractiveElementDto) -> Self {
+        Self {
+            id: element.id as i32,
+            role: element.role.clone(),
+            name: element.name.clone(),
+            tag: String::new(),
+            input_type: None,
+            bounds: None,
+        }
+    }
+}
+
+/// What the client saw when it sent the request.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PageContext<'a> {
+    pub elements: &'a [InteractiveElementDto],
+    pub content: Option<&'a str>,
+}
+
+impl<'a> PageContext<'a> {
+    pub fn from_request(request: &'a AgentRequest) -> Self {
+        Self {
+            elements: request.interactive_elements.as_deref().unwrap_or_default(),
+            content: request
+                .page_content
+                .as_deref()
+                .map(str::trim)
+                .filter(|c| !c.is_empty()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty() && self.content.is_none()
+    }
+
+    /// Renders the snapshot in about `token_budget` tokens (headings not
+    /// counted), or an empty string when there is nothing to show.
+    pub fn render(&self, strings: &PageContextStrings, token_budget: usize) -> String {
+        if self.is_empty() || token_budget == 0 {
+            return String::new();
+        }
+        let budget = token_budget * CHARS_PER_TOKEN;
+        let mut output = String::new();
+        let mut used = 0;
+
+        if !self.elements.is_empty() {
+            let share = match self.content {
+                Some(_) => budget * ELEMENT_SHARE_PERCENT / 100,
+                None => budget,
+            };
+            output.push_str(&strings.elements);
+            let mut shown = 0;
+            for element in self.elements {
+                let line = format_element(element);
+                let len = line.chars().count() + 1;
+                if used + len > share {
+                    break;
+                }
+                output.push('\n');
+                output.push_str(&line);
+                used += len;
+                shown += 1;
+            }
+            let omitted = self.elements.len() - shown;
+            if omitted > 0 {
+                output.push('\n');
+                output.push_str(
+                    &strings
+                        .omitted_elements
+                        .replace("{count}", &omitted.to_string()),
+                );
+            }
+        }
+
+        if let Some(content) = self.content {
+            // The line break before the text counts against the budget too
+            let (text, truncated) = truncate_chars(content, budget.saturating_sub(used + 1));
+            output.push_str(&strings.content);
+            if !text.is_empty() {
+                output.push('\n');
+                output.push_str(text);
+            }
+            if truncated {
+                output.push('\n');
+                output.push_str(&strings.truncated_content);
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::Catalog;
+
+    fn element(id: u32, name: &str) -> InteractiveElementDto {
+        InteractiveElementDto {
+            id,
+            role: "button".into(),
+            name: name.into(),
+        }
+    }
+
+    fn strings() -> PageContextStrings {
+        Catalog::builtin().get("en").unwrap().page_context.clone()
+    }
+
+    #[test]
+    fn test_render_lists_elements_and_content() {
+        let elements = [element(1, "Edit\n  Profile"), element(2, "Save")];
+        let context = PageContext {
+            elements: &elements,
+            content: Some("Welcome back"),
+        };
+        let rendered = context.render(&strings(), 1000);
+        assert!(rendered.starts_with("\n\n## Interactive Elements"));
+        assert!(rendered.contains("\n- Ref 1: Edit Profile (button)\n- Ref 2: Save (button)"));
+        assert!(rendered.ends_with("## Page Text (snapshot)\nWelcome back"));
+        assert_eq!(PageContext::default().render(&strings(), 1000), "");
+    }
+
+    #[test]
+    fn test_render_stays_within_budget() {
+        let elements: Vec<_> = (1..=100).map(|id| element(id, "Item")).collect();
+        let content = "x".repeat(10_000);
+        let context = PageContext {
+            elements: &elements,
+            content: Some(&content),
+        };
+        let rendered = context.render(&strings(), 100);
+        let listed = rendered.matches("- Ref ").count();
+        assert!(listed > 0 && listed < 100);
+        assert!(rendered.contains(&format!("{} more elements omitted", 100 - listed)));
+        assert!(rendered.contains("truncated; call `get_page_content()`"));
+        // Headings and notes aside, the snapshot fits the budget
+        let body: usize = rendered
+            .lines()
+            .filter(|l| l.starts_with("- Ref ") || l.starts_with('x'))
+            .map(|l| l.chars().count() + 1)
+            .sum();
+        assert!(body <= 100 * CHARS_PER_TOKEN);
+    }
+}
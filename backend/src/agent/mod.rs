@@ -1 +1,99 @@
+//! Pieces shared by every entry point that drives the tool-enabled agent.
 
+pub mod context;
+pub mod run;
+
+use rig::OneOrMany;
+use rig::message::{AssistantContent, Message, UserContent};
+use rig::tool::ToolDyn;
+use std::time::Duration;
+
+use crate::auth::Principal;
+use crate::dtos::agent::ChatMessageDto;
+use crate::error::AppError;
+use crate::i18n::{AgentPrompt, Messages};
+use crate::prompts::describe_tools;
+use crate::state::AppState;
+use context::PageContext;
+
+/// Limits applied to every tool-enabled agent run (`[agent]` config).
+#[derive(Debug, Clone, Copy)]
+pub struct AgentLimits {
+    /// Maximum number of tool turns before the run fails with `max_depth`.
+    pub max_depth: usize,
+    /// How long a browser action may take before `tool_timeout`.
+    pub tool_timeout: Duration,
+    /// Approximate token budget for the page snapshot a client sends with
+    /// its request; `0` leaves it out of the prompt.
+    pub context_tokens: usize,
+}
+
+impl Default for AgentLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 20,
+            tool_timeout: Duration::from_secs(30),
+            context_tokens: 2000,
+        }
+    }
+}
+
+impl AgentLimits {
+    /// Tool depth for a run: the client's choice, capped by `max_depth`.
+    pub fn depth(&self, requested: Option<usize>) -> Result<usize, AppError> {
+        match requested {
+            None => Ok(self.max_depth),
+            Some(depth) if (1..=self.max_depth).contains(&depth) => Ok(depth),
+            Some(_) => Err(AppError::InvalidRequest(format!(
+                "max_depth must be between 1 and {}",
+                self.max_depth
+            ))),
+        }
+    }
+}
+
+/// Renders the agent preamble for a session. The tool list comes from the
+/// tools' own definitions, the page URL from the session's page snapshot and
+/// `page_context` from the client. `custom_instruction` is the request's own
+/// layer on top of the stored workspace, site and user instructions.
+pub async fn agent_preamble(
+    state: &AppState,
+    principal: &Principal,
+    session_id: &str,
+    messages: &Messages,
+    tools: &[Box<dyn ToolDyn>],
+    custom_instruction: Option<&str>,
+    page_context: PageContext<'_>,
+) -> String {
+    let page = state.page_snapshot(session_id).await;
+    let instruction = state
+        .instructions
+        .compose(principal.name(), page.url.as_deref(), custom_instruction)
+        .await;
+    messages.agent_preamble(AgentPrompt {
+        tools: describe_tools(tools).await,
+        page_url: page.url.as_deref(),
+        page_title: page.title.as_deref(),
+        custom_instruction: instruction.as_deref(),
+        page_context: page_context
+            .render(&messages.page_context, state.agent_limits.context_tokens),
+    })
+}
+
+/// Converts client-supplied chat history into rig messages.
+///
+/// Unknown roles are treated as user messages.
+pub fn history_messages(history: &[ChatMessageDto]) -> Vec<Message> {
+    history
+        .iter()
+        .map(|msg| match msg.role.as_str() {
+            "assistant" => Message::Assistant {
+                id: None,
+                content: OneOrMany::one(AssistantContent::text(&msg.content)),
+            },
+            _ => Message::User {
+                content: OneOrMany::one(UserContent::text(&msg.content)),
+            },
+        })
+        .collect()
+}
@@ -0,0 +1,356 @@
+//! Cancellable agent runs.
+//!
+//! Every tool-enabled run on a session gets a run id, sent as the first
+//! event (`run_started`). A run stops early when it is cancelled with
+//! `POST /agent/runs/{id}/cancel`, by a `cancel_run` message from the
+//! session's extension, or when its consumer (the SSE client or a
+//! background task) goes away. Cancelling drops the model stream, fails the
+//! session's unanswered actions and ends the run with a `cancelled` event.
+//!
+//! Every run is also written to the trace store (see [`crate::store::traces`]):
+//! the prompt, the model's text per turn, tool calls and results, token
+//! usage, errors and how the run ended.
+
+use async_stream::stream;
+use axum::response::sse::Event;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+use crate::agent::context::PageContext;
+use crate::agent::{agent_preamble, history_messages};
+use crate::auth::Principal;
+use crate::dtos::AgentRequest;
+use crate::dtos::events::{StreamEvent, ToolEvent};
+use crate::error::AppError;
+use crate::i18n::Messages;
+use crate::llm::{AgentEvent, AgentParams, TokenUsage, user_message};
+use crate::models::trace::TraceRecord;
+use crate::state::AppState;
+use crate::tools::websocket::browser_tools;
+
+/// Reason recorded for the cancel endpoint and `cancel_run` messages.
+pub const CANCELLED_BY_USER: &str = "cancelled by user";
+/// Reason recorded when the SSE client disconnects mid-run.
+pub const CLIENT_DISCONNECTED: &str = "client disconnected";
+
+/// A run whose SSE stream is still open.
+#[derive(Debug)]
+pub struct ActiveRun {
+    pub session_id: String,
+    pub principal: String,
+    /// Unix milliseconds.
+    pub started_at: u64,
+    /// Wakes the run's stream with the cancellation reason.
+    pub cancel: oneshot::Sender<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunInfo {
+    pub run_id: String,
+    pub session_id: String,
+    pub principal: String,
+    pub started_at: u64,
+}
+
+/// Lives inside a run's SSE stream. If the stream is dropped before
+/// [`RunGuard::finish`], the client went away and the run is cancelled.
+pub struct RunGuard {
+    state: Arc<AppState>,
+    run_id: String,
+    finished: bool,
+}
+
+impl RunGuard {
+    pub fn new(state: Arc<AppState>, run_id: String) -> Self {
+        Self {
+            state,
+            run_id,
+            finished: false,
+        }
+    }
+
+    /// Forgets the run after the stream ended on its own (or was cancelled).
+    pub async fn finish(&mut self) {
+        self.finished = true;
+        self.state.finish_run(&self.run_id).await;
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let state = self.state.clone();
+        let run_id = std::mem::take(&mut self.run_id);
+        tokio::spawn(async move {
+            if state
+                .cancel_run(&run_id, CLIENT_DISCONNECTED)
+                .await
+                .is_some()
+            {
+                state.unregister_run_listener(&run_id).await;
+            }
+        });
+    }
+}
+
+/// One item of a run, in the order the client should see it.
+pub enum RunOutput {
+    /// Model text.
+    Text(String),
+    Event(StreamEvent),
+    /// Provider or tool loop error; the run may still continue.
+    Error(AppError),
+}
+
+impl RunOutput {
+    pub fn to_sse_event(&self, messages: &Messages) -> Event {
+        match self {
+            RunOutput::Text(text) => Event::default().data(text),
+            RunOutput::Event(event) => event.to_sse_event(),
+            RunOutput::Error(error) => messages.error_event(error),
+        }
+    }
+}
+
+/// Next item of the model stream or an out-of-band notice.
+enum RunItem {
+    Agent(Result<AgentEvent, AppError>),
+    Notice(StreamEvent),
+    Cancelled(String),
+}
+
+/// Starts the tool-enabled agent on `request.session_id`. Fails before
+/// anything runs when the request is invalid or the caller doesn't own the
+/// session.
+pub async fn start_agent_run(
+    state: Arc<AppState>,
+    principal: Principal,
+    request: AgentRequest,
+    messages: Arc<Messages>,
+) -> Result<BoxStream<'static, RunOutput>, AppError> {
+    let Some(session_id) = request.session_id.clone() else {
+        return Err(AppError::InvalidRequest(
+            "session_id is required".to_string(),
+        ));
+    };
+    // Per-request overrides must stay within what the server allows
+    let overrides = request.generation_overrides();
+    state.model_settings.check_overrides(&overrides)?;
+    let max_depth = state.agent_limits.depth(request.max_depth)?;
+    state.authorize_session(&session_id, &principal).await?;
+    tracing::info!(
+        "Using streaming tool-enabled agent with session_id: {}",
+        session_id
+    );
+
+    // Explicit history wins, otherwise continue the stored conversation
+    let conversation = state
+        .conversations
+        .get_or_create(
+            request.conversation_id.as_deref(),
+            Some(&session_id),
+            principal.name(),
+        )
+        .await?;
+    let history = match &request.history {
+        Some(history) => history_messages(history),
+        None => history_messages(&conversation.history()),
+    };
+
+    // A client snapshot saves the model a get_interactive_elements call;
+    // the refs also feed the approval policy until the page changes
+    let page_context = PageContext::from_request(&request);
+    if !page_context.elements.is_empty() {
+        state
+            .record_page_elements(
+                &session_id,
+                page_context.elements.iter().map(Into::into).collect(),
+            )
+            .await;
+    }
+
+    // The run exists before its tools so their actions and approvals carry
+    // its id; dropping the stream (consumer gone) cancels it
+    let (run_id, mut cancelled) = state.start_run(&session_id, &principal).await;
+    let mut run = RunGuard::new(state.clone(), run_id.clone());
+    let mut notices = state.register_run_listener(&run_id).await;
+    let tools = browser_tools(&state, &session_id, &run_id);
+    let preamble = agent_preamble(
+        &state,
+        &principal,
+        &session_id,
+        &messages,
+        &tools,
+        request.custom_instruction.as_deref(),
+        page_context,
+    )
+    .await;
+    let model = overrides
+        .model
+        .clone()
+        .unwrap_or_else(|| state.llm.agent_model().to_string());
+    let params = AgentParams {
+        preamble: preamble.clone(),
+        tools,
+        max_depth,
+        prompt: user_message(&request.query, request.image.as_deref()),
+        history,
+        overrides,
+    };
+
+    let mut agent_stream = state.llm.stream_agent(params);
+    let query = request.query;
+    let run_started = Instant::now();
+    let page_url = state.page_snapshot(&session_id).await.url;
+    state
+        .traces
+        .append(
+            &run_id,
+            TraceRecord::RunStarted {
+                run_id: run_id.clone(),
+                session_id: session_id.clone(),
+                principal: principal.name().to_string(),
+                query: query.clone(),
+                model,
+                preamble,
+                page_url,
+            },
+        )
+        .await;
+
+    Ok(Box::pin(stream! {
+        let mut full_response = String::new();
+        // Model text since the last tool call, traced one turn at a time
+        let mut turn_text = String::new();
+        let mut token_usage: Option<TokenUsage> = None;
+        // Tool calls awaiting their result: id -> (name, start time)
+        let mut running_tools: HashMap<String, (String, Instant)> = HashMap::new();
+        let mut cancel_reason = None;
+
+        yield RunOutput::Event(StreamEvent::RunStarted { run_id: run_id.clone() });
+        yield RunOutput::Event(StreamEvent::Conversation {
+            conversation_id: conversation.id.clone(),
+        });
+
+        loop {
+            // Approval requests arrive out of band while a tool call
+            // waits; cancellation wins over output already waiting, and
+            // queued notices go out before the stream can end
+            let item = tokio::select! {
+                biased;
+                reason = &mut cancelled => RunItem::Cancelled(reason.unwrap_or_default()),
+                Some(notice) = notices.recv() => RunItem::Notice(notice),
+                chunk = agent_stream.next() => match chunk {
+                    Some(chunk) => RunItem::Agent(chunk),
+                    None => break,
+                },
+            };
+            let chunk = match item {
+                RunItem::Agent(chunk) => chunk,
+                RunItem::Notice(notice) => {
+                    yield RunOutput::Event(notice);
+                    continue;
+                }
+                RunItem::Cancelled(reason) => {
+                    cancel_reason = Some(reason);
+                    break;
+                }
+            };
+
+            match chunk {
+                Ok(AgentEvent::Text(text)) => {
+                    full_response.push_str(&text);
+                    turn_text.push_str(&text);
+                    yield RunOutput::Text(text);
+                }
+                Ok(AgentEvent::ToolCall { id, name, arguments }) => {
+                    if !turn_text.is_empty() {
+                        let text = std::mem::take(&mut turn_text);
+                        state.traces.append(&run_id, TraceRecord::ModelText { text }).await;
+                    }
+                    let page = state.page_snapshot(&session_id).await;
+                    state
+                        .traces
+                        .append(
+                            &run_id,
+                            TraceRecord::tool_call(id.clone(), name.clone(), arguments.clone(), &page),
+                        )
+                        .await;
+                    running_tools.insert(id.clone(), (name.clone(), Instant::now()));
+                    yield RunOutput::Event(StreamEvent::Tool(ToolEvent::calling(id, name, arguments)));
+                }
+                Ok(AgentEvent::ToolResult { id, content }) => {
+                    let (name, started) = running_tools
+                        .remove(&id)
+                        .unwrap_or_else(|| (String::new(), Instant::now()));
+                    state
+                        .traces
+                        .append(&run_id, TraceRecord::ToolResult {
+                            id: id.clone(),
+                            name: name.clone(),
+                            output: content.clone(),
+                            duration_ms: started.elapsed().as_millis() as u64,
+                        })
+                        .await;
+                    yield RunOutput::Event(StreamEvent::Tool(ToolEvent::finished(id, name, content, started.elapsed())));
+                }
+                Ok(AgentEvent::Usage(usage)) => {
+                    token_usage = Some(usage);
+                }
+                Err(error) => {
+                    tracing::warn!("Agent stream error: {}", error);
+                    state
+                        .traces
+                        .append(&run_id, TraceRecord::Error {
+                            code: error.code().to_string(),
+                            message: error.to_string(),
+                        })
+                        .await;
+                    yield RunOutput::Error(error);
+                }
+            }
+        }
+
+        // Stops the model and any tool call still waiting
+        drop(agent_stream);
+        run.finish().await;
+        state.unregister_run_listener(&run_id).await;
+        state
+            .conversations
+            .append(&conversation.id, vec![("user", query), ("assistant", full_response)])
+            .await;
+
+        if !turn_text.is_empty() {
+            state.traces.append(&run_id, TraceRecord::ModelText { text: turn_text }).await;
+        }
+        if let Some(usage) = token_usage {
+            state.traces.append(&run_id, TraceRecord::Usage(usage)).await;
+        }
+        if let Some(reason) = &cancel_reason {
+            state
+                .traces
+                .append(&run_id, TraceRecord::Cancelled { reason: reason.clone() })
+                .await;
+        }
+        state
+            .traces
+            .append(&run_id, TraceRecord::RunFinished {
+                duration_ms: run_started.elapsed().as_millis() as u64,
+            })
+            .await;
+
+        if let Some(usage) = token_usage {
+            yield RunOutput::Event(StreamEvent::Usage(usage));
+        }
+        if let Some(reason) = cancel_reason {
+            yield RunOutput::Event(StreamEvent::Cancelled { run_id, reason });
+        }
+    }))
+}
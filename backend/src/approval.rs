@@ -0,0 +1,401 @@
+//! Human-in-the-loop approval for risky browser actions.
+//!
+//! Before a click, type or navigate command is sent to the extension, the
+//! [`ApprovalPolicy`] decides whether a human has to confirm it. If so, an
+//! `approval_required` event goes out over the session's WebSocket and the
+//! running agent's SSE stream, and the tool call waits until the user
+//! approves, edits or rejects it (`POST /approvals/{id}` or an
+//! `approval_response` WebSocket message). Rejections reach the model as a
+//! tool error.
+
+use serde::{Deserialize, Serialize};
+use std::mem::discriminant;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use url::Url;
+use uuid::Uuid;
+
+use crate::dtos::events::StreamEvent;
+use crate::error::AppError;
+use crate::models::ws::{ActionCommand, WsMessage};
+use crate::session::PageSnapshot;
+use crate::state::AppState;
+use crate::utils::time::now_millis;
+
+/// Button/link names that trigger approval when clicked.
+pub const DEFAULT_CLICK_KEYWORDS: &[&str] = &[
+    "buy", "beli", "pay", "bayar", "checkout", "purchase", "order", "delete", "hapus",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApprovalMode {
+    /// Never ask.
+    #[default]
+    Off,
+    /// Ask when a policy rule matches.
+    Policy,
+    /// Ask for every click, type and navigate.
+    Always,
+}
+
+impl ApprovalMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "policy" => Some(Self::Policy),
+            "always" => Some(Self::Always),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApprovalPolicy {
+    pub mode: ApprovalMode,
+    /// Typing into password-like fields needs approval.
+    pub password_fields: bool,
+    /// Navigating to another host than the current page needs approval.
+    pub off_domain_navigation: bool,
+    /// Clicking an element whose name contains one of these (case-insensitive).
+    pub click_keywords: Vec<String>,
+    /// How long to wait for a decision before rejecting.
+    pub timeout: Duration,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self {
+            mode: ApprovalMode::Off,
+            password_fields: true,
+            off_domain_navigation: true,
+            click_keywords: DEFAULT_CLICK_KEYWORDS
+                .iter()
+                .map(|k| k.to_string())
+                .collect(),
+            timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+impl ApprovalPolicy {
+    /// Returns why `command` needs approval, or `None` when it can run.
+    pub fn evaluate(&self, command: &ActionCommand, page: &PageSnapshot) -> Option<String> {
+        let gated = matches!(
+            command,
+            ActionCommand::NavigateTo { .. }
+                | ActionCommand::ClickElement { .. }
+                | ActionCommand::TypeText { .. }
+        );
+        match self.mode {
+            ApprovalMode::Off => None,
+            ApprovalMode::Always if gated => Some("Approval required for every action".into()),
+            ApprovalMode::Always => None,
+            ApprovalMode::Policy => self.match_rule(command, page),
+        }
+    }
+
+    /// Targets that can't be resolved against the page need approval too:
+    /// the rules can't tell what they would hit.
+    fn match_rule(&self, command: &ActionCommand, page: &PageSnapshot) -> Option<String> {
+        match command {
+            ActionCommand::TypeText { ref_id, .. } if self.password_fields => {
+                let Some(element) = page.element(*ref_id) else {
+                    return Some(format!("Typing into unknown element {}", ref_id));
+                };
+                element
+                    .is_password_like()
+                    .then(|| format!("Typing into sensitive field \"{}\"", element.name))
+            }
+            ActionCommand::NavigateTo { url } if self.off_domain_navigation => {
+                let Some(target) = host_of(url) else {
+                    return Some(format!("Navigating to unrecognized URL {}", url));
+                };
+                let Some(current) = page.url.as_deref().and_then(host_of) else {
+                    return Some(format!("Navigating to {} from an unknown page", target));
+                };
+                (current != target).then(|| format!("Navigating off-domain to {}", target))
+            }
+            ActionCommand::ClickElement { ref_id } => {
+                let Some(element) = page.element(*ref_id) else {
+                    return Some(format!("Clicking unknown element {}", ref_id));
+                };
+                let name = element.name.to_lowercase();
+                self.click_keywords
+                    .iter()
+                    .any(|k| name.contains(&k.to_lowercase()))
+                    .then(|| format!("Clicking \"{}\"", element.name))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(|h| h.to_lowercase())
+}
+
+/// The user's answer to an approval request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    Approve,
+    /// Run a modified command instead (same action type).
+    Edit {
+        command: ActionCommand,
+    },
+    Reject {
+        #[serde(default)]
+        reason: Option<String>,
+    },
+}
+
+impl ApprovalDecision {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ApprovalDecision::Approve => "approved",
+            ApprovalDecision::Edit { .. } => "edited",
+            ApprovalDecision::Reject { .. } => "rejected",
+        }
+    }
+}
+
+/// Public view of an action waiting for approval.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalRequest {
+    pub approval_id: String,
+    pub session_id: String,
+    /// Owner of the session when the approval was requested; only they may
+    /// see or decide it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+    /// Agent run waiting on the decision; `None` for replays.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    pub command: ActionCommand,
+    pub reason: String,
+    pub created_at: u64,
+}
+
+pub struct PendingApproval {
+    pub request: ApprovalRequest,
+    pub responder: oneshot::Sender<ApprovalDecision>,
+}
+
+/// Runs `command` through the approval policy. Returns the command to
+/// execute (possibly edited by the user) or `ActionRejected`.
+pub async fn review(
+    state: &Arc<AppState>,
+    session_id: &str,
+    run_id: Option<&str>,
+    command: ActionCommand,
+) -> Result<ActionCommand, AppError> {
+    let page = state.page_snapshot(session_id).await;
+    let Some(reason) = state.approval_policy.evaluate(&command, &page) else {
+        return Ok(command);
+    };
+
+    let request = ApprovalRequest {
+        approval_id: Uuid::new_v4().to_string(),
+        session_id: session_id.to_string(),
+        principal: state.session_principal(session_id).await,
+        run_id: run_id.map(str::to_string),
+        command: command.clone(),
+        reason,
+        created_at: now_millis(),
+    };
+    let approval_id = request.approval_id.clone();
+    tracing::info!(
+        "Approval required[{}] for session {}: {}",
+        approval_id,
+        session_id,
+        request.reason
+    );
+
+    let (tx, rx) = oneshot::channel();
+    state.register_approval(request.clone(), tx).await;
+
+    if let Some(connection) = state.get_connection(session_id).await {
+        let _ = connection.send(WsMessage::ApprovalRequired {
+            approval_id: approval_id.clone(),
+            command: request.command.clone(),
+            reason: request.reason.clone(),
+        });
+    }
+    if let Some(run_id) = run_id {
+        state
+            .notify_run(run_id, StreamEvent::ApprovalRequired(request))
+            .await;
+    }
+
+    let decision = match timeout(state.approval_policy.timeout, rx).await {
+        Ok(Ok(decision)) => decision,
+        Ok(Err(_)) => ApprovalDecision::Reject {
+            reason: Some("approval request was dropped".into()),
+        },
+        Err(_) => {
+            state.take_approval(&approval_id).await;
+            ApprovalDecision::Reject {
+                reason: Some("no decision before the approval timeout".into()),
+            }
+        }
+    };
+    tracing::info!("Approval[{}] {}", approval_id, decision.label());
+    if let Some(run_id) = run_id {
+        state
+            .notify_run(
+                run_id,
+                StreamEvent::ApprovalResolved {
+                    approval_id,
+                    decision: decision.label(),
+                },
+            )
+            .await;
+    }
+
+    match decision {
+        ApprovalDecision::Approve => Ok(command),
+        ApprovalDecision::Edit { command: edited } => Ok(edited),
+        ApprovalDecision::Reject { reason } => Err(AppError::ActionRejected(
+            reason.unwrap_or_else(|| "rejected by the user".into()),
+        )),
+    }
+}
+
+/// Checks an edit keeps the action type of the original command.
+pub fn validate_edit(
+    original: &ActionCommand,
+    decision: &ApprovalDecision,
+) -> Result<(), AppError> {
+    match decision {
+        ApprovalDecision::Edit { command } if discriminant(command) != discriminant(original) => {
+            Err(AppError::InvalidRequest(
+                "an edit must keep the original action type".into(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::output::InteractiveElement;
+
+    fn element(id: i32, name: &str, input_type: Option<&str>) -> InteractiveElement {
+        InteractiveElement {
+            id,
+            role: "textbox".into(),
+            name: name.into(),
+            tag: "INPUT".into(),
+            input_type: input_type.map(str::to_string),
+            bounds: None,
+        }
+    }
+
+    fn page() -> PageSnapshot {
+        PageSnapshot {
+            url: Some("https://shop.example.com/cart".into()),
+            elements: vec![
+                element(1, "Email", Some("email")),
+                element(2, "Secret", Some("password")),
+                element(3, "Buy now", None),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn policy() -> ApprovalPolicy {
+        ApprovalPolicy {
+            mode: ApprovalMode::Policy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_policy_rules() {
+        let policy = policy();
+        let page = page();
+
+        let typing = |ref_id| ActionCommand::TypeText {
+            ref_id,
+            text: "x".into(),
+        };
+        assert!(policy.evaluate(&typing(1), &page).is_none());
+        assert!(policy.evaluate(&typing(2), &page).is_some());
+
+        assert!(
+            policy
+                .evaluate(&ActionCommand::ClickElement { ref_id: 3 }, &page)
+                .is_some()
+        );
+
+        let navigate = |url: &str| ActionCommand::NavigateTo { url: url.into() };
+        assert!(
+            policy
+                .evaluate(&navigate("https://shop.example.com/pay"), &page)
+                .is_none()
+        );
+        assert!(
+            policy
+                .evaluate(&navigate("https://evil.test/"), &page)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_unresolved_targets_need_approval() {
+        let policy = policy();
+        let page = page();
+
+        assert!(
+            policy
+                .evaluate(&ActionCommand::ClickElement { ref_id: 99 }, &page)
+                .is_some()
+        );
+        let typing = ActionCommand::TypeText {
+            ref_id: 99,
+            text: "x".into(),
+        };
+        assert!(policy.evaluate(&typing, &page).is_some());
+
+        let navigate = |url: &str| ActionCommand::NavigateTo { url: url.into() };
+        assert!(policy.evaluate(&navigate("not a url"), &page).is_some());
+        assert!(
+            policy
+                .evaluate(
+                    &navigate("https://shop.example.com/pay"),
+                    &PageSnapshot::default()
+                )
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_off_mode_never_asks() {
+        let policy = ApprovalPolicy::default();
+        assert!(
+            policy
+                .evaluate(&ActionCommand::ClickElement { ref_id: 3 }, &page())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_edit_must_keep_action_type() {
+        let original = ActionCommand::ClickElement { ref_id: 3 };
+        let edit = ApprovalDecision::Edit {
+            command: ActionCommand::ScrollTo { x: 0, y: 0 },
+        };
+        assert!(validate_edit(&original, &edit).is_err());
+        assert!(validate_edit(&original, &ApprovalDecision::Approve).is_ok());
+    }
+
+    #[test]
+    fn test_decision_deserialization() {
+        let decision: ApprovalDecision =
+            serde_json::from_str(r#"{"decision":"reject","reason":"no"}"#).unwrap();
+        assert!(matches!(decision, ApprovalDecision::Reject { reason: Some(r) } if r == "no"));
+    }
+}
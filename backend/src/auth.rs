@@ -0,0 +1,270 @@
+//! Authentication for HTTP and WebSocket clients.
+//!
+//! Clients present either an API key or an HS256-signed token (JWT with
+//! `sub` and `exp`) as `Authorization: Bearer ...`, `X-API-Key` or, for
+//! WebSockets that can't set headers, the `access_token` query parameter.
+//! The resulting [`Principal`] owns every WebSocket session it opens; other
+//! principals can't run agents or resolve approvals on it.
+//!
+//! With no API keys and no token secret configured, authentication is off and
+//! every client is the `anonymous` principal.
+//!
+//! The admin API (`/admin/...`) is limited to the principals in
+//! `auth.admins`; it is open to everyone while authentication is off.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, Uri, header},
+    middleware::Next,
+    response::Response,
+};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::utils::time::now_millis;
+
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Default lifetime of tokens issued by `POST /auth/token`.
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(3600);
+
+/// The authenticated caller.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Principal(pub String);
+
+impl Principal {
+    pub fn anonymous() -> Self {
+        Self(ANONYMOUS.to_string())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// API key -> principal name.
+    pub api_keys: HashMap<String, String>,
+    /// Secret for HS256 tokens; tokens are rejected when unset.
+    pub token_secret: Option<String>,
+    pub token_ttl: Duration,
+    /// Principals allowed to use the admin API.
+    pub admins: HashSet<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            api_keys: HashMap::new(),
+            token_secret: None,
+            token_ttl: DEFAULT_TOKEN_TTL,
+            admins: HashSet::new(),
+        }
+    }
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.token_secret.is_some()
+    }
+
+    /// Admins may inspect and disconnect every principal's sessions.
+    pub fn require_admin(&self, principal: &Principal) -> Result<(), AppError> {
+        if !self.is_enabled() || self.admins.contains(principal.name()) {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(format!(
+            "{} is not an administrator",
+            principal.name()
+        )))
+    }
+
+    /// Parses `API_KEYS`: comma-separated `principal:key` pairs. A bare key
+    /// belongs to the `default` principal.
+    pub fn parse_api_keys(value: &str) -> HashMap<String, String> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((principal, key)) => (key.trim().to_string(), principal.trim().to_string()),
+                None => (entry.to_string(), "default".to_string()),
+            })
+            .collect()
+    }
+
+    pub fn authenticate(&self, credential: Option<&str>) -> Result<Principal, AppError> {
+        if !self.is_enabled() {
+            return Ok(Principal::anonymous());
+        }
+        let credential =
+            credential.ok_or_else(|| AppError::Unauthorized("missing credentials".into()))?;
+
+        // Compare against every key so timing doesn't reveal a partial match
+        let mut principal = None;
+        for (key, name) in &self.api_keys {
+            if constant_time_eq(key.as_bytes(), credential.as_bytes()) {
+                principal = Some(name.clone());
+            }
+        }
+        if let Some(name) = principal {
+            return Ok(Principal(name));
+        }
+
+        let secret = self
+            .token_secret
+            .as_ref()
+            .ok_or_else(|| AppError::Unauthorized("invalid API key".into()))?;
+        decode::<Claims>(
+            credential,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::default(),
+        )
+        .map(|data| Principal(data.claims.sub))
+        .map_err(|e| AppError::Unauthorized(format!("invalid credentials: {}", e)))
+    }
+
+    /// Signs a token for `principal`. Returns the token and its expiry in
+    /// Unix seconds.
+    pub fn issue_token(&self, principal: &Principal) -> Result<(String, u64), AppError> {
+        let secret = self.token_secret.as_ref().ok_or_else(|| {
+            AppError::InvalidRequest("token issuing is not configured (AUTH_TOKEN_SECRET)".into())
+        })?;
+        let exp = now_millis() / 1000 + self.token_ttl.as_secs();
+        let claims = Claims {
+            sub: principal.0.clone(),
+            exp,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .map(|token| (token, exp))
+        .map_err(|e| AppError::Internal(format!("failed to sign token: {}", e)))
+    }
+}
+
+/// Compares secrets without returning early at the first differing byte.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Finds the credential in the request, in order: bearer token, `X-API-Key`,
+/// `access_token` query parameter.
+fn credential(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    if let Some(value) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(value.trim().to_string());
+    }
+    if let Some(value) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(value.trim().to_string());
+    }
+    url::form_urlencoded::parse(uri.query()?.as_bytes())
+        .find(|(name, _)| name == "access_token")
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Middleware authenticating every protected route and storing the
+/// [`Principal`] in the request extensions.
+pub async fn require_auth(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let credential = credential(request.headers(), request.uri());
+    let principal = state.auth.authenticate(credential.as_deref())?;
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            api_keys: AuthConfig::parse_api_keys("alice:key-a, bob:key-b"),
+            token_secret: Some("secret".into()),
+            token_ttl: DEFAULT_TOKEN_TTL,
+            admins: HashSet::from(["alice".to_string()]),
+        }
+    }
+
+    #[test]
+    fn test_disabled_auth_is_anonymous() {
+        let principal = AuthConfig::default().authenticate(None).unwrap();
+        assert_eq!(principal, Principal::anonymous());
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let config = config();
+        assert_eq!(config.authenticate(Some("key-b")).unwrap().name(), "bob");
+        assert!(matches!(
+            config.authenticate(Some("nope")),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            config.authenticate(None),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_signed_tokens() {
+        let config = config();
+        let (token, _) = config.issue_token(&Principal("carol".into())).unwrap();
+        assert_eq!(config.authenticate(Some(&token)).unwrap().name(), "carol");
+
+        let other = AuthConfig {
+            token_secret: Some("other".into()),
+            ..config
+        };
+        assert!(other.authenticate(Some(&token)).is_err());
+    }
+
+    #[test]
+    fn test_require_admin() {
+        let config = config();
+        assert!(config.require_admin(&Principal("alice".into())).is_ok());
+        assert!(matches!(
+            config.require_admin(&Principal("bob".into())),
+            Err(AppError::Forbidden(_))
+        ));
+        // Without authentication there is nobody to tell apart
+        assert!(
+            AuthConfig::default()
+                .require_admin(&Principal::anonymous())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_credential_sources() {
+        let mut headers = HeaderMap::new();
+        let uri: Uri = "/ws?session_id=s&access_token=tok%2B1".parse().unwrap();
+        assert_eq!(credential(&headers, &uri).as_deref(), Some("tok+1"));
+
+        headers.insert("x-api-key", "key-a".parse().unwrap());
+        assert_eq!(credential(&headers, &uri).as_deref(), Some("key-a"));
+
+        headers.insert(header::AUTHORIZATION, "Bearer key-b".parse().unwrap());
+        assert_eq!(credential(&headers, &uri).as_deref(), Some("key-b"));
+    }
+}
@@ -0,0 +1,37 @@
+//! Headless mock extension for manual end-to-end testing.
+//!
+//! Usage: `cargo run --features testing --bin mock_extension -- [ws_url] [site.json]`
+//!
+//! Prints the session id to pass as `session_id` to `/agent/run`.
+
+use backend_rig::testing::{MockExtension, MockSite};
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let ws_url = args
+        .next()
+        .unwrap_or_else(|| "ws://localhost:3000/ws".to_string());
+    let site_path = args
+        .next()
+        .unwrap_or_else(|| "tests/fixtures/mock_site.json".to_string());
+
+    let raw = std::fs::read_to_string(&site_path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", site_path, e));
+    let site = MockSite::from_json(&raw).unwrap_or_else(|e| panic!("Invalid site model: {}", e));
+
+    let extension = MockExtension::connect(&ws_url, site)
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
+    println!("session_id={}", extension.session_id);
+
+    tokio::signal::ctrl_c().await.ok();
+
+    let browser = extension.browser();
+    println!(
+        "Received {} actions, final url: {}",
+        browser.actions.len(),
+        browser.current_page().url
+    );
+    extension.disconnect().await;
+}
@@ -1,23 +1,881 @@
+//! Layered configuration.
+//!
+//! Values come from, in increasing priority: built-in defaults, a TOML file
+//! (`--config`, `CONFIG_FILE`, or `config.toml` when present), environment
+//! variables and command-line flags. Everything is validated once at
+//! startup and all problems are reported together.
+
+use clap::Parser;
+use serde::Deserialize;
 use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::agent::AgentLimits;
+use crate::approval::{ApprovalMode, ApprovalPolicy};
+use crate::auth::AuthConfig;
+use crate::i18n::LocaleStore;
+use crate::llm::ModelSettings;
+use crate::navigation::{NavigationPolicy, NavigationPolicyConfig};
+use crate::store::instructions::InstructionSet;
+use crate::tasks::TaskLimits;
+
+/// Read when neither `--config` nor `CONFIG_FILE` is given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+pub const DEFAULT_BIND: &str = "0.0.0.0:3000";
+pub const MAX_AGENT_DEPTH: usize = 100;
+pub const MAX_TOOL_TIMEOUT_SECS: u64 = 600;
+pub const MAX_CONTEXT_TOKENS: usize = 32_000;
+pub const MAX_CONCURRENT_TASKS: usize = 256;
+pub const MAX_APPROVAL_TIMEOUT_SECS: u64 = 3600;
+
+/// LLM backend selected with `llm.provider` / `LLM_PROVIDER`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmBackend {
+    Gemini,
+    /// Any OpenAI-compatible chat-completions API (`OPENAI_BASE_URL` to override).
+    OpenAi,
+    /// Local Ollama server (`OLLAMA_API_BASE_URL` to override).
+    Local,
+    /// Scripted offline provider replaying `llm.mock_fixture`.
+    Mock,
+}
+
+impl LlmBackend {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "gemini" => Some(Self::Gemini),
+            "openai" => Some(Self::OpenAi),
+            "local" | "ollama" => Some(Self::Local),
+            "mock" => Some(Self::Mock),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl ConfigError {
+    pub fn single(message: impl Into<String>) -> Self {
+        Self(vec![message.into()])
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Command-line flags; each overrides the matching file/env setting.
+#[derive(Debug, Default, Parser)]
+#[command(name = "backend-rig", about = "Browser AI backend")]
+pub struct Cli {
+    /// TOML config file (default: config.toml when present)
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, e.g. 127.0.0.1:3000
+    #[arg(long)]
+    pub bind: Option<String>,
+    /// Log output: text or json
+    #[arg(long)]
+    pub log_format: Option<String>,
+    /// LLM provider: gemini, openai, local or mock
+    #[arg(long)]
+    pub provider: Option<String>,
+    /// Model for the chat path
+    #[arg(long)]
+    pub chat_model: Option<String>,
+    /// Model for the tool-enabled agent path
+    #[arg(long)]
+    pub agent_model: Option<String>,
+    #[arg(long)]
+    pub temperature: Option<f64>,
+    #[arg(long)]
+    pub max_tokens: Option<u64>,
+    /// Maximum tool turns per agent run
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+    /// Seconds to wait for a browser action
+    #[arg(long)]
+    pub tool_timeout_secs: Option<u64>,
+}
+
+impl Cli {
+    fn apply(&self, file: &mut FileConfig) {
+        set(&mut file.server.bind, self.bind.clone());
+        set(&mut file.server.log_format, self.log_format.clone());
+        set(&mut file.llm.provider, self.provider.clone());
+        set(&mut file.llm.chat_model, self.chat_model.clone());
+        set(&mut file.llm.agent_model, self.agent_model.clone());
+        set(&mut file.llm.temperature, self.temperature);
+        set(&mut file.llm.max_tokens, self.max_tokens);
+        set(&mut file.agent.max_depth, self.max_depth);
+        set(&mut file.agent.tool_timeout_secs, self.tool_timeout_secs);
+    }
+}
+
+// --- TOML file layout; every field is optional ---
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub server: ServerSection,
+    pub llm: LlmSection,
+    pub agent: AgentSection,
+    pub sessions: SessionsSection,
+    pub approval: ApprovalSection,
+    pub navigation: NavigationSection,
+    pub auth: AuthSection,
+    pub locales: LocalesSection,
+    pub instructions: InstructionsSection,
+    pub tasks: TasksSection,
+    pub traces: TracesSection,
+    pub macros: MacrosSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub bind: Option<String>,
+    /// Overrides the port of `bind` (`PORT`, set by most PaaS hosts).
+    pub port: Option<u16>,
+    pub log_format: Option<String>,
+    pub cors_allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmSection {
+    pub provider: Option<String>,
+    pub chat_model: Option<String>,
+    pub agent_model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+    /// Extra models clients may pick per request.
+    pub allowed_models: Option<Vec<String>>,
+    pub mock_fixture: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentSection {
+    pub max_depth: Option<usize>,
+    pub tool_timeout_secs: Option<u64>,
+    pub context_tokens: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TasksSection {
+    /// Background tasks running at once across all sessions.
+    pub max_concurrent: Option<usize>,
+    /// Background tasks running at once on one session.
+    pub max_per_session: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracesSection {
+    /// Where each run's `<run_id>.jsonl` trace is written.
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MacrosSection {
+    /// Where each user's recorded macros are saved.
+    pub store_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsSection {
+    pub conversations_dir: Option<PathBuf>,
+    pub resume_grace_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApprovalSection {
+    pub mode: Option<String>,
+    pub click_keywords: Option<Vec<String>>,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NavigationSection {
+    pub policy_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    /// `principal:key` entries.
+    pub api_keys: Option<Vec<String>>,
+    pub token_secret: Option<String>,
+    pub token_ttl_secs: Option<u64>,
+    /// Principals allowed to use the admin API.
+    pub admins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalesSection {
+    /// Directory of `<locale>/` template and message overrides.
+    pub dir: Option<PathBuf>,
+    /// Locale used when neither the request nor `Accept-Language` match.
+    pub default: Option<String>,
+    /// How often `dir` is checked for changes; 0 disables reloading.
+    pub reload_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstructionsSection {
+    /// JSON [`InstructionSet`] applied to every run.
+    pub workspace_file: Option<PathBuf>,
+    /// Where `PUT /instructions` saves each user's instructions.
+    pub store_file: Option<PathBuf>,
+}
+
+fn set<T>(target: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *target = value;
+    }
+}
+
+fn comma_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Environment variable lookup, injectable for tests.
+type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+struct EnvLayer<'a> {
+    lookup: Lookup<'a>,
+    errors: Vec<String>,
+}
+
+impl EnvLayer<'_> {
+    fn string(&self, name: &str) -> Option<String> {
+        (self.lookup)(name).filter(|v| !v.trim().is_empty())
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let value = self.string(name)?;
+        match value.trim().parse() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.errors
+                    .push(format!("{}: invalid value {:?} ({})", name, value, e));
+                None
+            }
+        }
+    }
+}
+
+impl FileConfig {
+    pub fn parse(raw: &str) -> Result<Self, ConfigError> {
+        toml::from_str(raw).map_err(|e| ConfigError::single(e.to_string()))
+    }
+
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::single(format!("cannot read {}: {}", path.display(), e)))?;
+        Self::parse(&raw).map_err(|e| {
+            ConfigError(
+                e.0.into_iter()
+                    .map(|m| format!("{}: {}", path.display(), m))
+                    .collect(),
+            )
+        })
+    }
+
+    /// Overrides file values with environment variables. Returns the
+    /// variables that failed to parse.
+    fn apply_env(&mut self, lookup: Lookup) -> Vec<String> {
+        let mut env = EnvLayer {
+            lookup,
+            errors: Vec::new(),
+        };
+
+        set(&mut self.server.bind, env.string("BIND_ADDRESS"));
+        set(&mut self.server.port, env.parse("PORT"));
+        set(&mut self.server.log_format, env.string("LOG_FORMAT"));
+        set(
+            &mut self.server.cors_allowed_origins,
+            env.string("CORS_ALLOWED_ORIGINS").map(|v| comma_list(&v)),
+        );
+
+        set(&mut self.llm.provider, env.string("LLM_PROVIDER"));
+        // LLM_MODEL sets both models; the specific variables win
+        if let Some(model) = env.string("LLM_MODEL") {
+            self.llm.chat_model = Some(model.clone());
+            self.llm.agent_model = Some(model);
+        }
+        set(&mut self.llm.chat_model, env.string("LLM_CHAT_MODEL"));
+        set(&mut self.llm.agent_model, env.string("LLM_AGENT_MODEL"));
+        set(&mut self.llm.temperature, env.parse("LLM_TEMPERATURE"));
+        set(&mut self.llm.max_tokens, env.parse("LLM_MAX_TOKENS"));
+        set(
+            &mut self.llm.allowed_models,
+            env.string("LLM_ALLOWED_MODELS").map(|v| comma_list(&v)),
+        );
+        set(
+            &mut self.llm.mock_fixture,
+            env.string("MOCK_LLM_FIXTURE").map(PathBuf::from),
+        );
+
+        set(&mut self.agent.max_depth, env.parse("AGENT_MAX_DEPTH"));
+        set(
+            &mut self.agent.tool_timeout_secs,
+            env.parse("TOOL_TIMEOUT_SECS"),
+        );
+        set(
+            &mut self.agent.context_tokens,
+            env.parse("AGENT_CONTEXT_TOKENS"),
+        );
+        set(
+            &mut self.tasks.max_concurrent,
+            env.parse("TASKS_MAX_CONCURRENT"),
+        );
+        set(
+            &mut self.tasks.max_per_session,
+            env.parse("TASKS_MAX_PER_SESSION"),
+        );
+
+        set(
+            &mut self.sessions.conversations_dir,
+            env.string("CONVERSATIONS_DIR").map(PathBuf::from),
+        );
+        set(
+            &mut self.sessions.resume_grace_secs,
+            env.parse("WS_RESUME_GRACE_SECS"),
+        );
+
+        set(&mut self.approval.mode, env.string("APPROVAL_MODE"));
+        set(
+            &mut self.approval.click_keywords,
+            env.string("APPROVAL_CLICK_KEYWORDS")
+                .map(|v| comma_list(&v)),
+        );
+        set(
+            &mut self.approval.timeout_secs,
+            env.parse("APPROVAL_TIMEOUT_SECS"),
+        );
+
+        set(
+            &mut self.navigation.policy_file,
+            env.string("NAVIGATION_POLICY_FILE").map(PathBuf::from),
+        );
+
+        set(
+            &mut self.auth.api_keys,
+            env.string("API_KEYS").map(|v| comma_list(&v)),
+        );
+        set(&mut self.auth.token_secret, env.string("AUTH_TOKEN_SECRET"));
+        set(
+            &mut self.auth.token_ttl_secs,
+            env.parse("AUTH_TOKEN_TTL_SECS"),
+        );
+        set(
+            &mut self.auth.admins,
+            env.string("ADMIN_PRINCIPALS").map(|v| comma_list(&v)),
+        );
+
+        set(
+            &mut self.locales.dir,
+            env.string("LOCALES_DIR").map(PathBuf::from),
+        );
+        set(&mut self.locales.default, env.string("DEFAULT_LOCALE"));
+        set(
+            &mut self.locales.reload_secs,
+            env.parse("LOCALES_RELOAD_SECS"),
+        );
+
+        set(
+            &mut self.instructions.workspace_file,
+            env.string("WORKSPACE_INSTRUCTIONS_FILE").map(PathBuf::from),
+        );
+        set(
+            &mut self.instructions.store_file,
+            env.string("INSTRUCTIONS_STORE_FILE").map(PathBuf::from),
+        );
+
+        set(
+            &mut self.traces.dir,
+            env.string("TRACES_DIR").map(PathBuf::from),
+        );
+
+        set(
+            &mut self.macros.store_file,
+            env.string("MACROS_STORE_FILE").map(PathBuf::from),
+        );
+
+        env.errors
+    }
+
+    /// Checks every value and builds the final configuration.
+    fn validate(self, lookup: Lookup, mut errors: Vec<String>) -> Result<AppConfig, ConfigError> {
+        let llm_provider = match self.llm.provider.as_deref() {
+            Some(value) => LlmBackend::parse(value).unwrap_or_else(|| {
+                errors.push(format!(
+                    "llm.provider: unsupported provider {:?} (gemini, openai, local, mock)",
+                    value
+                ));
+                LlmBackend::Gemini
+            }),
+            None => LlmBackend::Gemini,
+        };
+        // rig clients read their API key from the environment
+        let required_key = match llm_provider {
+            LlmBackend::Gemini => Some("GEMINI_API_KEY"),
+            LlmBackend::OpenAi => Some("OPENAI_API_KEY"),
+            LlmBackend::Local | LlmBackend::Mock => None,
+        };
+        if let Some(key) = required_key
+            && lookup(key).is_none()
+        {
+            errors.push(format!(
+                "{} environment variable is required for the {:?} provider",
+                key, llm_provider
+            ));
+        }
+        if llm_provider == LlmBackend::Mock && self.llm.mock_fixture.is_none() {
+            errors.push(
+                "llm.mock_fixture (MOCK_LLM_FIXTURE) is required for the mock provider".into(),
+            );
+        }
+        if let Some(temperature) = self.llm.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            errors.push(format!(
+                "llm.temperature: {} is outside 0.0..=2.0",
+                temperature
+            ));
+        }
+        if self.llm.max_tokens == Some(0) {
+            errors.push("llm.max_tokens: must be greater than 0".into());
+        }
+
+        let mut bind = match self
+            .server
+            .bind
+            .as_deref()
+            .unwrap_or(DEFAULT_BIND)
+            .parse::<SocketAddr>()
+        {
+            Ok(addr) => addr,
+            Err(e) => {
+                errors.push(format!("server.bind: {}", e));
+                DEFAULT_BIND.parse().expect("default bind address parses")
+            }
+        };
+        if let Some(port) = self.server.port {
+            bind.set_port(port);
+        }
+        let log_format = match self.server.log_format.as_deref() {
+            Some(value) => LogFormat::parse(value).unwrap_or_else(|| {
+                errors.push(format!(
+                    "server.log_format: unsupported format {:?} (text, json)",
+                    value
+                ));
+                LogFormat::Text
+            }),
+            None => LogFormat::Text,
+        };
+
+        let defaults = AgentLimits::default();
+        let max_depth = self.agent.max_depth.unwrap_or(defaults.max_depth);
+        if !(1..=MAX_AGENT_DEPTH).contains(&max_depth) {
+            errors.push(format!(
+                "agent.max_depth: {} is outside 1..={}",
+                max_depth, MAX_AGENT_DEPTH
+            ));
+        }
+        let tool_timeout_secs = self
+            .agent
+            .tool_timeout_secs
+            .unwrap_or(defaults.tool_timeout.as_secs());
+        if !(1..=MAX_TOOL_TIMEOUT_SECS).contains(&tool_timeout_secs) {
+            errors.push(format!(
+                "agent.tool_timeout_secs: {} is outside 1..={}",
+                tool_timeout_secs, MAX_TOOL_TIMEOUT_SECS
+            ));
+        }
+        let context_tokens = self.agent.context_tokens.unwrap_or(defaults.context_tokens);
+        if context_tokens > MAX_CONTEXT_TOKENS {
+            errors.push(format!(
+                "agent.context_tokens: {} is above {}",
+                context_tokens, MAX_CONTEXT_TOKENS
+            ));
+        }
+
+        let task_defaults = TaskLimits::default();
+        let task_limits = TaskLimits {
+            max_concurrent: self
+                .tasks
+                .max_concurrent
+                .unwrap_or(task_defaults.max_concurrent),
+            max_per_session: self
+                .tasks
+                .max_per_session
+                .unwrap_or(task_defaults.max_per_session),
+        };
+        for (key, value) in [
+            ("tasks.max_concurrent", task_limits.max_concurrent),
+            ("tasks.max_per_session", task_limits.max_per_session),
+        ] {
+            if !(1..=MAX_CONCURRENT_TASKS).contains(&value) {
+                errors.push(format!(
+                    "{}: {} is outside 1..={}",
+                    key, value, MAX_CONCURRENT_TASKS
+                ));
+            }
+        }
+
+        let mut approval_policy = ApprovalPolicy::default();
+        if let Some(value) = self.approval.mode.as_deref() {
+            match ApprovalMode::parse(value) {
+                Some(mode) => approval_policy.mode = mode,
+                None => errors.push(format!(
+                    "approval.mode: unsupported mode {:?} (off, policy, always)",
+                    value
+                )),
+            }
+        }
+        if let Some(keywords) = self.approval.click_keywords {
+            approval_policy.click_keywords = keywords;
+        }
+        if let Some(secs) = self.approval.timeout_secs {
+            if !(1..=MAX_APPROVAL_TIMEOUT_SECS).contains(&secs) {
+                errors.push(format!(
+                    "approval.timeout_secs: {} is outside 1..={}",
+                    secs, MAX_APPROVAL_TIMEOUT_SECS
+                ));
+            }
+            approval_policy.timeout = Duration::from_secs(secs);
+        }
+
+        let navigation_policy = match &self.navigation.policy_file {
+            Some(path) => load_navigation_policy(path).unwrap_or_else(|e| {
+                errors.push(e);
+                NavigationPolicy::default()
+            }),
+            None => NavigationPolicy::default(),
+        };
+
+        let workspace_instructions = match &self.instructions.workspace_file {
+            Some(path) => load_workspace_instructions(path).unwrap_or_else(|e| {
+                errors.push(e);
+                InstructionSet::default()
+            }),
+            None => InstructionSet::default(),
+        };
+
+        let mut auth = AuthConfig::default();
+        if let Some(keys) = &self.auth.api_keys {
+            auth.api_keys = AuthConfig::parse_api_keys(&keys.join(","));
+        }
+        auth.token_secret = self.auth.token_secret;
+        if let Some(secs) = self.auth.token_ttl_secs {
+            auth.token_ttl = Duration::from_secs(secs);
+        }
+        auth.admins = self.auth.admins.unwrap_or_default().into_iter().collect();
+
+        let locales = LocaleStore::load(self.locales.dir.clone(), self.locales.default.clone())
+            .unwrap_or_else(|e| {
+                errors.push(format!("locales: {}", e));
+                LocaleStore::default()
+            });
+
+        if !errors.is_empty() {
+            return Err(ConfigError(errors));
+        }
+
+        Ok(AppConfig {
+            bind,
+            log_format,
+            llm_provider,
+            models: ModelSettings {
+                chat_model: self.llm.chat_model,
+                agent_model: self.llm.agent_model,
+                temperature: self.llm.temperature,
+                max_tokens: self.llm.max_tokens,
+                allowed_models: self.llm.allowed_models.unwrap_or_default(),
+            },
+            mock_fixture: self.llm.mock_fixture,
+            agent_limits: AgentLimits {
+                max_depth,
+                tool_timeout: Duration::from_secs(tool_timeout_secs),
+                context_tokens,
+            },
+            task_limits,
+            conversations_dir: self
+                .sessions
+                .conversations_dir
+                .unwrap_or_else(|| "data/conversations".into()),
+            resume_grace: Duration::from_secs(self.sessions.resume_grace_secs.unwrap_or(60)),
+            approval_policy,
+            navigation_policy,
+            auth,
+            cors_origins: self.server.cors_allowed_origins.unwrap_or_default(),
+            locales,
+            locales_reload: Duration::from_secs(self.locales.reload_secs.unwrap_or(2)),
+            workspace_instructions,
+            instructions_store: self
+                .instructions
+                .store_file
+                .unwrap_or_else(|| "data/instructions.json".into()),
+            traces_dir: self.traces.dir.unwrap_or_else(|| "data/traces".into()),
+            macros_store: self
+                .macros
+                .store_file
+                .unwrap_or_else(|| "data/macros.json".into()),
+        })
+    }
+}
+
+fn load_navigation_policy(path: &Path) -> Result<NavigationPolicy, String> {
+    let raw = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "navigation.policy_file: cannot read {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    let config: NavigationPolicyConfig = serde_json::from_str(&raw)
+        .map_err(|e| format!("navigation.policy_file {}: {}", path.display(), e))?;
+    NavigationPolicy::from_config(config)
+        .map_err(|e| format!("navigation.policy_file {}: {}", path.display(), e))
+}
+
+fn load_workspace_instructions(path: &Path) -> Result<InstructionSet, String> {
+    let raw = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "instructions.workspace_file: cannot read {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    let set: InstructionSet = serde_json::from_str(&raw)
+        .map_err(|e| format!("instructions.workspace_file {}: {}", path.display(), e))?;
+    set.validate()
+        .map_err(|e| format!("instructions.workspace_file {}: {}", path.display(), e))?;
+    Ok(set)
+}
 
 pub struct AppConfig {
-    pub port: u16,
+    pub bind: SocketAddr,
+    pub log_format: LogFormat,
+    pub llm_provider: LlmBackend,
+    /// Model ids and generation parameters.
+    pub models: ModelSettings,
+    /// Fixture replayed by the mock provider.
+    pub mock_fixture: Option<PathBuf>,
+    /// Tool depth and browser action timeout.
+    pub agent_limits: AgentLimits,
+    /// Background task concurrency.
+    pub task_limits: TaskLimits,
+    /// Directory for persisted conversations.
+    pub conversations_dir: PathBuf,
+    /// How long a disconnected WebSocket session can be resumed.
+    pub resume_grace: Duration,
+    /// Which browser actions need human approval.
+    pub approval_policy: ApprovalPolicy,
+    /// Deployment-wide navigation rules.
+    pub navigation_policy: NavigationPolicy,
+    /// API keys and token secret.
+    pub auth: AuthConfig,
+    /// Origins allowed by CORS.
+    pub cors_origins: Vec<String>,
+    /// Localized preamble templates and error messages.
+    pub locales: LocaleStore,
+    /// Poll interval for template changes in `locales.dir`.
+    pub locales_reload: Duration,
+    /// Instructions applied to every run.
+    pub workspace_instructions: InstructionSet,
+    /// File backing each user's instructions.
+    pub instructions_store: PathBuf,
+    /// Directory for run traces.
+    pub traces_dir: PathBuf,
+    /// File backing each user's macros.
+    pub macros_store: PathBuf,
 }
 
 impl AppConfig {
-    pub fn from_env() -> Self {
+    /// Loads `.env`, the config file, the environment and `cli`.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
         dotenvy::dotenv().ok();
+        Self::from_layers(cli, &|name| env::var(name).ok())
+    }
 
-        // Validate that GEMINI_API_KEY is set (required by rig gemini client)
-        if env::var("GEMINI_API_KEY").is_err() {
-            panic!("GEMINI_API_KEY environment variable is required");
-        }
+    fn from_layers(cli: &Cli, lookup: Lookup) -> Result<Self, ConfigError> {
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| lookup("CONFIG_FILE").map(PathBuf::from));
+        let mut file = match path {
+            Some(path) => FileConfig::read(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                FileConfig::read(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => FileConfig::default(),
+        };
+        let errors = file.apply_env(lookup);
+        cli.apply(&mut file);
+        file.validate(lookup, errors)
+    }
+}
 
-        Self {
-            port: env::var("PORT")
-                .unwrap_or_else(|_| "3000".to_string())
-                .parse()
-                .unwrap(),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn build(toml: &str, env: &[(&str, &str)], cli: Cli) -> Result<AppConfig, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let lookup = move |name: &str| env.get(name).cloned();
+        let mut file = FileConfig::parse(toml)?;
+        let errors = file.apply_env(&lookup);
+        cli.apply(&mut file);
+        file.validate(&lookup, errors)
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let toml = r#"
+            [server]
+            bind = "127.0.0.1:8080"
+            log_format = "json"
+
+            [llm]
+            provider = "local"
+            chat_model = "file-chat"
+            agent_model = "file-agent"
+            temperature = 0.2
+
+            [agent]
+            max_depth = 10
+        "#;
+        let config = build(
+            toml,
+            &[("LLM_AGENT_MODEL", "env-agent"), ("AGENT_MAX_DEPTH", "12")],
+            Cli {
+                max_depth: Some(15),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(config.bind, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.llm_provider, LlmBackend::Local);
+        assert_eq!(config.models.chat_model.as_deref(), Some("file-chat"));
+        assert_eq!(config.models.agent_model.as_deref(), Some("env-agent"));
+        assert_eq!(config.models.temperature, Some(0.2));
+        assert_eq!(config.agent_limits.max_depth, 15);
+        assert_eq!(config.agent_limits.tool_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_port_overrides_bind() {
+        let config = build(
+            "",
+            &[("PORT", "8000"), ("GEMINI_API_KEY", "k")],
+            Cli::default(),
+        )
+        .unwrap();
+        assert_eq!(config.bind, "0.0.0.0:8000".parse().unwrap());
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let toml = r#"
+            [llm]
+            temperature = 3.5
+
+            [agent]
+            max_depth = 0
+            context_tokens = 100000
+
+            [tasks]
+            max_per_session = 0
+
+            [approval]
+            timeout_secs = 0
+        "#;
+        let error = build(
+            toml,
+            &[
+                ("LOG_FORMAT", "xml"),
+                ("TOOL_TIMEOUT_SECS", "soon"),
+                ("DEFAULT_LOCALE", "fr"),
+                (
+                    "WORKSPACE_INSTRUCTIONS_FILE",
+                    "/nonexistent/instructions.json",
+                ),
+            ],
+            Cli::default(),
+        )
+        .err()
+        .unwrap();
+
+        let message = error.to_string();
+        assert!(message.contains("GEMINI_API_KEY"));
+        assert!(message.contains("llm.temperature"));
+        assert!(message.contains("agent.max_depth"));
+        assert!(message.contains("agent.context_tokens"));
+        assert!(message.contains("tasks.max_per_session"));
+        assert!(message.contains("approval.timeout_secs"));
+        assert!(message.contains("server.log_format"));
+        assert!(message.contains("TOOL_TIMEOUT_SECS"));
+        assert!(message.contains("unknown locale \"fr\""));
+        assert!(message.contains("instructions.workspace_file"));
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(FileConfig::parse("[agent]\nmax_dept = 3").is_err());
     }
 }
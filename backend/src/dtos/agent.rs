@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::llm::GenerationOverrides;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AgentRequest {
     #[serde(alias = "message")]
@@ -12,6 +14,28 @@ pub struct AgentRequest {
     pub interactive_elements: Option<Vec<InteractiveElementDto>>,
     pub page_content: Option<String>,
     pub history: Option<Vec<ChatMessageDto>>,
+    /// Server-side conversation to continue. When `history` is omitted the
+    /// stored turns are used instead.
+    pub conversation_id: Option<String>,
+    /// Model id; must be on the server's allowlist.
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+    /// Tool turns for this run, up to the server's `max_depth`.
+    pub max_depth: Option<usize>,
+    /// Language of prompts and error messages (`en`, `id`, `ja`); defaults
+    /// to `Accept-Language`.
+    pub locale: Option<String>,
+}
+
+impl AgentRequest {
+    pub fn generation_overrides(&self) -> GenerationOverrides {
+        GenerationOverrides {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
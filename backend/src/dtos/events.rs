@@ -0,0 +1,155 @@
+//! Typed SSE events emitted by `/agent/run`.
+//!
+//! Every event carries `__type` in its JSON data and uses the same value as
+//! the SSE `event:` name, so clients can dispatch on either.
+
+use axum::response::sse::Event;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::approval::ApprovalRequest;
+use crate::llm::TokenUsage;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "__type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// First event of a tool-enabled run; the id cancels it.
+    RunStarted {
+        run_id: String,
+    },
+    Conversation {
+        conversation_id: String,
+    },
+    Tool(ToolEvent),
+    Usage(TokenUsage),
+    /// A tool call is paused until the user decides.
+    ApprovalRequired(ApprovalRequest),
+    ApprovalResolved {
+        approval_id: String,
+        decision: &'static str,
+    },
+    /// The run was stopped before the model finished; `[DONE]` follows.
+    Cancelled {
+        run_id: String,
+        reason: String,
+    },
+}
+
+impl StreamEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamEvent::RunStarted { .. } => "run_started",
+            StreamEvent::Conversation { .. } => "conversation",
+            StreamEvent::Tool(_) => "tool",
+            StreamEvent::Usage(_) => "usage",
+            StreamEvent::ApprovalRequired(_) => "approval_required",
+            StreamEvent::ApprovalResolved { .. } => "approval_resolved",
+            StreamEvent::Cancelled { .. } => "cancelled",
+        }
+    }
+
+    pub fn to_sse_event(&self) -> Event {
+        Event::default()
+            .event(self.name())
+            .data(serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolStatus {
+    Calling,
+    Completed,
+    Failed,
+}
+
+/// One step of a tool call. `calling` and the matching `completed`/`failed`
+/// event share the same `id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolEvent {
+    pub id: String,
+    pub name: String,
+    pub status: ToolStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl ToolEvent {
+    pub fn calling(id: String, name: String, arguments: Value) -> Self {
+        Self {
+            id,
+            name,
+            status: ToolStatus::Calling,
+            arguments: Some(arguments),
+            result: None,
+            error: None,
+            duration_ms: None,
+        }
+    }
+
+    /// Builds the final event from the text fed back to the model. Tool
+    /// outputs are always JSON objects; anything else is the error message
+    /// the agent framework substituted for a failed call.
+    pub fn finished(id: String, name: String, content: String, duration: Duration) -> Self {
+        let (status, result, error) = match serde_json::from_str::<Value>(&content) {
+            Ok(result @ Value::Object(_)) => (ToolStatus::Completed, Some(result), None),
+            _ => (ToolStatus::Failed, None, Some(content)),
+        };
+        Self {
+            id,
+            name,
+            status,
+            arguments: None,
+            result,
+            error,
+            duration_ms: Some(duration.as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_event_serialization() {
+        let event = StreamEvent::Tool(ToolEvent::calling(
+            "call_1".into(),
+            "click_element".into(),
+            json!({"ref": 1}),
+        ));
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"__type":"tool","id":"call_1","name":"click_element","status":"calling","arguments":{"ref":1}}"#
+        );
+    }
+
+    #[test]
+    fn test_finished_classifies_errors() {
+        let ok = ToolEvent::finished(
+            "1".into(),
+            "click_element".into(),
+            r#"{"clicked":1}"#.into(),
+            Duration::from_millis(12),
+        );
+        assert_eq!(ok.status, ToolStatus::Completed);
+        assert_eq!(ok.duration_ms, Some(12));
+
+        let failed = ToolEvent::finished(
+            "2".into(),
+            "click_element".into(),
+            "ToolCallError: Browser action failed: Element with ref 9 not found".into(),
+            Duration::ZERO,
+        );
+        assert_eq!(failed.status, ToolStatus::Failed);
+        assert!(failed.error.unwrap().contains("ref 9"));
+    }
+}
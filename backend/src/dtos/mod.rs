@@ -1,3 +1,5 @@
 pub mod agent;
+pub mod events;
+pub mod openai;
 
 pub use agent::AgentRequest;
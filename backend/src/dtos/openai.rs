@@ -0,0 +1,218 @@
+//! Subset of the OpenAI chat-completions schema accepted by `/v1/chat/completions`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::GenerationOverrides;
+
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: Option<String>,
+    pub messages: Vec<ChatCompletionMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    pub stream_options: Option<StreamOptions>,
+    pub temperature: Option<f64>,
+    #[serde(alias = "max_completion_tokens")]
+    pub max_tokens: Option<u64>,
+    /// Non-standard: browser session to run tools against. The
+    /// `X-Session-Id` header takes precedence.
+    pub session_id: Option<String>,
+}
+
+impl ChatCompletionRequest {
+    pub fn generation_overrides(&self) -> GenerationOverrides {
+        GenerationOverrides {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl MessageContent {
+    /// Concatenated text parts.
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// First image (usually a `data:` URL), if any.
+    pub fn image(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(_) => None,
+            MessageContent::Parts(parts) => parts.iter().find_map(|part| match part {
+                ContentPart::ImageUrl { image_url } => Some(image_url.url.as_str()),
+                _ => None,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoice>,
+    pub usage: Usage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionChoice {
+    pub index: u32,
+    pub message: AssistantMessage,
+    pub finish_reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssistantMessage {
+    pub role: &'static str,
+    pub content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkChoice {
+    pub index: u32,
+    pub delta: Delta,
+    pub finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_completion_request_deserialize() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "stream": true,
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,abc"}}
+                ]}
+            ]
+        }"#;
+        let req: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert!(req.stream);
+        assert_eq!(req.messages.len(), 2);
+
+        let content = req.messages[1].content.as_ref().unwrap();
+        assert_eq!(content.text(), "What is this?");
+        assert_eq!(content.image(), Some("data:image/png;base64,abc"));
+    }
+
+    #[test]
+    fn test_generation_overrides() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "temperature": 0.2,
+            "max_completion_tokens": 256,
+            "messages": [{"role": "user", "content": "Hi"}]
+        }"#;
+        let req: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        let overrides = req.generation_overrides();
+        assert_eq!(overrides.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(overrides.temperature, Some(0.2));
+        assert_eq!(overrides.max_tokens, Some(256));
+    }
+
+    #[test]
+    fn test_chunk_serialization_skips_empty_fields() {
+        let chunk = ChatCompletionChunk {
+            id: "chatcmpl-1".to_string(),
+            object: "chat.completion.chunk",
+            created: 0,
+            model: "gemini".to_string(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta: Delta {
+                    role: None,
+                    content: Some("Hi".to_string()),
+                },
+                finish_reason: None,
+            }],
+            usage: None,
+        };
+        let json = serde_json::to_string(&chunk).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","created":0,"model":"gemini","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#
+        );
+    }
+}
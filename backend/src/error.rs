@@ -1 +1,248 @@
-// Error types module - reserved for future error handling implementations
+//! Application-wide error type.
+//!
+//! Every failure that can reach a client goes through [`AppError`], which
+//! maps to an HTTP status for JSON endpoints and to a structured SSE `error`
+//! event for streams. Clients should switch on `code`, never on `message`.
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response, sse::Event},
+};
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppError {
+    /// The LLM provider failed (network, bad response, ...).
+    Provider(String),
+    /// The LLM provider rejected the request due to quota/rate limits.
+    RateLimited(String),
+    /// The model returned nothing actionable.
+    EmptyResponse,
+    /// The agent hit the maximum number of tool turns.
+    MaxDepth(usize),
+    /// The extension didn't answer an action in time.
+    ToolTimeout {
+        seconds: u64,
+    },
+    /// The extension reported a failed action.
+    ToolFailed(String),
+    /// No WebSocket connection exists for the session.
+    NoConnection,
+    /// The action's response channel was dropped.
+    ChannelClosed,
+    /// Navigation refused before reaching the browser.
+    NavigationBlocked(String),
+    /// The user rejected the action in the approval step.
+    ActionRejected(String),
+    InvalidRequest(String),
+    /// Missing or invalid credentials.
+    Unauthorized(String),
+    /// The caller may not act on this resource (e.g. another user's session).
+    Forbidden(String),
+    NotFound(String),
+    Internal(String),
+}
+
+/// Wire shape of an error, both in JSON bodies and SSE `error` events.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorPayload {
+    #[serde(rename = "__type")]
+    pub kind: &'static str,
+    pub code: &'static str,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl AppError {
+    /// Classifies a provider error message. rig only exposes most provider
+    /// failures (including HTTP 429) as text, so this is the single place
+    /// where error strings are inspected.
+    pub fn from_provider(message: impl Into<String>) -> Self {
+        let message = message.into();
+        let lower = message.to_lowercase();
+        if lower.contains("maxdepth") || lower.contains("max depth") {
+            let depth = message
+                .rsplit(|c: char| !c.is_ascii_digit())
+                .find(|s| !s.is_empty())
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default();
+            AppError::MaxDepth(depth)
+        } else if lower.contains("429")
+            || lower.contains("rate limit")
+            || lower.contains("resource_exhausted")
+            || lower.contains("quota")
+        {
+            AppError::RateLimited(message)
+        } else if lower.contains("empty") || lower.contains("no message") {
+            AppError::EmptyResponse
+        } else {
+            AppError::Provider(message)
+        }
+    }
+
+    /// Stable machine-readable code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Provider(_) => "provider_error",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::EmptyResponse => "empty_response",
+            AppError::MaxDepth(_) => "max_depth",
+            AppError::ToolTimeout { .. } => "tool_timeout",
+            AppError::ToolFailed(_) => "tool_failed",
+            AppError::NoConnection => "no_connection",
+            AppError::ChannelClosed => "channel_closed",
+            AppError::NavigationBlocked(_) => "navigation_blocked",
+            AppError::ActionRejected(_) => "action_rejected",
+            AppError::InvalidRequest(_) => "invalid_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Provider(_) | AppError::EmptyResponse => StatusCode::BAD_GATEWAY,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ToolTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            AppError::NoConnection => StatusCode::CONFLICT,
+            AppError::NavigationBlocked(_)
+            | AppError::ActionRejected(_)
+            | AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::MaxDepth(_)
+            | AppError::ToolFailed(_)
+            | AppError::ChannelClosed
+            | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether retrying the same request may succeed.
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            AppError::Provider(_)
+                | AppError::RateLimited(_)
+                | AppError::ToolTimeout { .. }
+                | AppError::NoConnection
+                | AppError::ChannelClosed
+        )
+    }
+
+    /// Variable part of the message, substituted for `{detail}` in
+    /// localized error strings.
+    pub fn detail(&self) -> String {
+        match self {
+            AppError::Provider(msg)
+            | AppError::RateLimited(msg)
+            | AppError::ToolFailed(msg)
+            | AppError::NavigationBlocked(msg)
+            | AppError::ActionRejected(msg)
+            | AppError::InvalidRequest(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Forbidden(msg)
+            | AppError::NotFound(msg)
+            | AppError::Internal(msg) => msg.clone(),
+            AppError::MaxDepth(depth) => depth.to_string(),
+            AppError::ToolTimeout { seconds } => seconds.to_string(),
+            AppError::EmptyResponse | AppError::NoConnection | AppError::ChannelClosed => {
+                String::new()
+            }
+        }
+    }
+
+    pub fn payload(&self) -> ErrorPayload {
+        ErrorPayload {
+            kind: "error",
+            code: self.code(),
+            message: self.to_string(),
+            retryable: self.retryable(),
+        }
+    }
+
+    /// SSE `error` event carrying the structured payload.
+    pub fn to_sse_event(&self) -> Event {
+        Event::default()
+            .event("error")
+            .data(serde_json::to_string(&self.payload()).unwrap_or_default())
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Provider(msg) => write!(f, "LLM provider error: {}", msg),
+            AppError::RateLimited(msg) => write!(f, "LLM provider rate limit reached: {}", msg),
+            AppError::EmptyResponse => write!(f, "The model returned an empty response"),
+            AppError::MaxDepth(depth) => {
+                write!(f, "Maximum tool call depth reached ({})", depth)
+            }
+            AppError::ToolTimeout { seconds } => {
+                write!(f, "Tool execution timed out after {} seconds", seconds)
+            }
+            AppError::ToolFailed(msg) => write!(f, "Browser action failed: {}", msg),
+            AppError::NoConnection => {
+                write!(f, "No active WebSocket connection for this session")
+            }
+            AppError::ChannelClosed => write!(f, "Response channel closed unexpectedly"),
+            AppError::NavigationBlocked(msg) => write!(f, "Navigation blocked: {}", msg),
+            AppError::ActionRejected(msg) => write!(f, "Action rejected: {}", msg),
+            AppError::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+            AppError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            AppError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            AppError::NotFound(msg) => write!(f, "{} not found", msg),
+            AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if self.status().is_server_error() {
+            tracing::error!("{}", self);
+        }
+        (self.status(), Json(json!({ "error": self.payload() }))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_provider_classification() {
+        assert_eq!(
+            AppError::from_provider("MaxDepthError: (reached limit: 20)"),
+            AppError::MaxDepth(20)
+        );
+        assert!(matches!(
+            AppError::from_provider("ProviderError: 429 Too Many Requests"),
+            AppError::RateLimited(_)
+        ));
+        assert_eq!(
+            AppError::from_provider("Response contained no message"),
+            AppError::EmptyResponse
+        );
+        assert!(matches!(
+            AppError::from_provider("connection reset"),
+            AppError::Provider(_)
+        ));
+    }
+
+    #[test]
+    fn test_error_payload_serialization() {
+        let json = serde_json::to_string(&AppError::NoConnection.payload()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"__type":"error","code":"no_connection","message":"No active WebSocket connection for this session","retryable":true}"#
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Operator endpoints over every principal's sessions. Each handler checks
+//! [`AuthConfig::require_admin`](crate::auth::AuthConfig::require_admin).
+
+use axum::{
+    extract::{Extension, Json, Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::session::{PageSnapshot, SessionSummary};
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct AdminSessionDetails {
+    #[serde(flatten)]
+    pub summary: SessionSummary,
+    pub page: PageSnapshot,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReasonRequest {
+    pub reason: String,
+}
+
+impl ReasonRequest {
+    fn reason(&self) -> Result<&str, AppError> {
+        let reason = self.reason.trim();
+        if reason.is_empty() {
+            return Err(AppError::InvalidRequest(
+                "reason must not be empty".to_string(),
+            ));
+        }
+        Ok(reason)
+    }
+}
+
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<SessionSummary>>, AppError> {
+    state.auth.require_admin(&principal)?;
+    Ok(Json(state.session_summaries().await))
+}
+
+pub async fn get_session(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<AdminSessionDetails>, AppError> {
+    state.auth.require_admin(&principal)?;
+    let summary = state
+        .session_summary(&id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Session {}", id)))?;
+    Ok(Json(AdminSessionDetails {
+        summary,
+        page: state.page_snapshot(&id).await,
+    }))
+}
+
+pub async fn disconnect_session(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    Json(request): Json<ReasonRequest>,
+) -> Result<StatusCode, AppError> {
+    state.auth.require_admin(&principal)?;
+    let reason = request.reason()?;
+    if !state.disconnect_session(&id, reason).await {
+        return Err(AppError::NotFound(format!("Session {}", id)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fails a stuck action; the waiting tool call returns the reason.
+pub async fn cancel_action(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path((id, request_id)): Path<(String, String)>,
+    Json(request): Json<ReasonRequest>,
+) -> Result<StatusCode, AppError> {
+    state.auth.require_admin(&principal)?;
+    let reason = request.reason()?;
+    if !state.cancel_action(&id, &request_id, reason).await {
+        return Err(AppError::NotFound(format!(
+            "Pending action {} of session {}",
+            request_id, id
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
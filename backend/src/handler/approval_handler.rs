@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Extension, Json, Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::approval::{ApprovalDecision, ApprovalRequest};
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct ListApprovalsQuery {
+    pub session_id: Option<String>,
+}
+
+fn owns(request: &ApprovalRequest, principal: &Principal) -> bool {
+    request.principal.as_deref() == Some(principal.name())
+}
+
+/// Pending approvals of the caller's sessions.
+pub async fn list_approvals(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ListApprovalsQuery>,
+) -> Json<Vec<ApprovalRequest>> {
+    let approvals = state.list_approvals(query.session_id.as_deref()).await;
+    Json(
+        approvals
+            .into_iter()
+            .filter(|request| owns(request, &principal))
+            .collect(),
+    )
+}
+
+pub async fn resolve_approval(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    Json(decision): Json<ApprovalDecision>,
+) -> Result<StatusCode, AppError> {
+    let request = state
+        .list_approvals(None)
+        .await
+        .into_iter()
+        .find(|r| r.approval_id == id)
+        .ok_or_else(|| AppError::NotFound(format!("Approval {}", id)))?;
+    if !owns(&request, &principal) {
+        return Err(AppError::Forbidden(format!(
+            "approval {} belongs to another principal",
+            id
+        )));
+    }
+    let session_id = request.session_id;
+    state
+        .resolve_approval(&id, Some(&session_id), decision)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,29 @@
+use axum::extract::{Extension, Json, State};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub principal: String,
+    /// Unix seconds.
+    pub expires_at: u64,
+}
+
+/// Exchanges the caller's credentials (usually an API key) for a
+/// short-lived signed token.
+pub async fn issue_token(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<TokenResponse>, AppError> {
+    let (token, expires_at) = state.auth.issue_token(&principal)?;
+    Ok(Json(TokenResponse {
+        token,
+        principal: principal.0,
+        expires_at,
+    }))
+}
@@ -0,0 +1,101 @@
+use axum::{
+    extract::{Extension, Json, Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::models::conversation::{Conversation, ConversationSummary};
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct ListConversationsQuery {
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ForkConversationRequest {
+    /// Number of messages to keep; all when omitted.
+    pub at: Option<usize>,
+}
+
+fn not_found(id: &str) -> AppError {
+    AppError::NotFound(format!("Conversation {}", id))
+}
+
+/// The conversation, if `principal` started it or is an admin.
+async fn authorized_conversation(
+    state: &AppState,
+    id: &str,
+    principal: &Principal,
+) -> Result<Conversation, AppError> {
+    let conversation = state
+        .conversations
+        .get(id)
+        .await
+        .ok_or_else(|| not_found(id))?;
+    if conversation.principal != principal.name() && state.auth.require_admin(principal).is_err() {
+        return Err(AppError::Forbidden(format!(
+            "conversation {} belongs to another principal",
+            id
+        )));
+    }
+    Ok(conversation)
+}
+
+/// The caller's conversations; admins see everyone's.
+pub async fn list_conversations(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ListConversationsQuery>,
+) -> Json<Vec<ConversationSummary>> {
+    let is_admin = state.auth.require_admin(&principal).is_ok();
+    let conversations = state.conversations.list(query.session_id.as_deref()).await;
+    Json(
+        conversations
+            .into_iter()
+            .filter(|c| is_admin || c.principal == principal.name())
+            .collect(),
+    )
+}
+
+pub async fn get_conversation(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<Conversation>, AppError> {
+    Ok(Json(
+        authorized_conversation(&state, &id, &principal).await?,
+    ))
+}
+
+pub async fn fork_conversation(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    request: Option<Json<ForkConversationRequest>>,
+) -> Result<(StatusCode, Json<Conversation>), AppError> {
+    authorized_conversation(&state, &id, &principal).await?;
+    let at = request.and_then(|Json(r)| r.at);
+    state
+        .conversations
+        .fork(&id, at, principal.name())
+        .await
+        .map(|fork| (StatusCode::CREATED, Json(fork)))
+        .ok_or_else(|| not_found(&id))
+}
+
+pub async fn delete_conversation(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    authorized_conversation(&state, &id, &principal).await?;
+    if state.conversations.delete(&id).await {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(not_found(&id))
+    }
+}
@@ -0,0 +1,81 @@
+use axum::{
+    extract::{Extension, Json, Path, State},
+    http::StatusCode,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::store::instructions::InstructionSet;
+
+#[derive(Debug, Serialize)]
+pub struct InstructionsResponse {
+    /// Read-only deployment layer.
+    pub workspace: InstructionSet,
+    /// The caller's own layer.
+    pub user: InstructionSet,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionInstructions {
+    pub page_url: Option<String>,
+    /// What the next run on this session gets, before the request's own
+    /// `custom_instruction`.
+    pub instruction: Option<String>,
+}
+
+pub async fn get_instructions(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+) -> Json<InstructionsResponse> {
+    Json(InstructionsResponse {
+        workspace: state.instructions.workspace().clone(),
+        user: state
+            .instructions
+            .get(principal.name())
+            .await
+            .unwrap_or_default(),
+    })
+}
+
+pub async fn set_instructions(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Json(set): Json<InstructionSet>,
+) -> Result<StatusCode, AppError> {
+    state.instructions.set(principal.name(), set).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_instructions(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+) -> Result<StatusCode, AppError> {
+    if !state.instructions.remove(principal.name()).await? {
+        return Err(AppError::NotFound(format!(
+            "Instructions of {}",
+            principal.name()
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Preview of the instructions matching the session's current page.
+pub async fn get_session_instructions(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionInstructions>, AppError> {
+    state.authorize_session(&id, &principal).await?;
+    let page_url = state.page_snapshot(&id).await.url;
+    let instruction = state
+        .instructions
+        .compose(principal.name(), page_url.as_deref(), None)
+        .await;
+    Ok(Json(SessionInstructions {
+        page_url,
+        instruction,
+    }))
+}
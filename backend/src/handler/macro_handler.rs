@@ -0,0 +1,81 @@
+use axum::{
+    extract::{Extension, Json, Path, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::macros::Macro;
+use crate::models::ws::ActionCommand;
+use crate::replay;
+use crate::state::AppState;
+
+/// Body of `PUT /macros/{name}`: the steps, or a trace to take them from.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaveMacroRequest {
+    pub description: Option<String>,
+    pub steps: Option<Vec<ActionCommand>>,
+    pub run_id: Option<String>,
+    /// With `run_id`, also keep actions that failed in the run.
+    #[serde(default)]
+    pub include_failed: bool,
+}
+
+pub async fn list_macros(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+) -> Json<Vec<Macro>> {
+    Json(state.macros.list(principal.name()).await)
+}
+
+pub async fn get_macro(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(name): Path<String>,
+) -> Result<Json<Macro>, AppError> {
+    state
+        .macros
+        .get(principal.name(), &name)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Macro {}", name)))
+}
+
+/// Creates or replaces a macro from explicit steps or a trace.
+pub async fn save_macro(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(name): Path<String>,
+    Json(req): Json<SaveMacroRequest>,
+) -> Result<Json<Macro>, AppError> {
+    let steps = match (req.steps, req.run_id) {
+        (Some(steps), None) => steps,
+        (None, Some(run_id)) => {
+            replay::trace_commands(&state, &run_id, &principal, req.include_failed).await?
+        }
+        _ => {
+            return Err(AppError::InvalidRequest(
+                "exactly one of steps or run_id is required".to_string(),
+            ));
+        }
+    };
+    let saved = state
+        .macros
+        .save(principal.name(), &name, req.description, steps)
+        .await?;
+    Ok(Json(saved))
+}
+
+pub async fn delete_macro(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !state.macros.remove(principal.name(), &name).await? {
+        return Err(AppError::NotFound(format!("Macro {}", name)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
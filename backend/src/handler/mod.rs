@@ -1 +1,13 @@
+pub mod admin_handler;
 pub mod agent_handler;
+pub mod approval_handler;
+pub mod auth_handler;
+pub mod conversation_handler;
+pub mod instruction_handler;
+pub mod macro_handler;
+pub mod navigation_handler;
+pub mod openai_handler;
+pub mod replay_handler;
+pub mod session_handler;
+pub mod task_handler;
+pub mod trace_handler;
@@ -0,0 +1,77 @@
+use axum::{
+    extract::{Extension, Json, Path, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::navigation::{NavigationAuditEntry, NavigationPolicy, NavigationPolicyConfig};
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub session_id: Option<String>,
+}
+
+pub async fn get_deployment_policy(
+    State(state): State<Arc<AppState>>,
+) -> Json<NavigationPolicyConfig> {
+    Json(state.navigation_policy.config().clone())
+}
+
+pub async fn get_session_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<NavigationPolicyConfig>, AppError> {
+    state.authorize_session(&id, &principal).await?;
+    let policy = state
+        .session_navigation_policy(&id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Navigation policy for session {}", id)))?;
+    Ok(Json(policy.config().clone()))
+}
+
+pub async fn set_session_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+    Json(config): Json<NavigationPolicyConfig>,
+) -> Result<StatusCode, AppError> {
+    state.authorize_session(&id, &principal).await?;
+    let policy = NavigationPolicy::from_config(config)?;
+    state.set_session_navigation_policy(&id, policy).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_session_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    state.authorize_session(&id, &principal).await?;
+    if !state.clear_session_navigation_policy(&id).await {
+        return Err(AppError::NotFound(format!(
+            "Navigation policy for session {}",
+            id
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Refused navigations of the caller's sessions, including expired ones.
+pub async fn list_audit(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<AuditQuery>,
+) -> Json<Vec<NavigationAuditEntry>> {
+    let entries = state.navigation_audit(query.session_id.as_deref()).await;
+    Json(
+        entries
+            .into_iter()
+            .filter(|entry| entry.principal.as_deref() == Some(principal.name()))
+            .collect(),
+    )
+}
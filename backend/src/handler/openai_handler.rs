@@ -0,0 +1,311 @@
+//! OpenAI-compatible `/v1/chat/completions` on top of the browser agent.
+//!
+//! Existing OpenAI SDK clients can talk to the backend unchanged. Tool calls
+//! are executed server-side against the browser session chosen with the
+//! `X-Session-Id` header (or the non-standard `session_id` body field), so
+//! clients only ever see the assistant's text. Such a completion is a regular
+//! agent run: its id comes back in `X-Run-Id` and it is cancelled, traced and
+//! stored as a conversation like `/agent/run`.
+
+use async_stream::stream;
+use axum::{
+    extract::{Extension, Json, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, Sse},
+    },
+};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::agent::history_messages;
+use crate::agent::run::{RunOutput, start_agent_run};
+use crate::auth::Principal;
+use crate::dtos::AgentRequest;
+use crate::dtos::agent::ChatMessageDto;
+use crate::dtos::events::StreamEvent;
+use crate::dtos::openai::{
+    AssistantMessage, ChatCompletionChoice, ChatCompletionChunk, ChatCompletionRequest,
+    ChatCompletionResponse, ChunkChoice, Delta, Usage,
+};
+use crate::error::AppError;
+use crate::i18n::accept_language;
+use crate::llm::{AgentEvent, AgentParams, TokenUsage, user_message};
+use crate::state::AppState;
+
+pub const SESSION_HEADER: &str = "x-session-id";
+/// Set on completions run against a session; the id works with
+/// `POST /agent/runs/{id}/cancel` and `GET /traces/{run_id}`.
+pub const RUN_ID_HEADER: &str = "x-run-id";
+
+type OpenAiError = (StatusCode, Json<serde_json::Value>);
+
+/// OpenAI-style error body; `code` carries the `AppError` code.
+fn openai_error_body(error: &AppError) -> serde_json::Value {
+    let kind = match error {
+        AppError::InvalidRequest(_) => "invalid_request_error",
+        AppError::RateLimited(_) => "rate_limit_error",
+        AppError::Unauthorized(_) => "authentication_error",
+        AppError::Forbidden(_) => "permission_error",
+        _ => "api_error",
+    };
+    json!({
+        "error": {
+            "message": error.to_string(),
+            "type": kind,
+            "code": error.code(),
+        }
+    })
+}
+
+fn openai_error(error: AppError) -> OpenAiError {
+    (error.status(), Json(openai_error_body(&error)))
+}
+
+impl From<TokenUsage> for Usage {
+    fn from(usage: TokenUsage) -> Self {
+        Self {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+pub async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Result<Response, OpenAiError> {
+    let session_id = headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .or(request.session_id.clone());
+    if let Some(session_id) = &session_id {
+        state
+            .authorize_session(session_id, &principal)
+            .await
+            .map_err(openai_error)?;
+    }
+
+    // System messages become extra instructions, the last user message is the
+    // prompt and everything in between is history.
+    let mut instructions = Vec::new();
+    let mut history = Vec::new();
+    for message in &request.messages {
+        let text = message
+            .content
+            .as_ref()
+            .map(|c| c.text())
+            .unwrap_or_default();
+        match message.role.as_str() {
+            "system" | "developer" => instructions.push(text),
+            role => history.push(ChatMessageDto {
+                role: role.to_string(),
+                content: text,
+            }),
+        }
+    }
+
+    let last = request
+        .messages
+        .iter()
+        .rev()
+        .find(|m| m.role != "system" && m.role != "developer");
+    let Some(last) = last.filter(|m| m.role == "user") else {
+        return Err(openai_error(AppError::InvalidRequest(
+            "The last message must have role 'user'".to_string(),
+        )));
+    };
+    history.pop();
+
+    let query = last.content.as_ref().map(|c| c.text()).unwrap_or_default();
+    let image = last.content.as_ref().and_then(|c| c.image());
+    let instruction = (!instructions.is_empty()).then(|| instructions.join("\n\n"));
+
+    tracing::info!(
+        "Chat completions request: {} (session_id: {:?})",
+        query,
+        session_id
+    );
+
+    let messages = state.locales.negotiate(None, accept_language(&headers));
+    let overrides = request.generation_overrides();
+    let model = overrides
+        .model
+        .clone()
+        .unwrap_or_else(|| state.llm.agent_model().to_string());
+    let (run_id, mut run) = match session_id {
+        Some(session_id) => {
+            // A regular agent run: cancellable, traced and kept as a
+            // conversation. The run id goes out as a header.
+            let agent_request = AgentRequest {
+                query,
+                session_id: Some(session_id),
+                stream: request.stream,
+                image: image.map(str::to_string),
+                custom_instruction: instruction,
+                interactive_elements: None,
+                page_content: None,
+                history: Some(history),
+                conversation_id: None,
+                model: overrides.model,
+                temperature: overrides.temperature,
+                max_tokens: overrides.max_tokens,
+                max_depth: None,
+                locale: None,
+            };
+            let mut run = start_agent_run(state.clone(), principal, agent_request, messages)
+                .await
+                .map_err(openai_error)?;
+            let run_id = match run.next().await {
+                Some(RunOutput::Event(StreamEvent::RunStarted { run_id })) => Some(run_id),
+                _ => None,
+            };
+            (run_id, run)
+        }
+        None => {
+            state
+                .model_settings
+                .check_overrides(&overrides)
+                .map_err(openai_error)?;
+            let instruction = state
+                .instructions
+                .compose(principal.name(), None, instruction.as_deref())
+                .await;
+            let params = AgentParams {
+                preamble: messages.chat_preamble(instruction.as_deref()),
+                tools: vec![],
+                max_depth: state.agent_limits.max_depth,
+                prompt: user_message(&query, image),
+                history: history_messages(&history),
+                overrides,
+            };
+            let run: BoxStream<'static, RunOutput> = Box::pin(
+                state
+                    .llm
+                    .stream_agent(params)
+                    .filter_map(|event| async move {
+                        match event {
+                            Ok(AgentEvent::Text(text)) => Some(RunOutput::Text(text)),
+                            Ok(AgentEvent::Usage(usage)) => {
+                                Some(RunOutput::Event(StreamEvent::Usage(usage)))
+                            }
+                            Ok(_) => None,
+                            Err(error) => Some(RunOutput::Error(error)),
+                        }
+                    }),
+            );
+            (None, run)
+        }
+    };
+
+    let id = format!("chatcmpl-{}", Uuid::new_v4().simple());
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    if !request.stream {
+        let mut content = String::new();
+        let mut usage = TokenUsage::default();
+        while let Some(output) = run.next().await {
+            match output {
+                RunOutput::Text(text) => content.push_str(&text),
+                RunOutput::Event(StreamEvent::Usage(u)) => usage = u,
+                RunOutput::Event(_) => {}
+                RunOutput::Error(e) => {
+                    tracing::warn!("Chat completions error: {}", e);
+                    return Err(openai_error(e));
+                }
+            }
+        }
+
+        let response = Json(ChatCompletionResponse {
+            id,
+            object: "chat.completion",
+            created,
+            model,
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: AssistantMessage {
+                    role: "assistant",
+                    content,
+                },
+                finish_reason: "stop",
+            }],
+            usage: usage.into(),
+        });
+        return Ok(with_run_id(response.into_response(), run_id));
+    }
+
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .is_some_and(|o| o.include_usage);
+
+    let sse_stream = stream! {
+        let chunk = |delta: Delta, finish_reason: Option<&'static str>, usage: Option<Usage>| {
+            let choices = if usage.is_some() {
+                vec![]
+            } else {
+                vec![ChunkChoice { index: 0, delta, finish_reason }]
+            };
+            let chunk = ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk",
+                created,
+                model: model.clone(),
+                choices,
+                usage,
+            };
+            Event::default().data(serde_json::to_string(&chunk).unwrap_or_default())
+        };
+
+        yield Ok::<_, Infallible>(chunk(Delta { role: Some("assistant"), content: None }, None, None));
+
+        let mut usage = TokenUsage::default();
+        let mut failed = false;
+        while let Some(output) = run.next().await {
+            match output {
+                RunOutput::Text(text) => {
+                    yield Ok(chunk(Delta { role: None, content: Some(text) }, None, None));
+                }
+                RunOutput::Event(StreamEvent::Usage(u)) => usage = u,
+                RunOutput::Event(_) => {}
+                RunOutput::Error(e) => {
+                    tracing::warn!("Chat completions stream error: {}", e);
+                    yield Ok(Event::default().data(openai_error_body(&e).to_string()));
+                    failed = true;
+                    break;
+                }
+            }
+        }
+
+        // A failed completion didn't stop, it only ends the stream
+        if !failed {
+            yield Ok(chunk(Delta::default(), Some("stop"), None));
+            if include_usage {
+                yield Ok(chunk(Delta::default(), None, Some(usage.into())));
+            }
+        }
+        yield Ok(Event::default().data("[DONE]"));
+    };
+
+    Ok(with_run_id(Sse::new(sse_stream).into_response(), run_id))
+}
+
+fn with_run_id(mut response: Response, run_id: Option<String>) -> Response {
+    if let Some(value) = run_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert(RUN_ID_HEADER, value);
+    }
+    response
+}
@@ -0,0 +1,117 @@
+use axum::{
+    extract::{Extension, Json, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::replay::{self, Replay, ReplayControl, ReplayRequest};
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct ListReplaysQuery {
+    pub session_id: Option<String>,
+}
+
+/// The replay, if `principal` started it or is an admin.
+async fn authorized_replay(
+    state: &AppState,
+    id: &str,
+    principal: &Principal,
+) -> Result<Replay, AppError> {
+    let replay = state
+        .replays
+        .get(id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Replay {}", id)))?;
+    if replay.principal != principal.name() && state.auth.require_admin(principal).is_err() {
+        return Err(AppError::Forbidden(format!(
+            "replay {} belongs to another principal",
+            id
+        )));
+    }
+    Ok(replay)
+}
+
+pub async fn start_replay(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(session_id): Path<String>,
+    Json(request): Json<ReplayRequest>,
+) -> Result<Response, AppError> {
+    let replay = replay::submit(&state, &principal, &session_id, request).await?;
+    Ok((StatusCode::ACCEPTED, Json(replay)).into_response())
+}
+
+pub async fn list_replays(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ListReplaysQuery>,
+) -> Json<Vec<Replay>> {
+    Json(
+        state
+            .replays
+            .list(principal.name(), query.session_id.as_deref())
+            .await,
+    )
+}
+
+pub async fn get_replay(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<Replay>, AppError> {
+    Ok(Json(authorized_replay(&state, &id, &principal).await?))
+}
+
+/// Sends `control` and answers once the replay paused again or finished.
+async fn control_and_wait(
+    state: &AppState,
+    id: &str,
+    principal: &Principal,
+    control: ReplayControl,
+) -> Result<Json<Replay>, AppError> {
+    authorized_replay(state, id, principal).await?;
+    let changes = state.replays.control(id, control).await?;
+    Ok(Json(replay::settled(changes).await))
+}
+
+/// Runs the next step (retrying it if it failed) and pauses.
+pub async fn step_replay(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<Replay>, AppError> {
+    control_and_wait(&state, &id, &principal, ReplayControl::Step).await
+}
+
+pub async fn skip_replay_step(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<Replay>, AppError> {
+    control_and_wait(&state, &id, &principal, ReplayControl::Skip).await
+}
+
+pub async fn cancel_replay(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<Replay>, AppError> {
+    control_and_wait(&state, &id, &principal, ReplayControl::Cancel).await
+}
+
+/// Continues without pausing between steps; answers right away.
+pub async fn resume_replay(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    authorized_replay(&state, &id, &principal).await?;
+    state.replays.control(&id, ReplayControl::Resume).await?;
+    let replay = authorized_replay(&state, &id, &principal).await?;
+    Ok((StatusCode::ACCEPTED, Json(replay)).into_response())
+}
@@ -0,0 +1,34 @@
+use axum::extract::{Extension, Json, Path, State};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::session::PageSnapshot;
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct SessionDetails {
+    pub session_id: String,
+    /// False while the session waits for the extension to reconnect.
+    pub connected: bool,
+    /// Where the browser is: URL, title, tab and recent pages.
+    pub page: PageSnapshot,
+}
+
+pub async fn get_session(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionDetails>, AppError> {
+    state.authorize_session(&id, &principal).await?;
+    let connected = state
+        .session_connected(&id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Session {}", id)))?;
+    Ok(Json(SessionDetails {
+        page: state.page_snapshot(&id).await,
+        session_id: id,
+        connected,
+    }))
+}
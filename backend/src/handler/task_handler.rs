@@ -0,0 +1,156 @@
+use async_stream::stream;
+use axum::{
+    extract::{Extension, Json, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, Sse},
+    },
+};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::auth::Principal;
+use crate::dtos::AgentRequest;
+use crate::error::AppError;
+use crate::i18n::accept_language;
+use crate::state::AppState;
+use crate::tasks::{self, Task, TaskLogEntry};
+
+#[derive(Debug, Deserialize)]
+pub struct ListTasksQuery {
+    pub session_id: Option<String>,
+}
+
+/// The task, if `principal` owns it or is an admin.
+async fn authorized_task(
+    state: &AppState,
+    id: &str,
+    principal: &Principal,
+) -> Result<Task, AppError> {
+    let task = state
+        .tasks
+        .get(id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Task {}", id)))?;
+    if task.principal != principal.name() && state.auth.require_admin(principal).is_err() {
+        return Err(AppError::Forbidden(format!(
+            "task {} belongs to another principal",
+            id
+        )));
+    }
+    Ok(task)
+}
+
+pub async fn create_task(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Json(request): Json<AgentRequest>,
+) -> Response {
+    let messages = state
+        .locales
+        .negotiate(request.locale.as_deref(), accept_language(&headers));
+    match tasks::submit(&state, principal, request, messages.clone()).await {
+        Ok(task) => (StatusCode::ACCEPTED, Json(task)).into_response(),
+        Err(error) => messages.error_response(error),
+    }
+}
+
+pub async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ListTasksQuery>,
+) -> Json<Vec<Task>> {
+    Json(
+        state
+            .tasks
+            .list(principal.name(), query.session_id.as_deref())
+            .await,
+    )
+}
+
+pub async fn get_task(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<Task>, AppError> {
+    Ok(Json(authorized_task(&state, &id, &principal).await?))
+}
+
+pub async fn cancel_task(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let task = authorized_task(&state, &id, &principal).await?;
+    if !state.tasks.cancel(&id).await {
+        return Err(AppError::InvalidRequest(format!(
+            "task {} is already {:?}",
+            id, task.status
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Same events as `/agent/run`: everything so far, then live ones. Ends
+/// with a `task` event holding the final state, then `[DONE]`.
+pub async fn task_events(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    authorized_task(&state, &id, &principal).await?;
+    let Some(subscription) = state.tasks.subscribe(&id).await else {
+        return Err(AppError::NotFound(format!("Task {}", id)));
+    };
+
+    let sse_stream = stream! {
+        let mut live = subscription.live;
+        let mut finished = false;
+        for entry in subscription.backlog {
+            finished = matches!(entry, TaskLogEntry::Finished(_));
+            if let Some(event) = to_sse_event(&entry) {
+                yield Ok::<_, Infallible>(event);
+            }
+        }
+        while !finished {
+            let entry = match live.recv().await {
+                Ok(entry) => entry,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Task {} follower missed {} events", id, skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            finished = matches!(entry, TaskLogEntry::Finished(_));
+            if let Some(event) = to_sse_event(&entry) {
+                yield Ok::<_, Infallible>(event);
+            }
+        }
+        if let Some(task) = state.tasks.get(&id).await {
+            yield Ok::<_, Infallible>(
+                Event::default()
+                    .event("task")
+                    .data(serde_json::to_string(&task).unwrap_or_default()),
+            );
+        }
+        yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
+    };
+    Ok(Sse::new(sse_stream).into_response())
+}
+
+fn to_sse_event(entry: &TaskLogEntry) -> Option<Event> {
+    match entry {
+        TaskLogEntry::Text(text) => Some(Event::default().data(text)),
+        TaskLogEntry::Event(event) => Some(event.to_sse_event()),
+        TaskLogEntry::Error(error) => Some(
+            Event::default()
+                .event("error")
+                .data(serde_json::to_string(error).unwrap_or_default()),
+        ),
+        TaskLogEntry::Finished(_) => None,
+    }
+}
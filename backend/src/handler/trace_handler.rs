@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::auth::Principal;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::store::traces::TraceSummary;
+
+#[derive(Debug, Deserialize)]
+pub struct ListTracesQuery {
+    pub session_id: Option<String>,
+}
+
+/// The caller's traces; admins see everyone's.
+pub async fn list_traces(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<ListTracesQuery>,
+) -> Json<Vec<TraceSummary>> {
+    let is_admin = state.auth.require_admin(&principal).is_ok();
+    let traces = state.traces.list(query.session_id.as_deref()).await;
+    Json(
+        traces
+            .into_iter()
+            .filter(|t| is_admin || t.principal == principal.name())
+            .collect(),
+    )
+}
+
+/// Downloads the trace as JSONL.
+pub async fn download_trace(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(run_id): Path<String>,
+) -> Result<Response, AppError> {
+    state.authorized_trace(&run_id, &principal).await?;
+    let body = state
+        .traces
+        .read(&run_id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Trace {}", run_id)))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.jsonl\"", run_id),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
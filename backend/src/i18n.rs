@@ -0,0 +1,616 @@
+//! Localized prompts and error messages.
+//!
+//! Each locale is a directory `locales/<tag>/` holding:
+//! - `messages.toml`: the custom-instruction block, the page snapshot
+//!   headings and one message per [`AppError`] code,
+//! - `agent.md` and `chat.md`: preamble templates (see [`crate::prompts`]).
+//!
+//! `en`, `id` and `ja` are built in. `locales.dir` adds or replaces locales
+//! (a directory may override only some of the files) and is polled for
+//! changes, so prompts can be edited without restarting. A request gets its
+//! locale from its `locale` field, then `Accept-Language`, then the
+//! configured default.
+
+use axum::{
+    Json,
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response, sse::Event},
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{AppError, ErrorPayload};
+use crate::prompts::{PromptVars, Template, today};
+
+/// Locale used when nothing else matches.
+pub const DEFAULT_LOCALE: &str = "id";
+
+struct BuiltinLocale {
+    tag: &'static str,
+    messages: &'static str,
+    agent: &'static str,
+    chat: &'static str,
+}
+
+const BUILTIN_LOCALES: &[BuiltinLocale] = &[
+    BuiltinLocale {
+        tag: "en",
+        messages: include_str!("../locales/en/messages.toml"),
+        agent: include_str!("../locales/en/agent.md"),
+        chat: include_str!("../locales/en/chat.md"),
+    },
+    BuiltinLocale {
+        tag: "id",
+        messages: include_str!("../locales/id/messages.toml"),
+        agent: include_str!("../locales/id/agent.md"),
+        chat: include_str!("../locales/id/chat.md"),
+    },
+    BuiltinLocale {
+        tag: "ja",
+        messages: include_str!("../locales/ja/messages.toml"),
+        agent: include_str!("../locales/ja/agent.md"),
+        chat: include_str!("../locales/ja/chat.md"),
+    },
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreambleStrings {
+    /// Value of `{{custom_instruction}}` when the client sends one;
+    /// `{instruction}` is replaced with it.
+    pub custom_instruction: String,
+    /// Value of `{{page_url}}` and `{{page_title}}` before the page is known.
+    pub unknown_page: String,
+}
+
+/// Pieces of `{{page_context}}`, see [`crate::agent::context`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PageContextStrings {
+    /// Heading above the element list.
+    pub elements: String,
+    /// Last list item when elements were cut; `{count}` is replaced.
+    pub omitted_elements: String,
+    /// Heading above the page text.
+    pub content: String,
+    /// Appended when the page text was cut.
+    pub truncated_content: String,
+}
+
+/// Contents of `messages.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MessageFile {
+    preamble: PreambleStrings,
+    page_context: PageContextStrings,
+    #[serde(default)]
+    errors: HashMap<String, String>,
+}
+
+impl MessageFile {
+    fn parse(name: &str, raw: &str) -> Result<Self, String> {
+        toml::from_str(raw).map_err(|e| format!("{}: {}", name, e))
+    }
+}
+
+/// Strings and templates of one locale.
+#[derive(Debug, Clone)]
+pub struct Messages {
+    pub locale: String,
+    pub preamble: PreambleStrings,
+    pub page_context: PageContextStrings,
+    /// Error code -> message; `{detail}` is replaced with the error detail.
+    /// Missing codes fall back to the English `Display` text.
+    pub errors: HashMap<String, String>,
+    /// System prompt of the tool-enabled agent.
+    pub agent: Template,
+    /// System prompt of the legacy chat path.
+    pub chat: Template,
+}
+
+/// Per-run inputs of the agent preamble.
+#[derive(Debug, Default)]
+pub struct AgentPrompt<'a> {
+    /// Tool list from [`crate::prompts::describe_tools`].
+    pub tools: String,
+    pub page_url: Option<&'a str>,
+    pub page_title: Option<&'a str>,
+    pub custom_instruction: Option<&'a str>,
+    /// Rendered client snapshot, or empty.
+    pub page_context: String,
+}
+
+impl Messages {
+    fn vars(&self, page_url: Option<&str>, custom_instruction: Option<&str>) -> PromptVars {
+        PromptVars {
+            tools: String::new(),
+            page_url: page_url.unwrap_or(&self.preamble.unknown_page).to_string(),
+            page_title: self.preamble.unknown_page.clone(),
+            page_context: String::new(),
+            date: today(),
+            custom_instruction: custom_instruction
+                .map(str::trim)
+                .filter(|i| !i.is_empty())
+                .map(|i| self.preamble.custom_instruction.replace("{instruction}", i))
+                .unwrap_or_default(),
+            locale: self.locale.clone(),
+        }
+    }
+
+    pub fn chat_preamble(&self, custom_instruction: Option<&str>) -> String {
+        self.chat.render(&self.vars(None, custom_instruction))
+    }
+
+    pub fn agent_preamble(&self, prompt: AgentPrompt) -> String {
+        let vars = PromptVars {
+            tools: prompt.tools,
+            page_title: prompt
+                .page_title
+                .unwrap_or(&self.preamble.unknown_page)
+                .to_string(),
+            page_context: prompt.page_context,
+            ..self.vars(prompt.page_url, prompt.custom_instruction)
+        };
+        self.agent.render(&vars)
+    }
+
+    pub fn error_message(&self, error: &AppError) -> String {
+        match self.errors.get(error.code()) {
+            Some(template) => template.replace("{detail}", &error.detail()),
+            None => error.to_string(),
+        }
+    }
+
+    pub fn error_payload(&self, error: &AppError) -> ErrorPayload {
+        ErrorPayload {
+            message: self.error_message(error),
+            ..error.payload()
+        }
+    }
+
+    /// Localized counterpart of [`AppError::to_sse_event`].
+    pub fn error_event(&self, error: &AppError) -> Event {
+        Event::default()
+            .event("error")
+            .data(serde_json::to_string(&self.error_payload(error)).unwrap_or_default())
+    }
+
+    /// Localized counterpart of `AppError::into_response`.
+    pub fn error_response(&self, error: AppError) -> Response {
+        if error.status().is_server_error() {
+            tracing::error!("{}", error);
+        }
+        (
+            error.status(),
+            Json(json!({ "error": self.error_payload(&error) })),
+        )
+            .into_response()
+    }
+}
+
+/// Every loaded locale.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    default: String,
+    locales: HashMap<String, Arc<Messages>>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Catalog {
+    /// The locales shipped with the binary.
+    pub fn builtin() -> Self {
+        let locales = BUILTIN_LOCALES
+            .iter()
+            .map(|builtin| {
+                let file = MessageFile::parse(builtin.tag, builtin.messages)
+                    .expect("built-in messages are valid");
+                let messages = Messages {
+                    locale: builtin.tag.to_string(),
+                    preamble: file.preamble,
+                    page_context: file.page_context,
+                    errors: file.errors,
+                    agent: Template::parse("agent.md", builtin.agent)
+                        .expect("built-in agent template is valid"),
+                    chat: Template::parse("chat.md", builtin.chat)
+                        .expect("built-in chat template is valid"),
+                };
+                (builtin.tag.to_string(), Arc::new(messages))
+            })
+            .collect();
+        Self {
+            default: DEFAULT_LOCALE.to_string(),
+            locales,
+        }
+    }
+
+    /// Built-in locales, overridden by `dir` when given.
+    pub fn load(dir: Option<&Path>, default: Option<&str>) -> Result<Self, String> {
+        let mut catalog = Self::builtin();
+        if let Some(dir) = dir {
+            catalog.load_dir(dir)?;
+        }
+        if let Some(default) = default {
+            catalog.set_default(default)?;
+        }
+        Ok(catalog)
+    }
+
+    /// Loads every `<locale>/` directory in `dir`. Files a directory omits
+    /// are taken from the built-in locale of the same name.
+    pub fn load_dir(&mut self, dir: &Path) -> Result<(), String> {
+        let entries =
+            std::fs::read_dir(dir).map_err(|e| format!("cannot read {}: {}", dir.display(), e))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let Some(locale) = path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let locale = normalize(locale);
+            let messages = self.load_locale(&locale, &path)?;
+            self.locales.insert(locale, Arc::new(messages));
+        }
+        Ok(())
+    }
+
+    fn load_locale(&self, locale: &str, dir: &Path) -> Result<Messages, String> {
+        let base = self.locales.get(locale);
+        let read = |file: &str| -> Result<Option<(String, String)>, String> {
+            let path = dir.join(file);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let raw = std::fs::read_to_string(&path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            Ok(Some((path.display().to_string(), raw)))
+        };
+        let missing = |file: &str| format!("{}: {} is missing", dir.display(), file);
+
+        let (preamble, page_context, errors) = match read("messages.toml")? {
+            Some((name, raw)) => {
+                let file = MessageFile::parse(&name, &raw)?;
+                (file.preamble, file.page_context, file.errors)
+            }
+            None => {
+                let base = base.ok_or_else(|| missing("messages.toml"))?;
+                (
+                    base.preamble.clone(),
+                    base.page_context.clone(),
+                    base.errors.clone(),
+                )
+            }
+        };
+        let template = |file: &str, fallback: Option<&Template>| match read(file)? {
+            Some((name, raw)) => Template::parse(&name, raw),
+            None => fallback.cloned().ok_or_else(|| missing(file)),
+        };
+        Ok(Messages {
+            locale: locale.to_string(),
+            preamble,
+            page_context,
+            errors,
+            agent: template("agent.md", base.map(|m| &m.agent))?,
+            chat: template("chat.md", base.map(|m| &m.chat))?,
+        })
+    }
+
+    pub fn set_default(&mut self, locale: &str) -> Result<(), String> {
+        let locale = normalize(locale);
+        if !self.locales.contains_key(&locale) {
+            return Err(format!(
+                "unknown locale {:?} (available: {})",
+                locale,
+                self.available().join(", ")
+            ));
+        }
+        self.default = locale;
+        Ok(())
+    }
+
+    pub fn available(&self) -> Vec<&str> {
+        let mut locales: Vec<&str> = self.locales.keys().map(String::as_str).collect();
+        locales.sort_unstable();
+        locales
+    }
+
+    pub fn default_messages(&self) -> Arc<Messages> {
+        self.locales[&self.default].clone()
+    }
+
+    /// Exact tag first (`pt-br`), then its primary language (`pt`).
+    pub fn get(&self, tag: &str) -> Option<Arc<Messages>> {
+        let tag = normalize(tag);
+        self.locales
+            .get(&tag)
+            .or_else(|| {
+                let primary = tag.split('-').next()?;
+                self.locales.get(primary)
+            })
+            .cloned()
+    }
+
+    /// Picks the locale for a request.
+    pub fn negotiate(
+        &self,
+        requested: Option<&str>,
+        accept_language: Option<&str>,
+    ) -> Arc<Messages> {
+        requested
+            .and_then(|tag| self.get(tag))
+            .or_else(|| {
+                accept_language_tags(accept_language?)
+                    .iter()
+                    .find_map(|tag| self.get(tag))
+            })
+            .unwrap_or_else(|| self.default_messages())
+    }
+}
+
+/// Modification times of every file under a locales directory.
+type Fingerprint = Vec<(PathBuf, SystemTime)>;
+
+/// The live catalog. With a `locales.dir`, [`LocaleStore::watch`] polls it
+/// and swaps in a new catalog when files change; a broken edit is logged and
+/// the previous catalog stays active.
+#[derive(Debug)]
+pub struct LocaleStore {
+    current: RwLock<Arc<Catalog>>,
+    dir: Option<PathBuf>,
+    default: Option<String>,
+    fingerprint: Mutex<Fingerprint>,
+}
+
+impl Default for LocaleStore {
+    fn default() -> Self {
+        Self {
+            current: RwLock::new(Arc::new(Catalog::builtin())),
+            dir: None,
+            default: None,
+            fingerprint: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl LocaleStore {
+    pub fn load(dir: Option<PathBuf>, default: Option<String>) -> Result<Self, String> {
+        let fingerprint = dir.as_deref().map(fingerprint).unwrap_or_default();
+        let catalog = Catalog::load(dir.as_deref(), default.as_deref())?;
+        Ok(Self {
+            current: RwLock::new(Arc::new(catalog)),
+            dir,
+            default,
+            fingerprint: Mutex::new(fingerprint),
+        })
+    }
+
+    pub fn current(&self) -> Arc<Catalog> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn negotiate(
+        &self,
+        requested: Option<&str>,
+        accept_language: Option<&str>,
+    ) -> Arc<Messages> {
+        self.current().negotiate(requested, accept_language)
+    }
+
+    /// Reloads the catalog if any file under `locales.dir` changed. Returns
+    /// whether a new catalog was installed.
+    pub fn reload_if_changed(&self) -> Result<bool, String> {
+        let Some(dir) = &self.dir else {
+            return Ok(false);
+        };
+        let latest = fingerprint(dir);
+        {
+            let mut known = self
+                .fingerprint
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if *known == latest {
+                return Ok(false);
+            }
+            // Remember the attempt so a broken file is reported once
+            *known = latest;
+        }
+        let catalog = Catalog::load(Some(dir), self.default.as_deref())?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(catalog);
+        Ok(true)
+    }
+
+    /// Polls `locales.dir` every `interval`. Does nothing without a
+    /// directory or with a zero interval.
+    pub fn watch(self: Arc<Self>, interval: Duration) {
+        if self.dir.is_none() || interval.is_zero() {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => tracing::info!("Reloaded locales and prompt templates"),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("Keeping previous prompts, reload failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn fingerprint(dir: &Path) -> Fingerprint {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                files.push((path, modified));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn normalize(tag: &str) -> String {
+    tag.trim().to_lowercase().replace('_', "-")
+}
+
+/// Language tags of an `Accept-Language` header, best first. Tags with
+/// `q=0` and the `*` wildcard are dropped.
+fn accept_language_tags(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim();
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then(|| (tag.to_string(), quality))
+        })
+        .collect();
+    // Stable sort keeps header order between equal weights
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+pub fn accept_language(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ERROR_CODES: &[&str] = &[
+        "provider_error",
+        "rate_limited",
+        "empty_response",
+        "max_depth",
+        "tool_timeout",
+        "tool_failed",
+        "no_connection",
+        "channel_closed",
+        "navigation_blocked",
+        "action_rejected",
+        "invalid_request",
+        "unauthorized",
+        "forbidden",
+        "not_found",
+        "internal_error",
+    ];
+
+    #[test]
+    fn test_builtin_locales_are_complete() {
+        let catalog = Catalog::builtin();
+        assert_eq!(catalog.available(), vec!["en", "id", "ja"]);
+        for locale in catalog.available() {
+            let messages = catalog.get(locale).unwrap();
+            for code in ERROR_CODES {
+                assert!(
+                    messages.errors.contains_key(*code),
+                    "{} is missing {}",
+                    locale,
+                    code
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let catalog = Catalog::builtin();
+        let pick = |requested, header| catalog.negotiate(requested, header).locale.clone();
+
+        assert_eq!(pick(None, None), "id");
+        assert_eq!(pick(Some("ja"), Some("en-US")), "ja");
+        assert_eq!(pick(Some("fr"), Some("en-US,en;q=0.9")), "en");
+        assert_eq!(
+            pick(None, Some("fr-CH, fr;q=0.9, ja;q=0.8, en;q=0.7")),
+            "ja"
+        );
+        assert_eq!(pick(None, Some("en;q=0, ja_JP")), "ja");
+        assert_eq!(pick(None, Some("*")), "id");
+    }
+
+    #[test]
+    fn test_localized_errors_and_preambles() {
+        let catalog = Catalog::builtin();
+        let ja = catalog.get("ja").unwrap();
+        assert_eq!(
+            ja.error_message(&AppError::ToolTimeout { seconds: 30 }),
+            "ツールの実行が 30 秒でタイムアウトしました"
+        );
+        assert_eq!(
+            ja.error_payload(&AppError::NoConnection).code,
+            "no_connection"
+        );
+
+        let en = catalog.get("en").unwrap();
+        let preamble = en.chat_preamble(Some("Be brief"));
+        assert!(preamble.starts_with("Always answer in English"));
+        assert!(preamble.ends_with("## Additional Instructions\nBe brief\n"));
+        assert!(!en.chat_preamble(Some("  ")).contains("Additional"));
+
+        let agent = en.agent_preamble(AgentPrompt {
+            tools: "- `navigate_to(url)`: Navigate".into(),
+            page_url: Some("https://example.com/"),
+            page_title: Some("Example Domain"),
+            custom_instruction: None,
+            page_context: "\n\n## Snapshot".into(),
+        });
+        assert!(agent.contains("- Locale: en\n\n## Snapshot\n"));
+        assert!(agent.contains("- Current page: https://example.com/"));
+        assert!(agent.contains("- Page title: Example Domain"));
+        assert!(agent.contains("## Available Tools\n- `navigate_to(url)`"));
+        assert!(!agent.contains("{{"));
+    }
+
+    #[test]
+    fn test_reload_picks_up_edited_templates() {
+        let dir = std::env::temp_dir().join(format!("locales-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("en")).unwrap();
+        std::fs::write(dir.join("en/chat.md"), "v1 {{locale}}").unwrap();
+
+        let store = LocaleStore::load(Some(dir.clone()), Some("en".into())).unwrap();
+        assert_eq!(store.negotiate(None, None).chat_preamble(None), "v1 en");
+        assert!(!store.reload_if_changed().unwrap());
+
+        // A broken edit keeps the previous templates
+        std::fs::write(dir.join("en/chat.md"), "v2 {{nope}}").unwrap();
+        std::fs::write(dir.join("en/extra.md"), "").unwrap();
+        assert!(store.reload_if_changed().is_err());
+        assert_eq!(store.negotiate(None, None).chat_preamble(None), "v1 en");
+
+        std::fs::write(dir.join("en/chat.md"), "v3 {{locale}}").unwrap();
+        std::fs::remove_file(dir.join("en/extra.md")).unwrap();
+        assert!(store.reload_if_changed().unwrap());
+        assert_eq!(store.negotiate(None, None).chat_preamble(None), "v3 en");
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,107 @@
+//! Browser AI backend: LLM providers, the tool-enabled agent and the
+//! WebSocket bridge to the browser extension.
+
+pub mod agent;
+pub mod approval;
+pub mod auth;
+pub mod config;
+pub mod dtos;
+pub mod error;
+pub mod handler;
+pub mod i18n;
+pub mod llm;
+pub mod macros;
+pub mod models;
+pub mod navigation;
+pub mod prompts;
+pub mod replay;
+pub mod routes;
+pub mod session;
+pub mod state;
+pub mod store;
+pub mod tasks;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tools;
+pub mod utils;
+
+#[cfg(test)]
+mod tests {
+    use crate::llm::parse_image_data;
+    use crate::models::{ChatRequest, ChatResponse, HealthResponse};
+    use rig::message::ImageMediaType;
+
+    #[test]
+    fn test_health_response_serialize() {
+        let resp = HealthResponse {
+            status: "ok".to_string(),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, r#"{"status":"ok"}"#);
+    }
+
+    #[test]
+    fn test_chat_request_deserialize() {
+        let json = r#"{
+            "message": "Hello",
+            "custom_instruction": "Be concise",
+            "image": "base64data"
+        }"#;
+        let req: ChatRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.message, "Hello");
+        assert_eq!(req.custom_instruction, Some("Be concise".to_string()));
+        assert_eq!(req.image, Some("base64data".to_string()));
+    }
+
+    #[test]
+    fn test_chat_response_serialize() {
+        let resp = ChatResponse {
+            response: "Hi".to_string(),
+            prompt_tokens: None,
+            response_tokens: None,
+            total_tokens: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        // Should not contain tokens since they are None and marked with skip_serializing_if
+        assert_eq!(json, r#"{"response":"Hi"}"#);
+
+        let resp_with_tokens = ChatResponse {
+            response: "Hi".to_string(),
+            prompt_tokens: Some(10),
+            response_tokens: Some(20),
+            total_tokens: Some(30),
+        };
+        let json_with_tokens = serde_json::to_string(&resp_with_tokens).unwrap();
+        assert!(json_with_tokens.contains(r#""prompt_tokens":10"#));
+        assert!(json_with_tokens.contains(r#""response_tokens":20"#));
+        assert!(json_with_tokens.contains(r#""total_tokens":30"#));
+    }
+
+    #[test]
+    fn test_base64_prefix_stripping() {
+        let png = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAA...";
+        let (media_type, data) = parse_image_data(png);
+        assert!(matches!(media_type, ImageMediaType::PNG));
+        assert_eq!(data, "iVBORw0KGgoAAAANSUhEUgAA...");
+
+        let jpeg = "data:image/jpeg;base64,/9j/4AAQSkZJRgABAQAAAQABAAD...";
+        let (media_type, data) = parse_image_data(jpeg);
+        assert!(matches!(media_type, ImageMediaType::JPEG));
+        assert_eq!(data, "/9j/4AAQSkZJRgABAQAAAQABAAD...");
+
+        let webp = "data:image/webp;base64,UklGRtAAAABXRUJQVlA4...";
+        let (media_type, data) = parse_image_data(webp);
+        assert!(matches!(media_type, ImageMediaType::WEBP));
+        assert_eq!(data, "UklGRtAAAABXRUJQVlA4...");
+
+        let unknown_with_comma = "image/tiff,somebase64data";
+        let (media_type, data) = parse_image_data(unknown_with_comma);
+        assert!(matches!(media_type, ImageMediaType::JPEG));
+        assert_eq!(data, "somebase64data");
+
+        let raw_data = "somebase64datawithoutcomma";
+        let (media_type, data) = parse_image_data(raw_data);
+        assert!(matches!(media_type, ImageMediaType::JPEG));
+        assert_eq!(data, "somebase64datawithoutcomma");
+    }
+}
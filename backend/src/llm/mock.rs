@@ -0,0 +1,313 @@
+//! Deterministic, offline `LlmProvider` that replays a scripted fixture.
+//!
+//! Selected with `LLM_PROVIDER=mock` and `MOCK_LLM_FIXTURE=<path>`. Tool calls
+//! in the script are executed against the real tools passed in
+//! `AgentParams`, so the whole SSE + WebSocket tool loop runs without network.
+//!
+//! Fixture format:
+//!
+//! ```json
+//! {
+//!   "turns": [
+//!     { "text": ["Let me look."], "tool_calls": [{ "name": "get_interactive_elements", "arguments": {} }] },
+//!     { "text": ["Done", "!"] }
+//!   ],
+//!   "usage": { "input_tokens": 10, "output_tokens": 5, "total_tokens": 15 }
+//! }
+//! ```
+
+use async_stream::stream;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::error::AppError;
+use crate::llm::{
+    AgentEvent, AgentParams, GenerationOverrides, LlmProvider, TokenUsage, usage_json,
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockScript {
+    #[serde(default)]
+    pub turns: Vec<MockTurn>,
+    pub usage: Option<MockUsage>,
+}
+
+/// One model turn: text chunks, then tool calls, then (optionally) a failure.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockTurn {
+    #[serde(default)]
+    pub text: Vec<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<MockToolCall>,
+    /// Emits a provider error instead of continuing.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockToolCall {
+    pub name: String,
+    #[serde(default = "empty_object")]
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct MockUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+fn empty_object() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+impl From<MockUsage> for TokenUsage {
+    fn from(usage: MockUsage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+pub struct MockProvider {
+    script: MockScript,
+}
+
+impl MockProvider {
+    pub fn new(script: MockScript) -> Self {
+        Self { script }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read mock fixture {}: {}", path.display(), e))?;
+        Self::from_json(&raw).map_err(|e| format!("Invalid mock fixture {}: {}", path.display(), e))
+    }
+
+    pub fn from_json(raw: &str) -> Result<Self, String> {
+        serde_json::from_str(raw)
+            .map(Self::new)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn chat_model(&self) -> &str {
+        "mock"
+    }
+
+    fn agent_model(&self) -> &str {
+        "mock"
+    }
+
+    fn complete<'a>(
+        &'a self,
+        _message: &'a str,
+        _preamble: &'a str,
+        _image: Option<&'a str>,
+        _overrides: &'a GenerationOverrides,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let mut response = String::new();
+            for turn in &self.script.turns {
+                if let Some(error) = &turn.error {
+                    return Err(AppError::from_provider(error.clone()));
+                }
+                response.extend(turn.text.iter().map(String::as_str));
+            }
+            Ok(response)
+        })
+    }
+
+    fn stream(
+        &self,
+        _message: &str,
+        _preamble: &str,
+        _image: Option<&str>,
+        _overrides: &GenerationOverrides,
+    ) -> BoxStream<'static, Result<String, AppError>> {
+        let script = self.script.clone();
+
+        Box::pin(stream! {
+            for turn in script.turns {
+                for chunk in turn.text {
+                    yield Ok(chunk);
+                }
+                if let Some(error) = turn.error {
+                    yield Err(AppError::from_provider(error));
+                    return;
+                }
+            }
+            if let Some(usage) = script.usage {
+                yield Ok(usage_json(&usage.into()));
+            }
+        })
+    }
+
+    fn stream_agent(
+        &self,
+        params: AgentParams,
+    ) -> BoxStream<'static, Result<AgentEvent, AppError>> {
+        let script = self.script.clone();
+
+        Box::pin(stream! {
+            let mut call_count = 0;
+            let mut depth = 0;
+            for turn in script.turns {
+                for chunk in turn.text {
+                    yield Ok(AgentEvent::Text(chunk));
+                }
+                if let Some(error) = turn.error {
+                    yield Err(AppError::from_provider(error));
+                    return;
+                }
+                if turn.tool_calls.is_empty() {
+                    continue;
+                }
+
+                depth += 1;
+                if depth > params.max_depth {
+                    yield Err(AppError::MaxDepth(params.max_depth));
+                    return;
+                }
+
+                for call in turn.tool_calls {
+                    call_count += 1;
+                    let id = format!("mock_call_{}", call_count);
+                    yield Ok(AgentEvent::ToolCall {
+                        id: id.clone(),
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    });
+
+                    let Some(tool) = params.tools.iter().find(|t| t.name() == call.name) else {
+                        yield Err(AppError::Provider(format!("ToolNotFoundError: {}", call.name)));
+                        return;
+                    };
+                    // Like rig, tool failures are fed back to the model as the result
+                    let content = match tool.call(call.arguments.to_string()).await {
+                        Ok(output) => output,
+                        Err(e) => e.to_string(),
+                    };
+                    yield Ok(AgentEvent::ToolResult { id, content });
+                }
+            }
+            if let Some(usage) = script.usage {
+                yield Ok(AgentEvent::Usage(usage.into()));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ws::{ActionResult, WsMessage};
+    use crate::routes::app_router;
+    use crate::state::AppState;
+    use axum::body::{Body, to_bytes};
+    use futures::StreamExt;
+    use http::Request;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/mock_llm.json");
+
+    fn mock_state() -> Arc<AppState> {
+        let provider = MockProvider::from_json(FIXTURE).unwrap();
+        Arc::new(AppState::new(Arc::new(provider)))
+    }
+
+    /// Registers a fake extension connection that answers every action.
+    async fn fake_extension(state: &Arc<AppState>, session_id: &str) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        state.register_connection(session_id.to_string(), tx).await;
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if let WsMessage::ActionRequest { request_id, .. } = msg {
+                    let result = ActionResult {
+                        request_id: request_id.clone(),
+                        success: true,
+                        error: None,
+                        data: Some(
+                            serde_json::json!([{"id": 1, "role": "button", "name": "Login"}]),
+                        ),
+                    };
+                    state.complete_pending_action(&request_id, result).await;
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_replays_text_and_usage() {
+        let provider = MockProvider::from_json(FIXTURE).unwrap();
+        let chunks: Vec<_> = provider
+            .stream("hi", "", None, &GenerationOverrides::default())
+            .collect()
+            .await;
+        let chunks: Vec<String> = chunks.into_iter().map(Result::unwrap).collect();
+
+        assert_eq!(chunks.first().map(String::as_str), Some("Let me look. "));
+        assert!(chunks.last().unwrap().starts_with(r#"{"__type":"usage""#));
+    }
+
+    #[tokio::test]
+    async fn test_mock_agent_reports_missing_tool() {
+        let provider = MockProvider::from_json(FIXTURE).unwrap();
+        let params = AgentParams {
+            preamble: String::new(),
+            tools: vec![],
+            max_depth: 5,
+            prompt: crate::llm::user_message("hi", None),
+            history: vec![],
+            overrides: GenerationOverrides::default(),
+        };
+        let events: Vec<_> = provider.stream_agent(params).collect().await;
+        assert!(
+            matches!(events.last(), Some(Err(AppError::Provider(e))) if e.contains("ToolNotFound"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_sse_with_mock_provider() {
+        let state = mock_state();
+        fake_extension(&state, "test-session").await;
+
+        let response = app_router(state)
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/agent/run")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({"query": "click login", "session_id": "test-session"})
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("event: tool"));
+        assert!(body.contains("get_interactive_elements"));
+        assert!(body.contains("Login"));
+        assert!(body.contains("event: usage"));
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+}
@@ -1,88 +1,361 @@
 use rig::OneOrMany;
 use rig::agent::MultiTurnStreamItem;
-use rig::completion::{GetTokenUsage, Prompt};
-use rig::message::{ImageMediaType, Message, UserContent};
+use rig::completion::{CompletionModel, GetTokenUsage, Prompt};
+use rig::message::{ImageMediaType, Message, ToolResultContent, UserContent};
 use rig::prelude::*;
-use rig::providers::gemini;
-use rig::streaming::{StreamedAssistantContent, StreamingPrompt};
+use rig::streaming::{
+    StreamedAssistantContent, StreamedUserContent, StreamingChat, StreamingPrompt,
+};
+use rig::tool::ToolDyn;
 
 use async_stream::stream;
-use futures::stream::{Stream, StreamExt};
-use std::pin::Pin;
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-pub struct GeminiProvider {
-    client: gemini::Client,
+use crate::config::{AppConfig, ConfigError, LlmBackend};
+use crate::error::AppError;
+use crate::llm::mock::MockProvider;
+
+/// Token accounting reported at the end of a completion.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
 }
 
-impl GeminiProvider {
-    pub fn new(client: gemini::Client) -> Self {
-        Self { client }
+/// Model ids and generation parameters (`[llm]` config). Unset model ids
+/// fall back to the provider's default.
+#[derive(Debug, Clone, Default)]
+pub struct ModelSettings {
+    /// Model for the legacy chat path.
+    pub chat_model: Option<String>,
+    /// Model for the tool-enabled agent path.
+    pub agent_model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+    /// Extra model ids clients may request besides the two above.
+    pub allowed_models: Vec<String>,
+}
+
+impl ModelSettings {
+    /// Fills unset model ids with the ones `provider` falls back to, so
+    /// clients may request the default model by name.
+    pub fn with_provider_defaults(mut self, provider: &dyn LlmProvider) -> Self {
+        self.chat_model
+            .get_or_insert_with(|| provider.chat_model().to_string());
+        self.agent_model
+            .get_or_insert_with(|| provider.agent_model().to_string());
+        self
+    }
+
+    /// Checks per-request overrides against the model allowlist and the
+    /// configured limits.
+    pub fn check_overrides(&self, overrides: &GenerationOverrides) -> Result<(), AppError> {
+        if let Some(model) = &overrides.model {
+            let allowed = self
+                .allowed_models
+                .iter()
+                .chain(&self.chat_model)
+                .chain(&self.agent_model)
+                .any(|m| m == model);
+            if !allowed {
+                return Err(AppError::InvalidRequest(format!(
+                    "model {:?} is not allowed on this server",
+                    model
+                )));
+            }
+        }
+        if let Some(temperature) = overrides.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            return Err(AppError::InvalidRequest(
+                "temperature must be between 0.0 and 2.0".into(),
+            ));
+        }
+        match (overrides.max_tokens, self.max_tokens) {
+            (Some(0), _) => Err(AppError::InvalidRequest(
+                "max_tokens must be greater than 0".into(),
+            )),
+            (Some(requested), Some(limit)) if requested > limit => Err(AppError::InvalidRequest(
+                format!("max_tokens must not exceed {}", limit),
+            )),
+            _ => Ok(()),
+        }
     }
+}
+
+/// Per-request generation settings. Unset fields fall back to the server's
+/// [`ModelSettings`].
+#[derive(Debug, Clone, Default)]
+pub struct GenerationOverrides {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+}
+
+/// Everything a provider needs to run one tool-enabled agent request.
+pub struct AgentParams {
+    pub preamble: String,
+    pub tools: Vec<Box<dyn ToolDyn>>,
+    pub max_depth: usize,
+    pub prompt: Message,
+    pub history: Vec<Message>,
+    pub overrides: GenerationOverrides,
+}
+
+/// Provider-agnostic view of a multi-turn agent stream.
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    Text(String),
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: serde_json::Value,
+    },
+    ToolResult {
+        id: String,
+        content: String,
+    },
+    Usage(TokenUsage),
+}
+
+/// Common interface for every LLM backend.
+///
+/// The legacy chat path uses `complete`/`stream`, the tool-enabled agent path
+/// uses `stream_agent`. Implementations must not assume a particular vendor.
+pub trait LlmProvider: Send + Sync {
+    /// Short identifier used in logs (e.g. "gemini").
+    fn name(&self) -> &str;
+
+    /// Model id `complete` and `stream` use when the request doesn't pick
+    /// one.
+    fn chat_model(&self) -> &str;
+
+    /// Model id `stream_agent` uses when the request doesn't pick one.
+    fn agent_model(&self) -> &str;
+
+    /// `preamble` is the localized system prompt (see [`crate::i18n`]).
+    fn complete<'a>(
+        &'a self,
+        message: &'a str,
+        preamble: &'a str,
+        image: Option<&'a str>,
+        overrides: &'a GenerationOverrides,
+    ) -> BoxFuture<'a, Result<String, AppError>>;
 
-    pub async fn complete(
+    /// Streams text chunks. Token usage is sent as a final
+    /// `{"__type":"usage",...}` JSON chunk.
+    fn stream(
         &self,
         message: &str,
-        custom_instruction: Option<&str>,
+        preamble: &str,
         image: Option<&str>,
-    ) -> Result<String, String> {
-        let mut preamble =
-            "WAJIB: Selalu jawab dalam Bahasa Indonesia kecuali diminta lain.".to_string();
-        if let Some(instruction) = custom_instruction {
-            preamble.push_str(&format!("\n\nINSTRUKSI TAMBAHAN: {}", instruction));
+        overrides: &GenerationOverrides,
+    ) -> BoxStream<'static, Result<String, AppError>>;
+
+    fn stream_agent(&self, params: AgentParams)
+    -> BoxStream<'static, Result<AgentEvent, AppError>>;
+}
+
+/// Builds the provider selected by `llm.provider`.
+pub fn build_provider(config: &AppConfig) -> Result<Arc<dyn LlmProvider>, ConfigError> {
+    let settings = config.models.clone();
+    Ok(match config.llm_provider {
+        LlmBackend::Gemini => Arc::new(crate::llm::GeminiProvider::new(
+            rig::providers::gemini::Client::from_env(),
+            settings,
+        )),
+        LlmBackend::OpenAi => Arc::new(crate::llm::OpenAiProvider::new(
+            rig::providers::openai::Client::from_env().completions_api(),
+            settings,
+        )),
+        LlmBackend::Local => Arc::new(crate::llm::LocalProvider::new(
+            rig::providers::ollama::Client::from_env(),
+            settings,
+        )),
+        LlmBackend::Mock => {
+            let fixture = config.mock_fixture.as_ref().ok_or_else(|| {
+                ConfigError::single("llm.mock_fixture is required for the mock provider")
+            })?;
+            Arc::new(MockProvider::from_file(fixture).map_err(ConfigError::single)?)
         }
+    })
+}
+
+/// Builds a user message from text and an optional base64 image.
+pub fn user_message(message: &str, image: Option<&str>) -> Message {
+    let mut parts = vec![UserContent::text(message.to_string())];
+
+    if let Some(img_data) = image {
+        let (media_type, data) = parse_image_data(img_data);
+        parts.push(UserContent::image_base64(
+            data.to_string(),
+            Some(media_type),
+            None,
+        ));
+    }
 
-        let agent = self
-            .client
-            .agent(gemini::completion::GEMINI_2_5_FLASH)
-            .preamble(&preamble)
-            .build();
+    Message::User {
+        content: OneOrMany::many(parts).expect("Parts list is not empty"),
+    }
+}
 
-        let mut parts = vec![UserContent::text(message.to_string())];
+pub(crate) fn usage_json(usage: &TokenUsage) -> String {
+    format!(
+        r#"{{"__type":"usage","input_tokens":{},"output_tokens":{},"total_tokens":{}}}"#,
+        usage.input_tokens, usage.output_tokens, usage.total_tokens
+    )
+}
 
-        if let Some(img_data) = image {
-            let (media_type, data) = parse_image_data(img_data);
-            parts.push(UserContent::image_base64(data, Some(media_type), None));
+/// `LlmProvider` backed by any rig completion client.
+///
+/// Gemini, OpenAI-compatible and local (Ollama) backends only differ in the
+/// client type and the default model id.
+pub struct RigProvider<C> {
+    client: C,
+    chat_model: String,
+    agent_model: String,
+    temperature: Option<f64>,
+    max_tokens: Option<u64>,
+    name: &'static str,
+}
+
+impl<C> RigProvider<C> {
+    fn with_settings(
+        client: C,
+        settings: ModelSettings,
+        default_model: &str,
+        name: &'static str,
+    ) -> Self {
+        let chat_model = settings
+            .chat_model
+            .unwrap_or_else(|| default_model.to_string());
+        RigProvider {
+            client,
+            agent_model: settings.agent_model.unwrap_or_else(|| chat_model.clone()),
+            chat_model,
+            temperature: settings.temperature,
+            max_tokens: settings.max_tokens,
+            name,
         }
+    }
 
-        let prompt = Message::User {
-            content: OneOrMany::many(parts).expect("Parts list is not empty"),
-        };
+    /// Applies `overrides` on top of the configured model and parameters.
+    fn resolve(
+        &self,
+        default_model: &str,
+        overrides: &GenerationOverrides,
+    ) -> (String, Option<f64>, Option<u64>) {
+        (
+            overrides
+                .model
+                .clone()
+                .unwrap_or_else(|| default_model.to_string()),
+            overrides.temperature.or(self.temperature),
+            overrides.max_tokens.or(self.max_tokens),
+        )
+    }
+}
+
+pub type GeminiProvider = RigProvider<rig::providers::gemini::Client>;
+pub type OpenAiProvider = RigProvider<rig::providers::openai::CompletionsClient>;
+pub type LocalProvider = RigProvider<rig::providers::ollama::Client>;
+
+impl GeminiProvider {
+    pub fn new(client: rig::providers::gemini::Client, settings: ModelSettings) -> Self {
+        Self::with_settings(
+            client,
+            settings,
+            rig::providers::gemini::completion::GEMINI_2_5_FLASH,
+            "gemini",
+        )
+    }
+}
+
+impl OpenAiProvider {
+    pub fn new(client: rig::providers::openai::CompletionsClient, settings: ModelSettings) -> Self {
+        Self::with_settings(
+            client,
+            settings,
+            rig::providers::openai::GPT_4O_MINI,
+            "openai",
+        )
+    }
+}
+
+impl LocalProvider {
+    pub fn new(client: rig::providers::ollama::Client, settings: ModelSettings) -> Self {
+        Self::with_settings(client, settings, "llama3.1", "local")
+    }
+}
+
+impl<C> LlmProvider for RigProvider<C>
+where
+    C: CompletionClient + Clone + Send + Sync + 'static,
+    C::CompletionModel: 'static,
+    <C::CompletionModel as CompletionModel>::StreamingResponse: GetTokenUsage,
+{
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn chat_model(&self) -> &str {
+        &self.chat_model
+    }
+
+    fn agent_model(&self) -> &str {
+        &self.agent_model
+    }
+
+    fn complete<'a>(
+        &'a self,
+        message: &'a str,
+        preamble: &'a str,
+        image: Option<&'a str>,
+        overrides: &'a GenerationOverrides,
+    ) -> BoxFuture<'a, Result<String, AppError>> {
+        Box::pin(async move {
+            let (model, temperature, max_tokens) = self.resolve(&self.chat_model, overrides);
+            let mut builder = self.client.agent(&model).preamble(preamble);
+            if let Some(temperature) = temperature {
+                builder = builder.temperature(temperature);
+            }
+            if let Some(max_tokens) = max_tokens {
+                builder = builder.max_tokens(max_tokens);
+            }
+            let agent = builder.build();
 
-        agent.prompt(prompt).await.map_err(|e| e.to_string())
+            agent
+                .prompt(user_message(message, image))
+                .await
+                .map_err(|e| AppError::from_provider(e.to_string()))
+        })
     }
 
-    pub fn stream(
+    fn stream(
         &self,
         message: &str,
-        custom_instruction: Option<&str>,
+        preamble: &str,
         image: Option<&str>,
-    ) -> Pin<Box<dyn Stream<Item = Result<String, String>> + Send + 'static>> {
-        let mut preamble =
-            "WAJIB: Selalu jawab dalam Bahasa Indonesia kecuali diminta lain.".to_string();
-        if let Some(instruction) = custom_instruction {
-            preamble.push_str(&format!("\n\nINSTRUKSI TAMBAHAN: {}", instruction));
-        }
-
+        overrides: &GenerationOverrides,
+    ) -> BoxStream<'static, Result<String, AppError>> {
+        let preamble = preamble.to_string();
         let client = self.client.clone();
-        let message = message.to_string();
-        let image = image.map(|s| s.to_string());
+        let (model, temperature, max_tokens) = self.resolve(&self.chat_model, overrides);
+        let prompt = user_message(message, image);
 
         Box::pin(stream! {
-            let agent = client
-                .agent(gemini::completion::GEMINI_2_5_FLASH)
-                .preamble(&preamble)
-                .build();
-
-            let mut parts = vec![UserContent::text(message)];
-
-            if let Some(img_data) = image {
-                let (media_type, data) = parse_image_data(&img_data);
-                parts.push(UserContent::image_base64(data.to_string(), Some(media_type), None));
+            let mut builder = client.agent(&model).preamble(&preamble);
+            if let Some(temperature) = temperature {
+                builder = builder.temperature(temperature);
             }
-
-            let prompt = Message::User {
-                content: OneOrMany::many(parts).expect("Parts list is not empty"),
-            };
+            if let Some(max_tokens) = max_tokens {
+                builder = builder.max_tokens(max_tokens);
+            }
+            let agent = builder.build();
 
             let mut rig_stream = agent.stream_prompt(prompt).await;
 
@@ -92,7 +365,7 @@ impl GeminiProvider {
                 tracing::debug!("Stream chunk #{}: {:?}", chunk_count, std::any::type_name_of_val(&chunk));
                 match chunk {
                     Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text))) => {
-                        yield Ok::<String, String>(text.text);
+                        yield Ok::<String, AppError>(text.text);
                     }
                     Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Final(final_resp))) => {
                         // Send token usage as special JSON marker at end of stream
@@ -100,11 +373,11 @@ impl GeminiProvider {
                         tracing::info!("Got StreamedAssistantContent::Final");
                         if let Some(usage) = final_resp.token_usage() {
                             tracing::info!("Token usage: in={}, out={}, total={}", usage.input_tokens, usage.output_tokens, usage.total_tokens);
-                            let usage_json = format!(
-                                r#"{{"__type":"usage","input_tokens":{},"output_tokens":{},"total_tokens":{}}}"#,
-                                usage.input_tokens, usage.output_tokens, usage.total_tokens
-                            );
-                            yield Ok::<String, String>(usage_json);
+                            yield Ok::<String, AppError>(usage_json(&TokenUsage {
+                                input_tokens: usage.input_tokens,
+                                output_tokens: usage.output_tokens,
+                                total_tokens: usage.total_tokens,
+                            }));
                         } else {
                             tracing::warn!("Final response has no token usage");
                         }
@@ -113,21 +386,94 @@ impl GeminiProvider {
                         // This is from multi-turn agent with tools - also has usage
                         tracing::info!("Got MultiTurnStreamItem::FinalResponse");
                         let usage = final_resp.usage();
-                        let usage_json = format!(
-                            r#"{{"__type":"usage","input_tokens":{},"output_tokens":{},"total_tokens":{}}}"#,
-                            usage.input_tokens, usage.output_tokens, usage.total_tokens
-                        );
-                        yield Ok::<String, String>(usage_json);
+                        yield Ok::<String, AppError>(usage_json(&TokenUsage {
+                            input_tokens: usage.input_tokens,
+                            output_tokens: usage.output_tokens,
+                            total_tokens: usage.total_tokens,
+                        }));
                     }
                     Ok(other) => {
                         tracing::debug!("Got other stream item: {:?}", std::any::type_name_of_val(&other));
                     }
-                    Err(e) => yield Err::<String, String>(e.to_string()),
+                    Err(e) => yield Err::<String, AppError>(AppError::from_provider(e.to_string())),
                 }
             }
             tracing::info!("Stream ended after {} chunks", chunk_count);
         })
     }
+
+    fn stream_agent(
+        &self,
+        params: AgentParams,
+    ) -> BoxStream<'static, Result<AgentEvent, AppError>> {
+        let client = self.client.clone();
+        let (model, temperature, max_tokens) = self.resolve(&self.agent_model, &params.overrides);
+
+        Box::pin(stream! {
+            let mut builder = client
+                .agent(&model)
+                .preamble(&params.preamble)
+                .tools(params.tools)
+                .default_max_depth(params.max_depth);
+            if let Some(temperature) = temperature {
+                builder = builder.temperature(temperature);
+            }
+            if let Some(max_tokens) = max_tokens {
+                builder = builder.max_tokens(max_tokens);
+            }
+            let agent = builder.build();
+
+            let mut agent_stream = agent.stream_chat(params.prompt, params.history).await;
+
+            while let Some(chunk) = agent_stream.next().await {
+                match chunk {
+                    Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text))) => {
+                        yield Ok(AgentEvent::Text(text.text));
+                    }
+                    Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::ToolCall(tool_call))) => {
+                        yield Ok(AgentEvent::ToolCall {
+                            id: tool_call.id,
+                            name: tool_call.function.name,
+                            arguments: tool_call.function.arguments,
+                        });
+                    }
+                    Ok(MultiTurnStreamItem::StreamUserItem(StreamedUserContent::ToolResult(tool_result))) => {
+                        let content = tool_result
+                            .content
+                            .iter()
+                            .filter_map(|c| match c {
+                                ToolResultContent::Text(text) => Some(text.text.clone()),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                        yield Ok(AgentEvent::ToolResult { id: tool_result.id, content });
+                    }
+                    Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Final(final_resp))) => {
+                        if let Some(usage) = final_resp.token_usage() {
+                            yield Ok(AgentEvent::Usage(TokenUsage {
+                                input_tokens: usage.input_tokens,
+                                output_tokens: usage.output_tokens,
+                                total_tokens: usage.total_tokens,
+                            }));
+                        }
+                    }
+                    Ok(MultiTurnStreamItem::FinalResponse(final_resp)) => {
+                        let usage = final_resp.usage();
+                        yield Ok(AgentEvent::Usage(TokenUsage {
+                            input_tokens: usage.input_tokens,
+                            output_tokens: usage.output_tokens,
+                            total_tokens: usage.total_tokens,
+                        }));
+                    }
+                    Ok(_) => {
+                        // Other variants (Reasoning, etc.)
+                    }
+                    Err(e) => yield Err(AppError::from_provider(e.to_string())),
+                }
+            }
+        })
+    }
 }
 
 pub fn parse_image_data(img_data: &str) -> (ImageMediaType, &str) {
@@ -143,3 +489,90 @@ pub fn parse_image_data(img_data: &str) -> (ImageMediaType, &str) {
         (ImageMediaType::JPEG, img_data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ModelSettings {
+        ModelSettings {
+            chat_model: Some("flash".into()),
+            agent_model: Some("pro".into()),
+            max_tokens: Some(1000),
+            allowed_models: vec!["lite".into()],
+            ..Default::default()
+        }
+    }
+
+    fn model(name: &str) -> GenerationOverrides {
+        GenerationOverrides {
+            model: Some(name.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_model_allowlist() {
+        let settings = settings();
+        for allowed in ["flash", "pro", "lite"] {
+            assert!(settings.check_overrides(&model(allowed)).is_ok());
+        }
+        assert!(matches!(
+            settings.check_overrides(&model("ultra")),
+            Err(AppError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_parameter_limits() {
+        let settings = settings();
+        let with = |temperature, max_tokens| GenerationOverrides {
+            model: None,
+            temperature,
+            max_tokens,
+        };
+        assert!(
+            settings
+                .check_overrides(&with(Some(0.3), Some(500)))
+                .is_ok()
+        );
+        assert!(settings.check_overrides(&with(Some(2.5), None)).is_err());
+        assert!(settings.check_overrides(&with(None, Some(0))).is_err());
+        assert!(settings.check_overrides(&with(None, Some(2000))).is_err());
+    }
+
+    #[test]
+    fn test_agent_model_falls_back_to_chat_then_default() {
+        let client = || rig::providers::ollama::Client::from_val(rig::client::Nothing);
+        assert_eq!(
+            LocalProvider::new(client(), settings()).agent_model(),
+            "pro"
+        );
+
+        let chat_only = ModelSettings {
+            agent_model: None,
+            ..settings()
+        };
+        assert_eq!(
+            LocalProvider::new(client(), chat_only).agent_model(),
+            "flash"
+        );
+        let provider = LocalProvider::new(client(), ModelSettings::default());
+        assert_eq!(provider.agent_model(), "llama3.1");
+    }
+
+    #[test]
+    fn test_provider_default_model_is_allowed() {
+        let provider = LocalProvider::new(
+            rig::providers::ollama::Client::from_val(rig::client::Nothing),
+            ModelSettings::default(),
+        );
+        let defaults = ModelSettings::default().with_provider_defaults(&provider);
+        assert!(defaults.check_overrides(&model("llama3.1")).is_ok());
+        assert!(defaults.check_overrides(&model("llama3.2")).is_err());
+
+        // Configured models stay as they are
+        let configured = settings().with_provider_defaults(&provider);
+        assert_eq!(configured.agent_model.as_deref(), Some("pro"));
+    }
+}
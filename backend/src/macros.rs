@@ -0,0 +1,357 @@
+//! Named macros: saved `ActionCommand` sequences.
+//!
+//! A macro comes from the user's own browsing in record mode (the extension
+//! streams `user_action` messages between `record_start` and `record_stop`),
+//! from a trace (`PUT /macros/{name}` with a `run_id`) or from an explicit
+//! command list. The agent runs them with the `run_macro` tool, and
+//! `POST /sessions/{id}/replay` can step through them.
+//!
+//! `{{param}}` placeholders in typed text and navigation URLs are filled in
+//! from the caller's parameters when the macro runs.
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::LazyLock;
+
+use crate::error::AppError;
+use crate::models::ws::{ActionCommand, UserAction};
+use crate::replay::MAX_REPLAY_STEPS;
+
+/// Longest macro name, in characters.
+pub const MAX_MACRO_NAME_CHARS: usize = 64;
+/// Longest macro description, in characters.
+pub const MAX_MACRO_DESCRIPTION_CHARS: usize = 500;
+/// Most steps one macro may hold, the same as one replay.
+pub const MAX_MACRO_STEPS: usize = MAX_REPLAY_STEPS;
+/// Parameter name for a sensitive field without a usable name.
+const DEFAULT_SECRET_PARAM: &str = "secret";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Placeholders the steps use, sorted.
+    pub params: Vec<String>,
+    pub steps: Vec<ActionCommand>,
+    /// Unix milliseconds.
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_MACRO_NAME_CHARS
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::InvalidRequest(format!(
+            "macro name must have 1 to {} letters, digits, '_' or '-'",
+            MAX_MACRO_NAME_CHARS
+        )))
+    }
+}
+
+pub fn validate(
+    name: &str,
+    description: Option<&str>,
+    steps: &[ActionCommand],
+) -> Result<(), AppError> {
+    validate_name(name)?;
+    if description.is_some_and(|d| d.chars().count() > MAX_MACRO_DESCRIPTION_CHARS) {
+        return Err(AppError::InvalidRequest(format!(
+            "macro description is longer than {} characters",
+            MAX_MACRO_DESCRIPTION_CHARS
+        )));
+    }
+    if steps.is_empty() || steps.len() > MAX_MACRO_STEPS {
+        return Err(AppError::InvalidRequest(format!(
+            "a macro needs 1 to {} steps",
+            MAX_MACRO_STEPS
+        )));
+    }
+    Ok(())
+}
+
+/// Text fields of a command that may hold placeholders.
+fn templated(command: &ActionCommand) -> Option<&str> {
+    match command {
+        ActionCommand::NavigateTo { url } => Some(url),
+        ActionCommand::TypeText { text, .. } => Some(text),
+        _ => None,
+    }
+}
+
+/// Names of the `{{name}}` placeholders in `text`, in order.
+fn placeholders(text: &str) -> impl Iterator<Item = &str> {
+    text.split("{{").skip(1).filter_map(|rest| {
+        let (name, _) = rest.split_once("}}")?;
+        (!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+            .then_some(name)
+    })
+}
+
+/// Every placeholder the steps use, sorted and deduplicated.
+pub fn params(steps: &[ActionCommand]) -> Vec<String> {
+    steps
+        .iter()
+        .filter_map(templated)
+        .flat_map(placeholders)
+        .map(str::to_string)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{([A-Za-z0-9_]+)\}\}").expect("valid placeholder regex"));
+
+/// Fills placeholders in one pass, so a value that itself looks like a
+/// placeholder is left as typed.
+fn fill(text: &str, values: &HashMap<String, String>) -> String {
+    PLACEHOLDER
+        .replace_all(text, |caps: &Captures| match values.get(&caps[1]) {
+            Some(value) => value.clone(),
+            None => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// The macro's steps with every placeholder filled in from `values`.
+pub fn instantiate(
+    macro_: &Macro,
+    values: &HashMap<String, String>,
+) -> Result<Vec<ActionCommand>, AppError> {
+    let missing: Vec<&str> = macro_
+        .params
+        .iter()
+        .filter(|p| !values.contains_key(*p))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(AppError::InvalidRequest(format!(
+            "macro {} needs parameters: {}",
+            macro_.name,
+            missing.join(", ")
+        )));
+    }
+    Ok(macro_
+        .steps
+        .iter()
+        .map(|step| match step {
+            ActionCommand::NavigateTo { url } => ActionCommand::NavigateTo {
+                url: fill(url, values),
+            },
+            ActionCommand::TypeText { ref_id, text } => ActionCommand::TypeText {
+                ref_id: *ref_id,
+                text: fill(text, values),
+            },
+            other => other.clone(),
+        })
+        .collect())
+}
+
+/// Parameter name for a sensitive field: its name in snake case.
+fn secret_param(name: Option<&str>) -> String {
+    let slug = name
+        .unwrap_or_default()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("_");
+    if slug.is_empty() {
+        DEFAULT_SECRET_PARAM.to_string()
+    } else {
+        slug
+    }
+}
+
+/// A recording in progress on one session.
+///
+/// User actions are turned into the commands the agent would have sent:
+/// every click, and typing that doesn't follow other typing, is preceded by
+/// `get_interactive_elements` so the recorded refs are re-read the same way
+/// when the macro runs. Repeated typing into one field keeps the last text.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub steps: Vec<ActionCommand>,
+    /// Refs of the last element snapshot still hold.
+    elements_fresh: bool,
+}
+
+impl Recording {
+    /// Starts on `page_url`, if known, so the macro opens the same page.
+    pub fn new(page_url: Option<&str>) -> Self {
+        let mut recording = Self::default();
+        if let Some(url) = page_url {
+            recording.navigate(url.to_string());
+        }
+        recording
+    }
+
+    fn navigate(&mut self, url: String) {
+        self.elements_fresh = false;
+        if matches!(self.steps.last(), Some(ActionCommand::NavigateTo { url: last }) if *last == url)
+        {
+            return;
+        }
+        self.steps.push(ActionCommand::NavigateTo { url });
+    }
+
+    fn refresh_elements(&mut self) {
+        if !self.elements_fresh {
+            self.steps
+                .push(ActionCommand::GetInteractiveElements { limit: None });
+            self.elements_fresh = true;
+        }
+    }
+
+    pub fn push(&mut self, action: UserAction) -> Result<(), AppError> {
+        if self.steps.len() >= MAX_MACRO_STEPS {
+            return Err(AppError::InvalidRequest(format!(
+                "a macro holds at most {} steps",
+                MAX_MACRO_STEPS
+            )));
+        }
+        match action {
+            UserAction::Navigate { url } => self.navigate(url),
+            UserAction::Click { ref_id } => {
+                self.refresh_elements();
+                self.steps.push(ActionCommand::ClickElement { ref_id });
+                // The click may have changed the page
+                self.elements_fresh = false;
+            }
+            UserAction::Type {
+                ref_id,
+                text,
+                sensitive,
+                name,
+            } => {
+                let text = if sensitive {
+                    format!("{{{{{}}}}}", secret_param(name.as_deref()))
+                } else {
+                    text
+                };
+                if let Some(ActionCommand::TypeText {
+                    ref_id: last,
+                    text: last_text,
+                }) = self.steps.last_mut()
+                    && *last == ref_id
+                {
+                    *last_text = text;
+                    return Ok(());
+                }
+                self.refresh_elements();
+                self.steps.push(ActionCommand::TypeText { ref_id, text });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_normalizes_user_actions() {
+        let mut recording = Recording::new(Some("https://example.com/"));
+        let actions = [
+            UserAction::Click { ref_id: 2 },
+            UserAction::Type {
+                ref_id: 1,
+                text: "qa@exa".into(),
+                sensitive: false,
+                name: Some("Email".into()),
+            },
+            UserAction::Type {
+                ref_id: 1,
+                text: "qa@example.com".into(),
+                sensitive: false,
+                name: Some("Email".into()),
+            },
+            UserAction::Type {
+                ref_id: 3,
+                text: String::new(),
+                sensitive: true,
+                name: Some("Account password".into()),
+            },
+            UserAction::Navigate {
+                url: "https://example.com/".into(),
+            },
+            UserAction::Navigate {
+                url: "https://example.com/".into(),
+            },
+        ];
+        for action in actions {
+            recording.push(action).unwrap();
+        }
+
+        let steps = serde_json::to_value(&recording.steps).unwrap();
+        assert_eq!(
+            steps,
+            serde_json::json!([
+                {"type": "navigate_to", "url": "https://example.com/"},
+                {"type": "get_interactive_elements", "limit": null},
+                {"type": "click_element", "ref": 2},
+                {"type": "get_interactive_elements", "limit": null},
+                {"type": "type_text", "ref": 1, "text": "qa@example.com"},
+                {"type": "type_text", "ref": 3, "text": "{{account_password}}"},
+                {"type": "navigate_to", "url": "https://example.com/"},
+            ])
+        );
+        assert_eq!(params(&recording.steps), ["account_password"]);
+    }
+
+    #[test]
+    fn test_instantiate_fills_placeholders() {
+        let macro_ = Macro {
+            name: "search".into(),
+            description: None,
+            params: vec!["query".into()],
+            steps: vec![
+                ActionCommand::NavigateTo {
+                    url: "https://example.com/?q={{query}}".into(),
+                },
+                ActionCommand::TypeText {
+                    ref_id: 1,
+                    text: "{{ query }} and {{query}}".into(),
+                },
+            ],
+            created_at: 0,
+            updated_at: 0,
+        };
+        assert!(instantiate(&macro_, &HashMap::new()).is_err());
+
+        let values = HashMap::from([("query".to_string(), "rust".to_string())]);
+        let steps = instantiate(&macro_, &values).unwrap();
+        assert!(matches!(
+            &steps[0],
+            ActionCommand::NavigateTo { url } if url == "https://example.com/?q=rust"
+        ));
+        // Only the exact `{{name}}` spelling is replaced
+        assert!(matches!(
+            &steps[1],
+            ActionCommand::TypeText { text, .. } if text == "{{ query }} and rust"
+        ));
+        assert!(validate_name("log in").is_err());
+        assert!(validate_name("log-in_2").is_ok());
+    }
+
+    #[test]
+    fn test_filled_values_are_not_filled_again() {
+        let values = HashMap::from([
+            ("query".to_string(), "{{password}}".to_string()),
+            ("password".to_string(), "hunter2".to_string()),
+        ]);
+        assert_eq!(
+            fill("{{query}} / {{password}}", &values),
+            "{{password}} / hunter2"
+        );
+    }
+}
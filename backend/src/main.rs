@@ -1,119 +1,119 @@
-use std::net::SocketAddr;
+use clap::Parser;
+use std::process::ExitCode;
 use std::sync::Arc;
 
-mod agent;
-mod config;
-mod dtos;
-mod error;
-mod handler;
-mod llm;
-mod models;
-mod routes;
-mod state;
-mod tools;
-mod utils;
-
-use crate::state::AppState;
+use backend_rig::config::{AppConfig, Cli, LogFormat};
+use backend_rig::state::AppState;
+use backend_rig::store::{ConversationStore, InstructionStore, MacroStore, TraceStore};
+use backend_rig::{llm, routes};
 
 #[tokio::main]
-async fn main() {
-    // Load config
-    let config = config::AppConfig::from_env();
+async fn main() -> ExitCode {
+    // Load config: defaults < config file < env < flags
+    let cli = Cli::parse();
+    let config = match AppConfig::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
     // Initialize tracing
-    tracing_subscriber::fmt::init();
+    match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().init(),
+    }
+    if !config.auth.is_enabled() {
+        tracing::warn!("Authentication is disabled: set API_KEYS or AUTH_TOKEN_SECRET");
+    }
 
     // Create shared state
-    let state = Arc::new(AppState::new());
+    let llm = match llm::build_provider(&config) {
+        Ok(llm) => llm,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing::info!("Using LLM provider: {}", llm.name());
+    let conversations = match ConversationStore::open(&config.conversations_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!(
+                "Failed to open conversation store {}: {}",
+                config.conversations_dir.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let instructions = match InstructionStore::open(&config.instructions_store) {
+        Ok(store) => store.with_workspace(config.workspace_instructions.clone()),
+        Err(e) => {
+            tracing::error!(
+                "Failed to open instruction store {}: {}",
+                config.instructions_store.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let traces = match TraceStore::open(&config.traces_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!(
+                "Failed to open trace store {}: {}",
+                config.traces_dir.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let macros = match MacroStore::open(&config.macros_store) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!(
+                "Failed to open macro store {}: {}",
+                config.macros_store.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let state = Arc::new(
+        AppState::new(llm)
+            .with_conversation_store(conversations)
+            .with_instruction_store(instructions)
+            .with_trace_store(traces)
+            .with_macro_store(macros)
+            .with_resume_grace(config.resume_grace)
+            .with_agent_limits(config.agent_limits)
+            .with_task_limits(config.task_limits)
+            .with_model_settings(config.models.clone())
+            .with_approval_policy(config.approval_policy.clone())
+            .with_navigation_policy(config.navigation_policy.clone())
+            .with_auth(config.auth.clone())
+            .with_cors_origins(config.cors_origins.clone())
+            .with_locales(config.locales),
+    );
+    state.locales.clone().watch(config.locales_reload);
 
     // Build the router
     let app = routes::app_router(state);
 
-    // Bind to port
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
-    tracing::info!("Server running on http://{}", addr);
-
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::llm::parse_image_data;
-    use crate::models::{ChatRequest, ChatResponse, HealthResponse};
-    use rig::message::ImageMediaType;
-
-    #[test]
-    fn test_health_response_serialize() {
-        let resp = HealthResponse {
-            status: "ok".to_string(),
-        };
-        let json = serde_json::to_string(&resp).unwrap();
-        assert_eq!(json, r#"{"status":"ok"}"#);
-    }
-
-    #[test]
-    fn test_chat_request_deserialize() {
-        let json = r#"{
-            "message": "Hello",
-            "custom_instruction": "Be concise",
-            "image": "base64data"
-        }"#;
-        let req: ChatRequest = serde_json::from_str(json).unwrap();
-        assert_eq!(req.message, "Hello");
-        assert_eq!(req.custom_instruction, Some("Be concise".to_string()));
-        assert_eq!(req.image, Some("base64data".to_string()));
-    }
-
-    #[test]
-    fn test_chat_response_serialize() {
-        let resp = ChatResponse {
-            response: "Hi".to_string(),
-            prompt_tokens: None,
-            response_tokens: None,
-            total_tokens: None,
-        };
-        let json = serde_json::to_string(&resp).unwrap();
-        // Should not contain tokens since they are None and marked with skip_serializing_if
-        assert_eq!(json, r#"{"response":"Hi"}"#);
-
-        let resp_with_tokens = ChatResponse {
-            response: "Hi".to_string(),
-            prompt_tokens: Some(10),
-            response_tokens: Some(20),
-            total_tokens: Some(30),
-        };
-        let json_with_tokens = serde_json::to_string(&resp_with_tokens).unwrap();
-        assert!(json_with_tokens.contains(r#""prompt_tokens":10"#));
-        assert!(json_with_tokens.contains(r#""response_tokens":20"#));
-        assert!(json_with_tokens.contains(r#""total_tokens":30"#));
-    }
-
-    #[test]
-    fn test_base64_prefix_stripping() {
-        let png = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAA...";
-        let (media_type, data) = parse_image_data(png);
-        assert!(matches!(media_type, ImageMediaType::PNG));
-        assert_eq!(data, "iVBORw0KGgoAAAANSUhEUgAA...");
-
-        let jpeg = "data:image/jpeg;base64,/9j/4AAQSkZJRgABAQAAAQABAAD...";
-        let (media_type, data) = parse_image_data(jpeg);
-        assert!(matches!(media_type, ImageMediaType::JPEG));
-        assert_eq!(data, "/9j/4AAQSkZJRgABAQAAAQABAAD...");
-
-        let webp = "data:image/webp;base64,UklGRtAAAABXRUJQVlA4...";
-        let (media_type, data) = parse_image_data(webp);
-        assert!(matches!(media_type, ImageMediaType::WEBP));
-        assert_eq!(data, "UklGRtAAAABXRUJQVlA4...");
-
-        let unknown_with_comma = "image/tiff,somebase64data";
-        let (media_type, data) = parse_image_data(unknown_with_comma);
-        assert!(matches!(media_type, ImageMediaType::JPEG));
-        assert_eq!(data, "somebase64data");
-
-        let raw_data = "somebase64datawithoutcomma";
-        let (media_type, data) = parse_image_data(raw_data);
-        assert!(matches!(media_type, ImageMediaType::JPEG));
-        assert_eq!(data, "somebase64datawithoutcomma");
+    let listener = match tokio::net::TcpListener::bind(config.bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to bind {}: {}", config.bind, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing::info!("Server running on http://{}", config.bind);
+
+    if let Err(e) = axum::serve(listener, app).await {
+        tracing::error!("Server error: {}", e);
+        return ExitCode::FAILURE;
     }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dtos::agent::ChatMessageDto;
+
+/// A stored chat conversation, optionally bound to a browser session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    /// Principal that started the conversation.
+    #[serde(default)]
+    pub principal: String,
+    pub session_id: Option<String>,
+    pub title: String,
+    /// Conversation this one was forked from.
+    pub forked_from: Option<String>,
+    /// Unix timestamps in milliseconds.
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<StoredMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub role: String,
+    pub content: String,
+    pub created_at: u64,
+}
+
+impl Conversation {
+    /// Stored turns in the shape clients send as `history`.
+    pub fn history(&self) -> Vec<ChatMessageDto> {
+        self.messages
+            .iter()
+            .map(|m| ChatMessageDto {
+                role: m.role.clone(),
+                content: m.content.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Listing entry without the message bodies.
+#[derive(Debug, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub principal: String,
+    pub session_id: Option<String>,
+    pub title: String,
+    pub forked_from: Option<String>,
+    pub message_count: usize,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl From<&Conversation> for ConversationSummary {
+    fn from(c: &Conversation) -> Self {
+        Self {
+            id: c.id.clone(),
+            principal: c.principal.clone(),
+            session_id: c.session_id.clone(),
+            title: c.title.clone(),
+            forked_from: c.forked_from.clone(),
+            message_count: c.messages.len(),
+            created_at: c.created_at,
+            updated_at: c.updated_at,
+        }
+    }
+}
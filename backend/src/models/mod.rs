@@ -1,4 +1,6 @@
 pub mod chat;
+pub mod conversation;
+pub mod trace;
 pub mod ws;
 
 pub use chat::ChatResponse;
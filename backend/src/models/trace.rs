@@ -0,0 +1,196 @@
+//! Lines of a run trace (`<traces.dir>/<run_id>.jsonl`).
+
+use rig::tool::Tool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::llm::TokenUsage;
+use crate::models::ws::{ActionCommand, ActionResult};
+use crate::session::PageSnapshot;
+use crate::tools::browser::TypeTool;
+
+/// Stands in for text typed into password-like fields.
+pub const REDACTED_TEXT: &str = "[redacted]";
+
+/// One JSONL line: when it happened and what.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceLine {
+    /// Unix milliseconds.
+    pub at: u64,
+    #[serde(flatten)]
+    pub record: TraceRecord,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceRecord {
+    /// First line of every trace.
+    RunStarted {
+        run_id: String,
+        session_id: String,
+        principal: String,
+        query: String,
+        model: String,
+        /// Rendered system prompt.
+        preamble: String,
+        page_url: Option<String>,
+    },
+    /// Model text up to the next tool call (or the end).
+    ModelText {
+        text: String,
+    },
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: Value,
+    },
+    /// What the model got back from a tool.
+    ToolResult {
+        id: String,
+        name: String,
+        output: String,
+        duration_ms: u64,
+    },
+    /// Sent to the extension.
+    ActionRequest {
+        request_id: String,
+        command: ActionCommand,
+    },
+    /// Answer from the extension, or the timeout/disconnect in its place.
+    ActionResult {
+        #[serde(flatten)]
+        result: ActionResult,
+        duration_ms: u64,
+    },
+    Usage(TokenUsage),
+    Error {
+        code: String,
+        message: String,
+    },
+    Cancelled {
+        reason: String,
+    },
+    /// Last line of a run that ended on its own or was cancelled.
+    RunFinished {
+        duration_ms: u64,
+    },
+}
+
+impl TraceRecord {
+    /// An `ActionRequest`; text typed into a field `page` doesn't show to
+    /// be an ordinary one is redacted.
+    pub fn action_request(request_id: String, command: ActionCommand, page: &PageSnapshot) -> Self {
+        let command = match command {
+            ActionCommand::TypeText { ref_id, .. } if page.may_be_password_field(ref_id) => {
+                ActionCommand::TypeText {
+                    ref_id,
+                    text: REDACTED_TEXT.to_string(),
+                }
+            }
+            command => command,
+        };
+        TraceRecord::ActionRequest {
+            request_id,
+            command,
+        }
+    }
+
+    /// A `ToolCall`, with the same redaction for `type_text` arguments.
+    pub fn tool_call(id: String, name: String, mut arguments: Value, page: &PageSnapshot) -> Self {
+        let typed_into = arguments.get("ref").and_then(Value::as_i64);
+        if name == TypeTool::NAME
+            && let Some(ref_id) = typed_into
+            && page.may_be_password_field(ref_id as i32)
+            && let Some(text) = arguments.get_mut("text")
+        {
+            *text = Value::String(REDACTED_TEXT.to_string());
+        }
+        TraceRecord::ToolCall {
+            id,
+            name,
+            arguments,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::output::InteractiveElement;
+
+    #[test]
+    fn test_password_input_is_redacted() {
+        let field = |id, name: &str, input_type: &str| InteractiveElement {
+            id,
+            role: "textbox".into(),
+            name: name.into(),
+            tag: "INPUT".into(),
+            input_type: Some(input_type.into()),
+            bounds: None,
+        };
+        let page = PageSnapshot {
+            elements: vec![field(2, "Secret", "password"), field(3, "Search", "text")],
+            ..Default::default()
+        };
+        let typing = |ref_id| ActionCommand::TypeText {
+            ref_id,
+            text: "hunter2".into(),
+        };
+
+        let record = TraceRecord::action_request("r1".into(), typing(2), &page);
+        assert!(matches!(
+            record,
+            TraceRecord::ActionRequest { command: ActionCommand::TypeText { text, .. }, .. }
+                if text == REDACTED_TEXT
+        ));
+        let record = TraceRecord::action_request("r2".into(), typing(3), &page);
+        assert!(matches!(
+            record,
+            TraceRecord::ActionRequest { command: ActionCommand::TypeText { text, .. }, .. }
+                if text == "hunter2"
+        ));
+        // Refs missing from the snapshot (e.g. after a click) may be secret
+        let record = TraceRecord::action_request("r3".into(), typing(4), &page);
+        assert!(matches!(
+            record,
+            TraceRecord::ActionRequest { command: ActionCommand::TypeText { text, .. }, .. }
+                if text == REDACTED_TEXT
+        ));
+
+        let arguments = serde_json::json!({"ref": 2, "text": "hunter2"});
+        let record = TraceRecord::tool_call("c1".into(), "type_text".into(), arguments, &page);
+        let TraceRecord::ToolCall { arguments, .. } = record else {
+            unreachable!();
+        };
+        assert_eq!(arguments["text"], REDACTED_TEXT);
+    }
+
+    #[test]
+    fn test_trace_line_round_trip() {
+        let line = TraceLine {
+            at: 7,
+            record: TraceRecord::ActionResult {
+                result: ActionResult {
+                    request_id: "r1".into(),
+                    success: true,
+                    error: None,
+                    data: Some(serde_json::json!({"clicked": 1})),
+                },
+                duration_ms: 12,
+            },
+        };
+        let json = serde_json::to_string(&line).unwrap();
+        assert_eq!(
+            json,
+            r#"{"at":7,"type":"action_result","request_id":"r1","success":true,"error":null,"data":{"clicked":1},"duration_ms":12}"#
+        );
+        let parsed: TraceLine = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            parsed.record,
+            TraceRecord::ActionResult {
+                duration_ms: 12,
+                ..
+            }
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::approval::ApprovalDecision;
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "type", content = "data")]
 pub enum WsMessage {
@@ -8,10 +10,24 @@ pub enum WsMessage {
     #[serde(rename = "session_init")]
     SessionInit {
         session_id: String,
+        /// Present with `session_id` when reconnecting to resume the session.
+        #[serde(default)]
+        resume_token: String,
+        /// True when an existing session was rebound to this socket.
+        #[serde(default)]
+        resumed: bool,
     },
+    /// Client -> server: the browser's active tab changed or navigated.
+    #[serde(
+        rename = "session_update",
+        alias = "SessionUpdate",
+        alias = "context_update"
+    )]
     SessionUpdate {
         url: String,
         title: Option<String>,
+        #[serde(default)]
+        tab_id: Option<i64>,
     },
     #[serde(rename = "action_request")]
     ActionRequest {
@@ -19,11 +35,53 @@ pub enum WsMessage {
         command: ActionCommand,
     },
     ActionResult(ActionResult),
+    /// Server -> client: an action waits for the user's decision.
+    #[serde(rename = "approval_required")]
+    ApprovalRequired {
+        approval_id: String,
+        command: ActionCommand,
+        reason: String,
+    },
+    /// Client -> server: the user's decision for `approval_id`.
+    #[serde(rename = "approval_response")]
+    ApprovalResponse {
+        approval_id: String,
+        #[serde(flatten)]
+        decision: ApprovalDecision,
+    },
+    /// Client -> server: stop an agent run on this session.
+    #[serde(rename = "cancel_run")]
+    CancelRun {
+        run_id: String,
+    },
+    /// Client -> server: the user turned record mode on.
+    #[serde(rename = "record_start")]
+    RecordStart {},
+    /// Client -> server: something the user did while recording.
+    #[serde(rename = "user_action")]
+    UserAction(UserAction),
+    /// Client -> server: record mode off. The recording is saved as the
+    /// caller's macro `name`, or discarded without a name.
+    #[serde(rename = "record_stop")]
+    RecordStop {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        description: Option<String>,
+    },
+    /// Server -> client: what became of the recording.
+    #[serde(rename = "record_stopped")]
+    RecordStopped {
+        /// The saved macro, `None` when discarded or on error.
+        name: Option<String>,
+        steps: usize,
+        error: Option<String>,
+    },
     #[serde(other)]
     Unknown,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum ActionCommand {
     #[serde(rename = "navigate_to")]
@@ -47,7 +105,36 @@ pub enum ActionCommand {
     GetInteractiveElements { limit: Option<usize> },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A user's own action, reported by the extension in record mode. Refs
+/// come from a snapshot `content.js` takes right before reporting.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UserAction {
+    Click {
+        #[serde(rename = "ref")]
+        ref_id: i32,
+    },
+    /// Final value of a text field once the user left it.
+    Type {
+        #[serde(rename = "ref")]
+        ref_id: i32,
+        /// Left out for sensitive fields.
+        #[serde(default)]
+        text: String,
+        /// Password-like field; the text is never sent and the macro gets
+        /// a parameter instead.
+        #[serde(default)]
+        sensitive: bool,
+        /// The field's accessible name, used to name that parameter.
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Typed URL, bookmark, reload or back/forward; link clicks and form
+    /// submits are covered by the click that caused them.
+    Navigate { url: String },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ActionResult {
     pub request_id: String,
     pub success: bool,
@@ -91,6 +178,87 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_session_init_serialization() {
+        let msg = WsMessage::SessionInit {
+            session_id: "abc".to_string(),
+            resume_token: "token".to_string(),
+            resumed: true,
+        };
+        let serialized = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"type":"session_init","data":{"session_id":"abc","resume_token":"token","resumed":true}}"#
+        );
+    }
+
+    #[test]
+    fn test_approval_response_deserialization() {
+        let raw = r#"{"type":"approval_response","data":{"approval_id":"a1","decision":"edit","command":{"type":"type_text","ref":2,"text":"hi"}}}"#;
+        match serde_json::from_str::<WsMessage>(raw).unwrap() {
+            WsMessage::ApprovalResponse {
+                approval_id,
+                decision: ApprovalDecision::Edit { command },
+            } => {
+                assert_eq!(approval_id, "a1");
+                assert!(matches!(command, ActionCommand::TypeText { ref_id: 2, .. }));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_session_update_deserialization() {
+        let raw = r#"{"type":"session_update","data":{"url":"https://example.com/","title":"Example","tab_id":42,"timestamp":"2025-01-01T00:00:00Z"}}"#;
+        match serde_json::from_str::<WsMessage>(raw).unwrap() {
+            WsMessage::SessionUpdate { url, title, tab_id } => {
+                assert_eq!(url, "https://example.com/");
+                assert_eq!(title.as_deref(), Some("Example"));
+                assert_eq!(tab_id, Some(42));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_cancel_run_deserialization() {
+        let raw = r#"{"type":"cancel_run","data":{"run_id":"r1"}}"#;
+        assert!(matches!(
+            serde_json::from_str::<WsMessage>(raw).unwrap(),
+            WsMessage::CancelRun { run_id } if run_id == "r1"
+        ));
+    }
+
+    #[test]
+    fn test_recording_messages_deserialization() {
+        let raw = r#"{"type":"user_action","data":{"kind":"type","ref":3,"sensitive":true,"name":"Password"}}"#;
+        match serde_json::from_str::<WsMessage>(raw).unwrap() {
+            WsMessage::UserAction(UserAction::Type {
+                ref_id,
+                text,
+                sensitive,
+                name,
+            }) => {
+                assert_eq!(ref_id, 3);
+                assert!(text.is_empty());
+                assert!(sensitive);
+                assert_eq!(name.as_deref(), Some("Password"));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let raw = r#"{"type":"record_start","data":{}}"#;
+        assert!(matches!(
+            serde_json::from_str::<WsMessage>(raw).unwrap(),
+            WsMessage::RecordStart {}
+        ));
+        let raw = r#"{"type":"record_stop","data":{"name":"login"}}"#;
+        assert!(matches!(
+            serde_json::from_str::<WsMessage>(raw).unwrap(),
+            WsMessage::RecordStop { name: Some(name), description: None } if name == "login"
+        ));
+    }
+
     #[test]
     fn test_action_result_serialization() {
         let res = WsMessage::ActionResult(ActionResult {
@@ -0,0 +1,532 @@
+//! URL navigation policy.
+//!
+//! Every `navigate_to` is checked against the deployment
+//! [`NavigationPolicy`] and, when one is set, the session's own policy. A URL
+//! has to pass both, so a session policy can only narrow what the deployment
+//! allows. Refusals reach the model as a `navigation_blocked` tool error and
+//! are kept in an audit log (`GET /navigation/audit`).
+//!
+//! Relative URLs are resolved against the session's current page first, so
+//! the policy always sees (and the extension always gets) an absolute URL.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use url::{Host, Url};
+
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::utils::time::now_millis;
+
+/// Schemes allowed when a policy doesn't list any.
+pub const DEFAULT_SCHEMES: &[&str] = &["http", "https"];
+
+/// Host names that always point inside the network.
+const INTERNAL_HOSTS: &[&str] = &["localhost", "*.localhost", "*.local", "*.internal"];
+
+/// How many refusals the audit log keeps.
+pub const NAVIGATION_AUDIT_CAPACITY: usize = 500;
+
+/// One side (allow or deny) of a policy. Empty lists don't restrict.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NavigationRules {
+    /// URL schemes without `:` (e.g. `https`).
+    #[serde(default)]
+    pub schemes: Vec<String>,
+    /// Host globs, `*` matches any characters (e.g. `*.example.com`).
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// IP ranges for IP-literal hosts (e.g. `10.0.0.0/8`, `::1/128`).
+    #[serde(default)]
+    pub cidrs: Vec<String>,
+    /// Regexes matched against the URL path.
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// Serialized form of a policy, as read from `NAVIGATION_POLICY_FILE` or
+/// `PUT /sessions/{id}/navigation-policy`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NavigationPolicyConfig {
+    #[serde(default)]
+    pub allow: NavigationRules,
+    #[serde(default)]
+    pub deny: NavigationRules,
+    /// Refuse loopback, private, link-local and `*.local`-style hosts.
+    #[serde(default = "default_true")]
+    pub block_internal: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for NavigationPolicyConfig {
+    fn default() -> Self {
+        Self {
+            allow: NavigationRules::default(),
+            deny: NavigationRules::default(),
+            block_internal: true,
+        }
+    }
+}
+
+/// IPv4 or IPv6 network in CIDR notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parses `addr/prefix`; a bare address is a single-host network.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr.trim().parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Matchers {
+    schemes: Vec<String>,
+    hosts: Vec<String>,
+    cidrs: Vec<Cidr>,
+    paths: Vec<Regex>,
+}
+
+impl Matchers {
+    fn compile(rules: &NavigationRules) -> Result<Self, AppError> {
+        let lower = |values: &[String]| -> Vec<String> {
+            values
+                .iter()
+                .map(|v| v.trim().trim_end_matches(':').to_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect()
+        };
+        let cidrs = rules
+            .cidrs
+            .iter()
+            .map(|c| {
+                Cidr::parse(c)
+                    .ok_or_else(|| AppError::InvalidRequest(format!("invalid CIDR {}", c)))
+            })
+            .collect::<Result<_, _>>()?;
+        let paths = rules
+            .paths
+            .iter()
+            .map(|p| {
+                Regex::new(p).map_err(|e| {
+                    AppError::InvalidRequest(format!("invalid path regex {}: {}", p, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            schemes: lower(&rules.schemes),
+            hosts: lower(&rules.hosts),
+            cidrs,
+            paths,
+        })
+    }
+}
+
+/// Why a URL was refused. `rule` is stable, `message` is for humans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl Violation {
+    fn new(rule: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            rule,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.rule)
+    }
+}
+
+/// Compiled allow/deny rules.
+#[derive(Debug, Clone)]
+pub struct NavigationPolicy {
+    config: NavigationPolicyConfig,
+    allow: Matchers,
+    deny: Matchers,
+}
+
+impl Default for NavigationPolicy {
+    fn default() -> Self {
+        Self::from_config(NavigationPolicyConfig::default()).expect("default policy compiles")
+    }
+}
+
+impl NavigationPolicy {
+    pub fn from_config(config: NavigationPolicyConfig) -> Result<Self, AppError> {
+        let mut allow = Matchers::compile(&config.allow)?;
+        if allow.schemes.is_empty() {
+            allow.schemes = DEFAULT_SCHEMES.iter().map(|s| s.to_string()).collect();
+        }
+        let deny = Matchers::compile(&config.deny)?;
+        Ok(Self {
+            config,
+            allow,
+            deny,
+        })
+    }
+
+    pub fn config(&self) -> &NavigationPolicyConfig {
+        &self.config
+    }
+
+    pub fn evaluate(&self, raw: &str) -> Result<(), Violation> {
+        let url = Url::parse(raw.trim()).map_err(|e| {
+            Violation::new(
+                "invalid_url",
+                format!("\"{}\" is not a valid URL: {}", raw, e),
+            )
+        })?;
+
+        let scheme = url.scheme();
+        if !self.allow.schemes.iter().any(|s| s == scheme)
+            || self.deny.schemes.iter().any(|s| s == scheme)
+        {
+            return Err(Violation::new(
+                "scheme",
+                format!("the {}: scheme is not allowed", scheme),
+            ));
+        }
+
+        let Some(host) = url.host() else {
+            return Err(Violation::new(
+                "host",
+                "URLs without a host are not allowed",
+            ));
+        };
+        let (name, ip) = match host {
+            Host::Domain(domain) => (domain.trim_end_matches('.').to_lowercase(), None),
+            Host::Ipv4(ip) => (ip.to_string(), Some(IpAddr::V4(ip))),
+            Host::Ipv6(ip) => (ip.to_string(), Some(IpAddr::V6(ip))),
+        };
+
+        if self.config.block_internal {
+            let internal = match ip {
+                Some(ip) => is_internal_ip(ip),
+                None => INTERNAL_HOSTS.iter().any(|p| glob_match(p, &name)),
+            };
+            if internal {
+                return Err(Violation::new(
+                    "internal_address",
+                    format!("{} is an internal address", name),
+                ));
+            }
+        }
+
+        if self.deny.hosts.iter().any(|p| glob_match(p, &name)) {
+            return Err(Violation::new(
+                "host_denied",
+                format!("host {} is denied", name),
+            ));
+        }
+        if !self.allow.hosts.is_empty() && !self.allow.hosts.iter().any(|p| glob_match(p, &name)) {
+            return Err(Violation::new(
+                "host_not_allowed",
+                format!("host {} is not in the allowlist", name),
+            ));
+        }
+
+        if let Some(ip) = ip {
+            if self.deny.cidrs.iter().any(|c| c.contains(ip)) {
+                return Err(Violation::new(
+                    "cidr_denied",
+                    format!("address {} is denied", ip),
+                ));
+            }
+            if !self.allow.cidrs.is_empty() && !self.allow.cidrs.iter().any(|c| c.contains(ip)) {
+                return Err(Violation::new(
+                    "cidr_not_allowed",
+                    format!("address {} is not in the allowlist", ip),
+                ));
+            }
+        }
+
+        let path = url.path();
+        if self.deny.paths.iter().any(|r| r.is_match(path)) {
+            return Err(Violation::new(
+                "path_denied",
+                format!("path {} is denied", path),
+            ));
+        }
+        if !self.allow.paths.is_empty() && !self.allow.paths.iter().any(|r| r.is_match(path)) {
+            return Err(Violation::new(
+                "path_not_allowed",
+                format!("path {} is not in the allowlist", path),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn is_internal_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_internal_v4(v4),
+            None => is_internal_v6(ip),
+        },
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // 0.0.0.0/8 and carrier-grade NAT 100.64.0.0/10
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64)
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// Case-sensitive glob where `*` matches any run of characters.
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let (p, v): (Vec<char>, Vec<char>) = (pattern.chars().collect(), value.chars().collect());
+    let (mut pi, mut vi) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while vi < v.len() {
+        if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, vi));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == v[vi] {
+            pi += 1;
+            vi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            vi = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Resolves `raw` against the page the browser shows; absolute URLs and
+/// anything that doesn't parse are returned unchanged.
+pub fn resolve(page_url: Option<&str>, raw: &str) -> String {
+    if Url::parse(raw).is_ok() {
+        return raw.to_string();
+    }
+    page_url
+        .and_then(|base| Url::parse(base).ok())
+        .and_then(|base| base.join(raw).ok())
+        .map(String::from)
+        .unwrap_or_else(|| raw.to_string())
+}
+
+/// A refused navigation.
+#[derive(Debug, Clone, Serialize)]
+pub struct NavigationAuditEntry {
+    pub session_id: String,
+    /// Owner of the session when the navigation was refused; only they see
+    /// the entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+    pub url: String,
+    /// Page the browser was on when the navigation was attempted.
+    pub from: Option<String>,
+    /// `deployment` or `session`.
+    pub scope: &'static str,
+    pub rule: &'static str,
+    pub message: String,
+    pub at: u64,
+}
+
+/// Checks `url` against the deployment and session policies, recording
+/// refusals in the audit log.
+pub async fn enforce(state: &Arc<AppState>, session_id: &str, url: &str) -> Result<(), AppError> {
+    let session_policy = state.session_navigation_policy(session_id).await;
+    let verdict = state
+        .navigation_policy
+        .evaluate(url)
+        .map_err(|v| ("deployment", v))
+        .and_then(|_| match &session_policy {
+            Some(policy) => policy.evaluate(url).map_err(|v| ("session", v)),
+            None => Ok(()),
+        });
+
+    let Err((scope, violation)) = verdict else {
+        return Ok(());
+    };
+    tracing::warn!(
+        "Navigation blocked[{}] session={} scope={} url={}: {}",
+        violation.rule,
+        session_id,
+        scope,
+        url,
+        violation.message
+    );
+    state
+        .record_navigation_refusal(NavigationAuditEntry {
+            session_id: session_id.to_string(),
+            principal: state.session_principal(session_id).await,
+            url: url.to_string(),
+            from: state.page_snapshot(session_id).await.url,
+            scope,
+            rule: violation.rule,
+            message: violation.message.clone(),
+            at: now_millis(),
+        })
+        .await;
+    Err(AppError::NavigationBlocked(violation.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(policy: &NavigationPolicy, url: &str) -> Option<&'static str> {
+        policy.evaluate(url).err().map(|v| v.rule)
+    }
+
+    #[test]
+    fn test_default_policy_blocks_unsafe_urls() {
+        let policy = NavigationPolicy::default();
+        assert_eq!(rule(&policy, "https://example.com/a"), None);
+        for url in [
+            "javascript:alert(1)",
+            "data:text/html,<script>x</script>",
+            "view-source:https://example.com",
+            "chrome-extension://abc/popup.html",
+            "chrome://settings",
+            "about:blank",
+            "file:///etc/passwd",
+        ] {
+            assert_eq!(rule(&policy, url), Some("scheme"), "{}", url);
+        }
+        for url in [
+            "http://127.0.0.1:8080",
+            "http://10.1.2.3/admin",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "http://2130706433/",
+            "http://localhost:3000",
+            "http://printer.local",
+        ] {
+            assert_eq!(rule(&policy, url), Some("internal_address"), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_allow_and_deny_rules() {
+        let policy = NavigationPolicy::from_config(NavigationPolicyConfig {
+            allow: NavigationRules {
+                hosts: vec!["*.example.com".into()],
+                ..Default::default()
+            },
+            deny: NavigationRules {
+                hosts: vec!["admin.example.com".into()],
+                paths: vec!["^/billing".into()],
+                ..Default::default()
+            },
+            block_internal: true,
+        })
+        .unwrap();
+
+        assert_eq!(rule(&policy, "https://shop.example.com/cart"), None);
+        assert_eq!(
+            rule(&policy, "https://evil.test/"),
+            Some("host_not_allowed")
+        );
+        assert_eq!(
+            rule(&policy, "https://admin.example.com/"),
+            Some("host_denied")
+        );
+        assert_eq!(
+            rule(&policy, "https://shop.example.com/billing/cards"),
+            Some("path_denied")
+        );
+    }
+
+    #[test]
+    fn test_relative_urls_resolve_against_current_page() {
+        let page = Some("https://example.com/docs/intro");
+        assert_eq!(resolve(page, "setup"), "https://example.com/docs/setup");
+        assert_eq!(resolve(page, "/admin"), "https://example.com/admin");
+        assert_eq!(resolve(page, "https://other.com/"), "https://other.com/");
+        assert_eq!(resolve(None, "/admin"), "/admin");
+    }
+
+    #[test]
+    fn test_cidr_rules() {
+        let net = Cidr::parse("10.20.0.0/16").unwrap();
+        assert!(net.contains("10.20.3.4".parse().unwrap()));
+        assert!(!net.contains("10.21.0.1".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.0/33").is_none());
+
+        let policy = NavigationPolicy::from_config(NavigationPolicyConfig {
+            allow: NavigationRules {
+                cidrs: vec!["10.20.0.0/16".into()],
+                ..Default::default()
+            },
+            block_internal: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(rule(&policy, "http://10.20.0.5/"), None);
+        assert_eq!(rule(&policy, "http://10.30.0.5/"), Some("cidr_not_allowed"));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let config = NavigationPolicyConfig {
+            deny: NavigationRules {
+                paths: vec!["(".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            NavigationPolicy::from_config(config),
+            Err(AppError::InvalidRequest(_))
+        ));
+    }
+}
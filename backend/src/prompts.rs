@@ -0,0 +1,207 @@
+//! Preamble templates.
+//!
+//! `agent.md` and `chat.md` of every locale are templates with `{{name}}`
+//! placeholders. Only the variables in [`VARIABLES`] are allowed, so a typo
+//! fails when the file is loaded instead of leaking into a prompt.
+
+use rig::tool::ToolDyn;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const VARIABLES: &[&str] = &[
+    "tools",
+    "page_url",
+    "page_title",
+    "page_context",
+    "date",
+    "custom_instruction",
+    "locale",
+];
+
+/// Values substituted into a template.
+#[derive(Debug, Clone, Default)]
+pub struct PromptVars {
+    /// Markdown list generated from the tool definitions.
+    pub tools: String,
+    pub page_url: String,
+    pub page_title: String,
+    /// Elements and page text the client sent with the request, or empty.
+    pub page_context: String,
+    /// Today as `YYYY-MM-DD` (UTC).
+    pub date: String,
+    /// The locale's custom instruction block, or empty.
+    pub custom_instruction: String,
+    pub locale: String,
+}
+
+impl PromptVars {
+    fn get(&self, name: &str) -> Option<&str> {
+        Some(match name {
+            "tools" => &self.tools,
+            "page_url" => &self.page_url,
+            "page_title" => &self.page_title,
+            "page_context" => &self.page_context,
+            "date" => &self.date,
+            "custom_instruction" => &self.custom_instruction,
+            "locale" => &self.locale,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+}
+
+impl Template {
+    /// Checks every placeholder names a known variable.
+    pub fn parse(name: &str, source: impl Into<String>) -> Result<Self, String> {
+        let template = Self {
+            source: source.into(),
+        };
+        for (_, variable) in template.placeholders() {
+            if !VARIABLES.contains(&variable) {
+                return Err(format!(
+                    "{}: unknown variable {{{{{}}}}} (expected one of: {})",
+                    name,
+                    variable,
+                    VARIABLES.join(", ")
+                ));
+            }
+        }
+        Ok(template)
+    }
+
+    pub fn render(&self, vars: &PromptVars) -> String {
+        let mut output = String::with_capacity(self.source.len());
+        let mut rest = 0;
+        for (range, variable) in self.placeholders() {
+            output.push_str(&self.source[rest..range.start]);
+            output.push_str(vars.get(variable).unwrap_or_default());
+            rest = range.end;
+        }
+        output.push_str(&self.source[rest..]);
+        output
+    }
+
+    /// Byte range and trimmed name of every `{{ name }}`.
+    fn placeholders(&self) -> Vec<(std::ops::Range<usize>, &str)> {
+        let mut found = Vec::new();
+        let mut offset = 0;
+        while let Some(start) = self.source[offset..].find("{{") {
+            let start = offset + start;
+            let Some(len) = self.source[start + 2..].find("}}") else {
+                break;
+            };
+            let end = start + 2 + len + 2;
+            found.push((start..end, self.source[start + 2..end - 2].trim()));
+            offset = end;
+        }
+        found
+    }
+}
+
+/// Lists tools as `- \`name(arg, ...)\`: description`, required arguments
+/// first, straight from their definitions.
+pub async fn describe_tools(tools: &[Box<dyn ToolDyn>]) -> String {
+    let mut lines = Vec::with_capacity(tools.len());
+    for tool in tools {
+        let definition = tool.definition(String::new()).await;
+        let parameters = &definition.parameters;
+        let mut arguments: Vec<&str> = parameters["required"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .collect();
+        if let Some(properties) = parameters["properties"].as_object() {
+            for name in properties.keys() {
+                if !arguments.contains(&name.as_str()) {
+                    arguments.push(name);
+                }
+            }
+        }
+        lines.push(format!(
+            "- `{}({})`: {}",
+            definition.name,
+            arguments.join(", "),
+            definition.description
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Today's UTC date as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or_default() as i64;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian date (H. Hinnant).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::browser::{ClickTool, NavigateTool, TypeTool};
+
+    #[test]
+    fn test_render_substitutes_variables() {
+        let template = Template::parse(
+            "t",
+            "Page: {{ page_url }} ({{locale}}){{custom_instruction}}",
+        )
+        .unwrap();
+        let vars = PromptVars {
+            page_url: "https://example.com".into(),
+            locale: "en".into(),
+            ..Default::default()
+        };
+        assert_eq!(template.render(&vars), "Page: https://example.com (en)");
+    }
+
+    #[test]
+    fn test_unknown_variables_are_rejected() {
+        let error = Template::parse("agent.md", "Hi {{page_ur}}").unwrap_err();
+        assert!(error.contains("page_ur"));
+    }
+
+    #[tokio::test]
+    async fn test_tool_list_comes_from_definitions() {
+        let tools: Vec<Box<dyn ToolDyn>> = vec![
+            Box::new(NavigateTool),
+            Box::new(ClickTool),
+            Box::new(TypeTool),
+        ];
+        let list = describe_tools(&tools).await;
+        assert_eq!(
+            list.lines().collect::<Vec<_>>(),
+            vec![
+                "- `navigate_to(url)`: Navigate to a specific URL in the browser",
+                "- `click_element(ref)`: Click an element on the page using its reference ID",
+                "- `type_text(ref, text)`: Type text into an input field using its reference ID",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+}
@@ -0,0 +1,777 @@
+//! Replaying recorded browser actions without the model.
+//!
+//! `POST /sessions/{id}/replay` takes the `ActionCommand`s of a trace, a
+//! saved macro or an explicit list and sends them to the session one by one
+//! through [`execute_tool`], in the order they were recorded. Navigations
+//! are still checked against the navigation policy; approvals are not asked
+//! for, the caller started the replay on purpose.
+//!
+//! A replay in `step` mode pauses before every command and waits for
+//! `POST /replays/{id}/step`. In `continuous` mode it pauses only when a
+//! command fails (unless `pause_on_failure` is off, which fails the replay
+//! instead); the failed step can then be retried with `step`, skipped with
+//! `skip`, or retried and continued with `resume`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, mpsc, watch};
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use crate::approval::review;
+use crate::auth::Principal;
+use crate::error::{AppError, ErrorPayload};
+use crate::macros::instantiate;
+use crate::models::trace::{REDACTED_TEXT, TraceRecord};
+use crate::models::ws::ActionCommand;
+use crate::navigation::enforce;
+use crate::state::AppState;
+use crate::tools::output::InteractiveElements;
+use crate::tools::websocket::execute_tool;
+use crate::utils::time::now_millis;
+
+/// Finished replays kept for polling; the oldest are dropped first.
+pub const REPLAY_HISTORY_CAPACITY: usize = 100;
+/// Longest command list one replay accepts.
+pub const MAX_REPLAY_STEPS: usize = 500;
+/// Unfinished replays one principal may have at a time.
+pub const MAX_ACTIVE_REPLAYS_PER_PRINCIPAL: usize = 4;
+/// How long a paused replay waits for a control before it is cancelled.
+pub const DEFAULT_REPLAY_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayMode {
+    /// Run every command, pausing only on failures.
+    #[default]
+    Continuous,
+    /// Pause before every command.
+    Step,
+}
+
+fn default_pause_on_failure() -> bool {
+    true
+}
+
+/// `POST /sessions/{id}/replay`. Exactly one of `run_id`, `commands` and
+/// `macro`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayRequest {
+    /// Trace whose actions are replayed.
+    pub run_id: Option<String>,
+    /// Commands to replay instead of a trace.
+    pub commands: Option<Vec<ActionCommand>>,
+    /// One of the caller's macros instead of a trace.
+    #[serde(rename = "macro")]
+    pub macro_name: Option<String>,
+    /// Values for the macro's `{{param}}` placeholders.
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    #[serde(default)]
+    pub mode: ReplayMode,
+    /// Wait at a failed command instead of failing the replay.
+    #[serde(default = "default_pause_on_failure")]
+    pub pause_on_failure: bool,
+    /// Also replay trace actions that failed (or never got a result).
+    #[serde(default)]
+    pub include_failed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayStatus {
+    Running,
+    /// Waiting for `step`, `skip`, `resume` or `cancel`.
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ReplayStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            ReplayStatus::Completed | ReplayStatus::Failed | ReplayStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Completed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayStep {
+    pub command: ActionCommand,
+    pub status: StepStatus,
+    /// What the extension returned for the last attempt.
+    pub data: Option<Value>,
+    pub error: Option<ErrorPayload>,
+    pub duration_ms: Option<u64>,
+    pub attempts: u32,
+}
+
+impl ReplayStep {
+    fn new(command: ActionCommand) -> Self {
+        Self {
+            command,
+            status: StepStatus::Pending,
+            data: None,
+            error: None,
+            duration_ms: None,
+            attempts: 0,
+        }
+    }
+}
+
+/// `GET /replays/{id}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Replay {
+    pub replay_id: String,
+    pub session_id: String,
+    pub principal: String,
+    /// The trace replayed, if any.
+    pub run_id: Option<String>,
+    /// The macro replayed, if any.
+    #[serde(rename = "macro")]
+    pub macro_name: Option<String>,
+    pub mode: ReplayMode,
+    pub pause_on_failure: bool,
+    pub status: ReplayStatus,
+    /// Index of the next step to run.
+    pub cursor: usize,
+    pub steps: Vec<ReplayStep>,
+    /// Unix milliseconds.
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+/// Sent to a replay's driver by the control endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayControl {
+    /// Run the next step (again, if it failed), then pause.
+    Step,
+    /// Run the next step and keep going.
+    Resume,
+    /// Mark the next step skipped and stay paused.
+    Skip,
+    /// Stop after the step in progress.
+    Cancel,
+}
+
+struct ReplayEntry {
+    /// Written only by the driver; everyone else reads or waits on it.
+    replay: Arc<watch::Sender<Replay>>,
+    control: mpsc::UnboundedSender<ReplayControl>,
+}
+
+pub struct ReplayStore {
+    replays: RwLock<HashMap<String, ReplayEntry>>,
+    idle_timeout: Duration,
+}
+
+impl Default for ReplayStore {
+    fn default() -> Self {
+        Self {
+            replays: RwLock::default(),
+            idle_timeout: DEFAULT_REPLAY_IDLE_TIMEOUT,
+        }
+    }
+}
+
+impl ReplayStore {
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub async fn get(&self, replay_id: &str) -> Option<Replay> {
+        let replays = self.replays.read().await;
+        replays.get(replay_id).map(|e| e.replay.borrow().clone())
+    }
+
+    /// Replays of `principal`, optionally on one session, newest first.
+    pub async fn list(&self, principal: &str, session_id: Option<&str>) -> Vec<Replay> {
+        let replays = self.replays.read().await;
+        let mut list: Vec<Replay> = replays
+            .values()
+            .map(|e| e.replay.borrow().clone())
+            .filter(|r| r.principal == principal)
+            .filter(|r| session_id.is_none_or(|s| r.session_id == s))
+            .collect();
+        list.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        list
+    }
+
+    async fn has_active(&self, session_id: &str) -> bool {
+        let replays = self.replays.read().await;
+        replays.values().any(|e| {
+            let replay = e.replay.borrow();
+            replay.session_id == session_id && !replay.status.is_finished()
+        })
+    }
+
+    /// Hands `control` to the replay's driver. `step`, `skip` and `resume`
+    /// need a paused replay, `cancel` an unfinished one. The receiver sees
+    /// what the driver does next.
+    pub async fn control(
+        &self,
+        replay_id: &str,
+        control: ReplayControl,
+    ) -> Result<watch::Receiver<Replay>, AppError> {
+        let replays = self.replays.read().await;
+        let entry = replays
+            .get(replay_id)
+            .ok_or_else(|| AppError::NotFound(format!("Replay {}", replay_id)))?;
+        let status = entry.replay.borrow().status;
+        let allowed = match control {
+            ReplayControl::Cancel => !status.is_finished(),
+            _ => status == ReplayStatus::Paused,
+        };
+        if !allowed {
+            return Err(AppError::InvalidRequest(format!(
+                "replay {} is {:?}",
+                replay_id, status
+            )));
+        }
+        let changes = entry.replay.subscribe();
+        entry
+            .control
+            .send(control)
+            .map_err(|_| AppError::Internal(format!("replay {} has stopped", replay_id)))?;
+        Ok(changes)
+    }
+
+    /// Fails with `RateLimited` when the principal already has
+    /// [`MAX_ACTIVE_REPLAYS_PER_PRINCIPAL`] unfinished replays, or the
+    /// history is full of unfinished ones.
+    async fn insert(
+        &self,
+        replay: Replay,
+    ) -> Result<
+        (
+            Arc<watch::Sender<Replay>>,
+            mpsc::UnboundedReceiver<ReplayControl>,
+        ),
+        AppError,
+    > {
+        let mut replays = self.replays.write().await;
+        let active = replays
+            .values()
+            .filter(|e| {
+                let r = e.replay.borrow();
+                r.principal == replay.principal && !r.status.is_finished()
+            })
+            .count();
+        if active >= MAX_ACTIVE_REPLAYS_PER_PRINCIPAL {
+            return Err(AppError::RateLimited(format!(
+                "{} replays are already running or paused",
+                active
+            )));
+        }
+        if replays.len() >= REPLAY_HISTORY_CAPACITY {
+            let oldest = replays
+                .iter()
+                .filter(|(_, e)| e.replay.borrow().status.is_finished())
+                .min_by_key(|(_, e)| e.replay.borrow().created_at)
+                .map(|(id, _)| id.clone());
+            let Some(id) = oldest else {
+                return Err(AppError::RateLimited(format!(
+                    "{} replays are already running or paused",
+                    REPLAY_HISTORY_CAPACITY
+                )));
+            };
+            replays.remove(&id);
+        }
+        let replay_id = replay.replay_id.clone();
+        let sender = Arc::new(watch::Sender::new(replay));
+        let (control, controls) = mpsc::unbounded_channel();
+        replays.insert(
+            replay_id,
+            ReplayEntry {
+                replay: sender.clone(),
+                control,
+            },
+        );
+        Ok((sender, controls))
+    }
+}
+
+/// Waits until the driver is done with the control just sent: paused
+/// again or finished.
+pub async fn settled(mut changes: watch::Receiver<Replay>) -> Replay {
+    while changes.changed().await.is_ok() {
+        if changes.borrow().status != ReplayStatus::Running {
+            break;
+        }
+    }
+    changes.borrow().clone()
+}
+
+/// The `ActionCommand`s a trace sent, in order. Unless `include_failed`,
+/// only those the extension reported as successful.
+pub(crate) async fn trace_commands(
+    state: &AppState,
+    run_id: &str,
+    principal: &Principal,
+    include_failed: bool,
+) -> Result<Vec<ActionCommand>, AppError> {
+    state.authorized_trace(run_id, principal).await?;
+    let lines = state
+        .traces
+        .lines(run_id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Trace {}", run_id)))?;
+
+    let mut sent: Vec<(String, ActionCommand)> = Vec::new();
+    let mut succeeded: HashMap<String, bool> = HashMap::new();
+    for line in lines {
+        match line.record {
+            TraceRecord::ActionRequest {
+                request_id,
+                command,
+            } => sent.push((request_id, command)),
+            TraceRecord::ActionResult { result, .. } => {
+                succeeded.insert(result.request_id, result.success);
+            }
+            _ => {}
+        }
+    }
+    Ok(sent
+        .into_iter()
+        .filter(|(id, _)| include_failed || succeeded.get(id).copied().unwrap_or(false))
+        .map(|(_, command)| command)
+        .collect())
+}
+
+/// Starts replaying on `session_id` and returns the replay right away.
+pub async fn submit(
+    state: &Arc<AppState>,
+    principal: &Principal,
+    session_id: &str,
+    request: ReplayRequest,
+) -> Result<Replay, AppError> {
+    state.authorize_session(session_id, principal).await?;
+    if state.session_connected(session_id).await.is_none() {
+        return Err(AppError::NotFound(format!("Session {}", session_id)));
+    }
+    let commands = match (&request.run_id, request.commands, &request.macro_name) {
+        (Some(run_id), None, None) => {
+            trace_commands(state, run_id, principal, request.include_failed).await?
+        }
+        (None, Some(commands), None) => commands,
+        (None, None, Some(name)) => {
+            let saved = state
+                .macros
+                .get(principal.name(), name)
+                .await
+                .ok_or_else(|| AppError::NotFound(format!("Macro {}", name)))?;
+            instantiate(&saved, &request.params)?
+        }
+        _ => {
+            return Err(AppError::InvalidRequest(
+                "exactly one of run_id, commands and macro is required".to_string(),
+            ));
+        }
+    };
+    if commands.is_empty() {
+        return Err(AppError::InvalidRequest(
+            "there are no actions to replay".to_string(),
+        ));
+    }
+    if commands.len() > MAX_REPLAY_STEPS {
+        return Err(AppError::InvalidRequest(format!(
+            "at most {} actions can be replayed at once",
+            MAX_REPLAY_STEPS
+        )));
+    }
+    // Interleaved actions would defeat the point of a replay
+    if state.has_active_run(session_id).await {
+        return Err(AppError::InvalidRequest(format!(
+            "session {} has an agent run in progress",
+            session_id
+        )));
+    }
+    if state.replays.has_active(session_id).await {
+        return Err(AppError::InvalidRequest(format!(
+            "session {} is already replaying",
+            session_id
+        )));
+    }
+
+    let replay = Replay {
+        replay_id: Uuid::new_v4().to_string(),
+        session_id: session_id.to_string(),
+        principal: principal.name().to_string(),
+        run_id: request.run_id,
+        macro_name: request.macro_name,
+        mode: request.mode,
+        pause_on_failure: request.pause_on_failure,
+        status: match request.mode {
+            ReplayMode::Continuous => ReplayStatus::Running,
+            ReplayMode::Step => ReplayStatus::Paused,
+        },
+        cursor: 0,
+        steps: commands.into_iter().map(ReplayStep::new).collect(),
+        created_at: now_millis(),
+        finished_at: None,
+    };
+    let (sender, controls) = state.replays.insert(replay.clone()).await?;
+    tracing::info!(
+        "Replay started: replay_id={} session_id={} steps={}",
+        replay.replay_id,
+        session_id,
+        replay.steps.len()
+    );
+    tokio::spawn(drive(state.clone(), sender, controls));
+    Ok(replay)
+}
+
+/// Sends one recorded command, keeping the session's page knowledge up to
+/// date the way the agent's tools do. Navigations are checked against the
+/// navigation policy; with `approve` the command also goes through the
+/// approval policy first.
+pub(crate) async fn execute_step(
+    state: &Arc<AppState>,
+    session_id: &str,
+    run_id: Option<&str>,
+    command: ActionCommand,
+    approve: bool,
+) -> Result<Option<Value>, AppError> {
+    // Traces don't keep what was typed into password fields
+    if let ActionCommand::TypeText { text, .. } = &command
+        && text == REDACTED_TEXT
+    {
+        return Err(AppError::ToolFailed(
+            "the typed text was redacted from the trace".to_string(),
+        ));
+    }
+    // Refused navigations never reach the user
+    if approve && let ActionCommand::NavigateTo { url } = &command {
+        enforce(state, session_id, url).await?;
+    }
+    let command = if approve {
+        review(state, session_id, run_id, command).await?
+    } else {
+        command
+    };
+    match command {
+        ActionCommand::NavigateTo { url } => {
+            enforce(state, session_id, &url).await?;
+            let data = execute_tool(
+                state,
+                session_id,
+                run_id,
+                ActionCommand::NavigateTo { url: url.clone() },
+            )
+            .await?;
+            state.invalidate_page_elements(session_id).await;
+            state.record_page_url(session_id, &url).await;
+            Ok(data)
+        }
+        ActionCommand::GetInteractiveElements { limit } => {
+            let data = execute_tool(
+                state,
+                session_id,
+                run_id,
+                ActionCommand::GetInteractiveElements { limit },
+            )
+            .await?;
+            // Malformed snapshots are the caller's business, not a failure
+            if let Ok(output) = InteractiveElements::from_data(data.clone(), limit) {
+                state
+                    .record_page_elements(session_id, output.elements)
+                    .await;
+            }
+            Ok(data)
+        }
+        ActionCommand::ClickElement { ref_id } => {
+            let data = execute_tool(
+                state,
+                session_id,
+                run_id,
+                ActionCommand::ClickElement { ref_id },
+            )
+            .await?;
+            state.invalidate_page_elements(session_id).await;
+            Ok(data)
+        }
+        command => execute_tool(state, session_id, run_id, command).await,
+    }
+}
+
+fn finish(replay: &mut Replay, status: ReplayStatus) {
+    replay.status = status;
+    replay.finished_at = Some(now_millis());
+}
+
+/// Moves past the current step, completing the replay after the last one
+/// so nobody sees it paused at the end.
+fn advance(replay: &mut Replay) {
+    replay.cursor += 1;
+    if replay.cursor >= replay.steps.len() {
+        finish(replay, ReplayStatus::Completed);
+    }
+}
+
+/// Runs the replay's steps, taking controls while paused.
+async fn drive(
+    state: Arc<AppState>,
+    replay: Arc<watch::Sender<Replay>>,
+    mut controls: mpsc::UnboundedReceiver<ReplayControl>,
+) {
+    let (replay_id, session_id) = {
+        let r = replay.borrow();
+        (r.replay_id.clone(), r.session_id.clone())
+    };
+    loop {
+        let (status, cursor, total) = {
+            let r = replay.borrow();
+            (r.status, r.cursor, r.steps.len())
+        };
+        if status.is_finished() {
+            break;
+        }
+        if cursor >= total {
+            replay.send_modify(|r| finish(r, ReplayStatus::Completed));
+            break;
+        }
+
+        // Whether to pause again after this step
+        let single_step = if status == ReplayStatus::Paused {
+            let Ok(control) = timeout(state.replays.idle_timeout, controls.recv()).await else {
+                tracing::info!("Replay {} cancelled after idling while paused", replay_id);
+                replay.send_modify(|r| finish(r, ReplayStatus::Cancelled));
+                break;
+            };
+            match control {
+                Some(ReplayControl::Step) => true,
+                Some(ReplayControl::Resume) => {
+                    replay.send_modify(|r| r.mode = ReplayMode::Continuous);
+                    false
+                }
+                Some(ReplayControl::Skip) => {
+                    replay.send_modify(|r| {
+                        r.steps[r.cursor].status = StepStatus::Skipped;
+                        advance(r);
+                    });
+                    continue;
+                }
+                Some(ReplayControl::Cancel) | None => {
+                    replay.send_modify(|r| finish(r, ReplayStatus::Cancelled));
+                    break;
+                }
+            }
+        } else {
+            // Cancelling a running replay takes effect between steps
+            if let Ok(ReplayControl::Cancel) = controls.try_recv() {
+                replay.send_modify(|r| finish(r, ReplayStatus::Cancelled));
+                break;
+            }
+            false
+        };
+
+        let command = replay.borrow().steps[cursor].command.clone();
+        replay.send_modify(|r| r.status = ReplayStatus::Running);
+        let started = Instant::now();
+        let outcome = execute_step(&state, &session_id, None, command, false).await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+
+        replay.send_modify(|r| {
+            let step_mode = r.mode == ReplayMode::Step;
+            let pause_on_failure = r.pause_on_failure;
+            let step = &mut r.steps[cursor];
+            step.attempts += 1;
+            step.duration_ms = Some(duration_ms);
+            match outcome {
+                Ok(data) => {
+                    step.status = StepStatus::Completed;
+                    step.data = data;
+                    step.error = None;
+                    advance(r);
+                    if (single_step || step_mode) && !r.status.is_finished() {
+                        r.status = ReplayStatus::Paused;
+                    }
+                }
+                Err(error) => {
+                    tracing::info!("Replay {} step {} failed: {}", replay_id, cursor, error);
+                    step.status = StepStatus::Failed;
+                    step.data = None;
+                    step.error = Some(error.payload());
+                    if pause_on_failure {
+                        r.status = ReplayStatus::Paused;
+                    } else {
+                        finish(r, ReplayStatus::Failed);
+                    }
+                }
+            }
+        });
+    }
+    let status = replay.borrow().status;
+    tracing::info!(
+        "Replay finished: replay_id={} status={:?}",
+        replay_id,
+        status
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockProvider;
+    use crate::models::ws::ActionResult;
+
+    fn state() -> AppState {
+        let provider = MockProvider::from_json(r#"{"turns": []}"#).unwrap();
+        AppState::new(Arc::new(provider))
+    }
+
+    #[tokio::test]
+    async fn test_trace_commands_keep_successful_actions_in_order() {
+        let state = state();
+        let principal = Principal::anonymous();
+        state
+            .traces
+            .append(
+                "run-1",
+                TraceRecord::RunStarted {
+                    run_id: "run-1".into(),
+                    session_id: "s1".into(),
+                    principal: principal.name().to_string(),
+                    query: "click login".into(),
+                    model: "mock".into(),
+                    preamble: String::new(),
+                    page_url: None,
+                },
+            )
+            .await;
+        let actions = [
+            (
+                "a",
+                ActionCommand::GetInteractiveElements { limit: None },
+                true,
+            ),
+            ("b", ActionCommand::ClickElement { ref_id: 9 }, false),
+            ("c", ActionCommand::ClickElement { ref_id: 1 }, true),
+        ];
+        for (id, command, success) in actions {
+            state
+                .traces
+                .append(
+                    "run-1",
+                    TraceRecord::ActionRequest {
+                        request_id: id.into(),
+                        command,
+                    },
+                )
+                .await;
+            state
+                .traces
+                .append(
+                    "run-1",
+                    TraceRecord::ActionResult {
+                        result: ActionResult {
+                            request_id: id.into(),
+                            success,
+                            error: None,
+                            data: None,
+                        },
+                        duration_ms: 1,
+                    },
+                )
+                .await;
+        }
+
+        let commands = trace_commands(&state, "run-1", &principal, false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            commands.as_slice(),
+            [
+                ActionCommand::GetInteractiveElements { limit: None },
+                ActionCommand::ClickElement { ref_id: 1 },
+            ]
+        ));
+        let all = trace_commands(&state, "run-1", &principal, true)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+    }
+
+    fn replay(id: usize, principal: &str, status: ReplayStatus) -> Replay {
+        Replay {
+            replay_id: format!("r{}", id),
+            session_id: format!("s{}", id),
+            principal: principal.into(),
+            run_id: None,
+            macro_name: None,
+            mode: ReplayMode::Step,
+            pause_on_failure: true,
+            status,
+            cursor: 0,
+            steps: vec![ReplayStep::new(ActionCommand::ClickElement { ref_id: 1 })],
+            created_at: id as u64,
+            finished_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unfinished_replays_are_capped() {
+        let store = ReplayStore::default();
+        let mut senders = Vec::new();
+        for i in 0..MAX_ACTIVE_REPLAYS_PER_PRINCIPAL {
+            let (sender, _) = store
+                .insert(replay(i, "alice", ReplayStatus::Paused))
+                .await
+                .unwrap();
+            senders.push(sender);
+        }
+        let next = MAX_ACTIVE_REPLAYS_PER_PRINCIPAL;
+        assert!(matches!(
+            store
+                .insert(replay(next, "alice", ReplayStatus::Paused))
+                .await,
+            Err(AppError::RateLimited(_))
+        ));
+        // Other principals and finished replays don't count
+        store
+            .insert(replay(next + 1, "bob", ReplayStatus::Paused))
+            .await
+            .unwrap();
+        senders[0].send_modify(|r| finish(r, ReplayStatus::Cancelled));
+        store
+            .insert(replay(next + 2, "alice", ReplayStatus::Paused))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_paused_replay_is_cancelled() {
+        let mut state = state();
+        state.replays = ReplayStore::default().with_idle_timeout(Duration::from_millis(50));
+        let state = Arc::new(state);
+        let (sender, controls) = state
+            .replays
+            .insert(replay(0, "alice", ReplayStatus::Paused))
+            .await
+            .unwrap();
+        let changes = sender.subscribe();
+        tokio::spawn(drive(state.clone(), sender, controls));
+
+        let replay = timeout(Duration::from_secs(5), settled(changes))
+            .await
+            .unwrap();
+        assert_eq!(replay.status, ReplayStatus::Cancelled);
+        assert_eq!(replay.steps[0].status, StepStatus::Pending);
+    }
+}
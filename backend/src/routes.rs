@@ -1,62 +1,248 @@
-use crate::handler::agent_handler;
+use crate::agent::run::CANCELLED_BY_USER;
+use crate::auth::{Principal, require_auth};
+use crate::handler::{
+    admin_handler, agent_handler, approval_handler, auth_handler, conversation_handler,
+    instruction_handler, macro_handler, navigation_handler, openai_handler, replay_handler,
+    session_handler, task_handler, trace_handler,
+};
 use crate::models::ws::{ActionCommand, WsMessage};
 use crate::state::AppState;
 use axum::{
-    Router,
+    Extension, Router,
     extract::{
-        State,
+        Query, State,
         ws::{Message, WebSocket, WebSocketUpgrade},
     },
+    http::HeaderValue,
+    middleware,
     response::IntoResponse,
     routing::{get, post},
 };
 use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use uuid::Uuid;
 
 pub fn app_router(state: Arc<AppState>) -> Router {
+    let origins: Vec<HeaderValue> = state
+        .cors_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!("Ignoring invalid CORS origin: {}", origin);
+                None
+            }
+        })
+        .collect();
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(AllowOrigin::list(origins))
         .allow_methods(Any)
         .allow_headers(Any);
 
-    Router::new()
-        .route("/health", get(health_check))
+    // Everything except the health check needs credentials
+    let protected = Router::new()
         .route("/agent/run", post(agent_handler::run_agent))
+        .route("/agent/runs/{id}/cancel", post(agent_handler::cancel_run))
+        .route(
+            "/tasks",
+            get(task_handler::list_tasks).post(task_handler::create_task),
+        )
+        .route("/tasks/{id}", get(task_handler::get_task))
+        .route("/tasks/{id}/events", get(task_handler::task_events))
+        .route("/tasks/{id}/cancel", post(task_handler::cancel_task))
+        .route("/traces", get(trace_handler::list_traces))
+        .route("/replays", get(replay_handler::list_replays))
+        .route("/replays/{id}", get(replay_handler::get_replay))
+        .route("/replays/{id}/step", post(replay_handler::step_replay))
+        .route("/replays/{id}/skip", post(replay_handler::skip_replay_step))
+        .route("/replays/{id}/resume", post(replay_handler::resume_replay))
+        .route("/replays/{id}/cancel", post(replay_handler::cancel_replay))
+        .route("/traces/{run_id}", get(trace_handler::download_trace))
+        .route(
+            "/v1/chat/completions",
+            post(openai_handler::chat_completions),
+        )
+        .route(
+            "/conversations",
+            get(conversation_handler::list_conversations),
+        )
+        .route(
+            "/conversations/{id}",
+            get(conversation_handler::get_conversation)
+                .delete(conversation_handler::delete_conversation),
+        )
+        .route(
+            "/conversations/{id}/fork",
+            post(conversation_handler::fork_conversation),
+        )
+        .route("/approvals", get(approval_handler::list_approvals))
+        .route("/approvals/{id}", post(approval_handler::resolve_approval))
+        .route(
+            "/navigation/policy",
+            get(navigation_handler::get_deployment_policy),
+        )
+        .route("/navigation/audit", get(navigation_handler::list_audit))
+        .route(
+            "/sessions/{id}/navigation-policy",
+            get(navigation_handler::get_session_policy)
+                .put(navigation_handler::set_session_policy)
+                .delete(navigation_handler::delete_session_policy),
+        )
+        .route(
+            "/instructions",
+            get(instruction_handler::get_instructions)
+                .put(instruction_handler::set_instructions)
+                .delete(instruction_handler::delete_instructions),
+        )
+        .route("/macros", get(macro_handler::list_macros))
+        .route(
+            "/macros/{name}",
+            get(macro_handler::get_macro)
+                .put(macro_handler::save_macro)
+                .delete(macro_handler::delete_macro),
+        )
+        .route("/sessions/{id}", get(session_handler::get_session))
+        .route("/sessions/{id}/replay", post(replay_handler::start_replay))
+        .route(
+            "/sessions/{id}/instructions",
+            get(instruction_handler::get_session_instructions),
+        )
+        .route("/admin/sessions", get(admin_handler::list_sessions))
+        .route("/admin/sessions/{id}", get(admin_handler::get_session))
+        .route(
+            "/admin/sessions/{id}/disconnect",
+            post(admin_handler::disconnect_session),
+        )
+        .route(
+            "/admin/sessions/{id}/actions/{request_id}/cancel",
+            post(admin_handler::cancel_action),
+        )
         .route("/ws", get(ws_handler))
+        .route("/auth/token", post(auth_handler::issue_token))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_auth));
+
+    Router::new()
+        .route("/health", get(health_check))
+        .merge(protected)
         .with_state(state)
         .layer(cors)
 }
 
+/// Ends the session's recording, saving it as the principal's macro
+/// `name` when one is given.
+async fn stop_recording(
+    state: &AppState,
+    session_id: &str,
+    principal: &Principal,
+    name: Option<String>,
+    description: Option<String>,
+) -> WsMessage {
+    let Some(recording) = state.take_recording(session_id).await else {
+        return WsMessage::RecordStopped {
+            name: None,
+            steps: 0,
+            error: Some(format!("session {} is not recording", session_id)),
+        };
+    };
+    let steps = recording.steps.len();
+    let Some(name) = name.filter(|n| !n.trim().is_empty()) else {
+        tracing::info!("Recording discarded: session_id={}", session_id);
+        return WsMessage::RecordStopped {
+            name: None,
+            steps,
+            error: None,
+        };
+    };
+    match state
+        .macros
+        .save(principal.name(), &name, description, recording.steps)
+        .await
+    {
+        Ok(saved) => {
+            tracing::info!("Macro saved: name={} steps={}", saved.name, steps);
+            WsMessage::RecordStopped {
+                name: Some(saved.name),
+                steps,
+                error: None,
+            }
+        }
+        Err(e) => WsMessage::RecordStopped {
+            name: None,
+            steps,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
 async fn health_check() -> impl IntoResponse {
     axum::Json(serde_json::json!({"status": "ok"}))
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+/// Query parameters a reconnecting client uses to resume its session.
+#[derive(Debug, Default, Deserialize)]
+pub struct ResumeParams {
+    pub session_id: Option<String>,
+    pub resume_token: Option<String>,
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Query(resume): Query<ResumeParams>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state, principal, resume))
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
-    let session_id = Uuid::new_v4().to_string();
-    tracing::info!("New WebSocket connection: session_id={}", session_id);
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    principal: Principal,
+    resume: ResumeParams,
+) {
+    let connection_id = Uuid::new_v4().to_string();
 
     let (mut sink, mut stream) = socket.split();
     let (tx, mut rx) = mpsc::unbounded_channel::<WsMessage>();
 
-    // Register connection
-    state
-        .register_connection(session_id.clone(), tx.clone())
+    // Register connection, resuming the previous session if the token matches
+    let resume_pair = resume
+        .session_id
+        .as_deref()
+        .zip(resume.resume_token.as_deref());
+    let bound = state
+        .bind_session(&connection_id, &principal, resume_pair, tx.clone())
         .await;
+    let session_id = bound.session_id.clone();
+    tracing::info!(
+        "New WebSocket connection: session_id={}, principal={}, resumed={}",
+        session_id,
+        principal.name(),
+        bound.resumed
+    );
 
     // Send session_id to frontend
     let init_msg = WsMessage::SessionInit {
         session_id: session_id.clone(),
+        resume_token: bound.resume_token,
+        resumed: bound.resumed,
     };
     let _ = tx.send(init_msg);
     tracing::info!("Sent session_init to client");
 
+    // Re-deliver actions the previous socket never answered
+    for action in bound.redeliver {
+        tracing::info!("Re-delivering ActionRequest[{}]", action.request_id);
+        let _ = tx.send(WsMessage::ActionRequest {
+            request_id: action.request_id,
+            command: action.command,
+        });
+    }
+    let close = bound.close;
+
     // Spawn task to forward messages from channel to WebSocket
     let session_id_clone = session_id.clone();
     let send_task = tokio::spawn(async move {
@@ -70,14 +256,34 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
         tracing::info!("Send task terminated for session_id={}", session_id_clone);
     });
 
-    while let Some(msg) = stream.next().await {
+    loop {
+        // An operator may close the session while the client is idle
+        let msg = tokio::select! {
+            msg = stream.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = close.notified() => {
+                tracing::info!("Closing WebSocket: session_id={}", session_id);
+                break;
+            }
+        };
         if let Ok(Message::Text(text)) = msg {
             match serde_json::from_str::<WsMessage>(&text) {
                 Ok(WsMessage::Ping) => {
+                    state.record_ping(&session_id).await;
                     let _ = tx.send(WsMessage::Pong);
                 }
-                Ok(WsMessage::SessionUpdate { url, title }) => {
-                    tracing::info!("Context update: url={}, title={:?}", url, title);
+                Ok(WsMessage::SessionUpdate { url, title, tab_id }) => {
+                    tracing::info!(
+                        "Session update: url={}, title={:?}, tab_id={:?}",
+                        url,
+                        title,
+                        tab_id
+                    );
+                    state
+                        .record_page_visit(&session_id, &url, title, tab_id)
+                        .await;
                 }
                 Ok(WsMessage::ActionRequest {
                     request_id,
@@ -147,8 +353,43 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                         res.error,
                         res.data
                     );
-                    let request_id = res.request_id.clone();
-                    state.complete_pending_action(&request_id, res).await;
+                    state.complete_session_action(&session_id, res).await;
+                }
+                Ok(WsMessage::ApprovalResponse {
+                    approval_id,
+                    decision,
+                }) => {
+                    // Only the session that was asked may answer
+                    if let Err(e) = state
+                        .resolve_approval(&approval_id, Some(&session_id), decision)
+                        .await
+                    {
+                        tracing::warn!("Approval response [{}] rejected: {}", approval_id, e);
+                    }
+                }
+                Ok(WsMessage::CancelRun { run_id }) => {
+                    // Only runs on this session can be stopped from here
+                    let own = state
+                        .run_info(&run_id)
+                        .await
+                        .is_some_and(|run| run.session_id == session_id);
+                    if !own || state.cancel_run(&run_id, CANCELLED_BY_USER).await.is_none() {
+                        tracing::warn!("Cancel for unknown run [{}] ignored", run_id);
+                    }
+                }
+                Ok(WsMessage::RecordStart {}) => {
+                    tracing::info!("Recording started: session_id={}", session_id);
+                    state.start_recording(&session_id).await;
+                }
+                Ok(WsMessage::UserAction(action)) => {
+                    if let Err(e) = state.record_user_action(&session_id, action).await {
+                        tracing::warn!("User action ignored: {}", e);
+                    }
+                }
+                Ok(WsMessage::RecordStop { name, description }) => {
+                    let _ = tx.send(
+                        stop_recording(&state, &session_id, &principal, name, description).await,
+                    );
                 }
                 Ok(WsMessage::Unknown) => {
                     tracing::warn!("Unknown WebSocket message type");
@@ -161,8 +402,14 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
         }
     }
 
-    // Cleanup
+    // Cleanup: keep the session resumable for the grace period
     send_task.abort();
-    state.unregister_connection(&session_id).await;
-    tracing::info!("WebSocket disconnected: session_id={}", session_id);
+    if state.release_session(&session_id, &connection_id).await {
+        tracing::info!("WebSocket disconnected: session_id={}", session_id);
+        let grace = state.resume_grace;
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            state.expire_session(&session_id, &connection_id).await;
+        });
+    }
 }
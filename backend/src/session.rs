@@ -0,0 +1,176 @@
+//! Bookkeeping for WebSocket sessions that outlives a single socket.
+//!
+//! A session keeps its id across reconnects: the client presents the
+//! `resume_token` it received in `session_init` and the backend rebinds the
+//! session to the new socket, re-delivering every `ActionRequest` that was
+//! not answered yet. Disconnected sessions are kept for a grace period.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::models::ws::ActionCommand;
+use crate::tools::output::InteractiveElement;
+use crate::utils::time::now_millis;
+
+/// How long a disconnected session can be resumed by default.
+pub const DEFAULT_RESUME_GRACE: Duration = Duration::from_secs(60);
+
+/// Pages remembered per session.
+pub const PAGE_HISTORY_CAPACITY: usize = 50;
+
+/// An action request sent to the extension and not answered yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct InFlightAction {
+    pub request_id: String,
+    pub command: ActionCommand,
+    /// Agent run that sent the action; `None` for replays.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Unix milliseconds.
+    pub sent_at: u64,
+}
+
+#[derive(Debug)]
+pub struct SessionRecord {
+    pub resume_token: String,
+    /// Socket currently bound to the session.
+    pub connection_id: String,
+    /// When the current socket was bound, in Unix milliseconds.
+    pub connected_at: u64,
+    /// Last `Ping` from the extension, in Unix milliseconds.
+    pub last_ping: Option<u64>,
+    /// Set while no socket is bound.
+    pub disconnected_at: Option<Instant>,
+    /// Action requests sent but not yet answered, in send order.
+    pub unacked: Vec<InFlightAction>,
+    /// Principal that opened the session; only it may drive the session.
+    pub principal: String,
+    /// Signalled to close the bound socket (admin disconnect).
+    pub close: Arc<Notify>,
+}
+
+impl SessionRecord {
+    pub fn new(connection_id: String, principal: String) -> Self {
+        Self {
+            resume_token: new_resume_token(),
+            connection_id,
+            connected_at: now_millis(),
+            last_ping: None,
+            disconnected_at: None,
+            unacked: Vec::new(),
+            principal,
+            close: Arc::new(Notify::new()),
+        }
+    }
+}
+
+/// Operator view of a session (`GET /admin/sessions`).
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub principal: String,
+    pub connected: bool,
+    pub connected_at: u64,
+    pub last_ping: Option<u64>,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub in_flight: Vec<InFlightAction>,
+}
+
+pub fn new_resume_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Result of binding a socket to a session.
+#[derive(Debug)]
+pub struct BoundSession {
+    pub session_id: String,
+    pub resume_token: String,
+    pub resumed: bool,
+    /// Requests to send again on the new socket.
+    pub redeliver: Vec<InFlightAction>,
+    /// Notified when the socket must be closed.
+    pub close: Arc<Notify>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageVisit {
+    pub url: String,
+    pub title: Option<String>,
+    /// When the page was first seen, in Unix milliseconds.
+    pub at: u64,
+}
+
+/// What the backend last learned about a session's page, from the
+/// extension's `session_update` messages and from tool results.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PageSnapshot {
+    pub url: Option<String>,
+    pub title: Option<String>,
+    /// Browser tab the extension reported last.
+    pub tab_id: Option<i64>,
+    /// Elements from the latest `get_interactive_elements` call or the
+    /// snapshot sent with the agent request; cleared on navigation.
+    #[serde(skip)]
+    pub elements: Vec<InteractiveElement>,
+    /// Visited pages, oldest first; the last one is the current page.
+    pub history: VecDeque<PageVisit>,
+    /// Last update, in Unix milliseconds.
+    pub updated_at: Option<u64>,
+}
+
+impl PageSnapshot {
+    pub fn element(&self, ref_id: i32) -> Option<&InteractiveElement> {
+        self.elements.iter().find(|e| e.id == ref_id)
+    }
+
+    /// Whether `ref_id` may be a password-like field: unless the current
+    /// snapshot shows it isn't, it is treated as one.
+    pub fn may_be_password_field(&self, ref_id: i32) -> bool {
+        self.element(ref_id)
+            .is_none_or(InteractiveElement::is_password_like)
+    }
+
+    /// Records that the browser shows `url`. Element refs are dropped when
+    /// the page or the tab changes.
+    pub fn visit(&mut self, url: &str, title: Option<String>, tab_id: Option<i64>) {
+        let page_changed = self.url.as_deref().is_some_and(|current| current != url);
+        let tab_changed = tab_id.is_some() && self.tab_id.is_some() && tab_id != self.tab_id;
+        if page_changed || tab_changed {
+            // Element refs belong to the previous document
+            self.elements.clear();
+        }
+
+        let now = now_millis();
+        match self.history.back_mut() {
+            Some(last) if last.url == url => {
+                if title.is_some() {
+                    last.title = title.clone();
+                }
+            }
+            _ => {
+                if self.history.len() == PAGE_HISTORY_CAPACITY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(PageVisit {
+                    url: url.to_string(),
+                    title: title.clone(),
+                    at: now,
+                });
+            }
+        }
+
+        if page_changed || title.is_some() {
+            self.title = title;
+        }
+        self.url = Some(url.to_string());
+        if tab_id.is_some() {
+            self.tab_id = tab_id;
+        }
+        self.updated_at = Some(now);
+    }
+}
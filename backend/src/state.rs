@@ -1,26 +1,172 @@
-use crate::llm::GeminiProvider;
-use crate::models::ws::{ActionResult, WsMessage};
-use rig::client::ProviderClient;
-use rig::providers::gemini;
-use std::collections::HashMap;
+use crate::agent::AgentLimits;
+use crate::agent::run::{ActiveRun, RunInfo};
+use crate::approval::{
+    ApprovalDecision, ApprovalPolicy, ApprovalRequest, PendingApproval, validate_edit,
+};
+use crate::auth::{AuthConfig, Principal, constant_time_eq};
+use crate::dtos::events::StreamEvent;
+use crate::error::AppError;
+use crate::i18n::LocaleStore;
+use crate::llm::{LlmProvider, ModelSettings};
+use crate::macros::Recording;
+use crate::models::ws::{ActionCommand, ActionResult, UserAction, WsMessage};
+use crate::navigation::{NAVIGATION_AUDIT_CAPACITY, NavigationAuditEntry, NavigationPolicy};
+use crate::replay::ReplayStore;
+use crate::session::{
+    BoundSession, DEFAULT_RESUME_GRACE, InFlightAction, PageSnapshot, SessionRecord,
+    SessionSummary, new_resume_token,
+};
+use crate::store::traces::TraceSummary;
+use crate::store::{ConversationStore, InstructionStore, MacroStore, TraceStore};
+use crate::tasks::{TaskLimits, TaskQueue};
+use crate::tools::output::InteractiveElement;
+use crate::utils::time::now_millis;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{RwLock, mpsc, oneshot};
+use uuid::Uuid;
 
 pub struct AppState {
-    pub llm: GeminiProvider,
+    pub llm: Arc<dyn LlmProvider>,
     pub active_connections: Arc<RwLock<HashMap<String, mpsc::UnboundedSender<WsMessage>>>>,
     pub pending_actions: Arc<RwLock<HashMap<String, oneshot::Sender<ActionResult>>>>,
+    pub conversations: ConversationStore,
+    /// Workspace and per-user custom instructions.
+    pub instructions: InstructionStore,
+    /// JSONL traces of agent runs.
+    pub traces: TraceStore,
+    /// Saved macros per principal.
+    pub macros: MacroStore,
+    /// Macros being recorded, by session.
+    pub recordings: Arc<RwLock<HashMap<String, Recording>>>,
+    pub sessions: Arc<RwLock<HashMap<String, SessionRecord>>>,
+    pub resume_grace: Duration,
+    pub approval_policy: ApprovalPolicy,
+    pub pending_approvals: Arc<RwLock<HashMap<String, PendingApproval>>>,
+    /// Latest page knowledge per session, fed by tool results.
+    pub pages: Arc<RwLock<HashMap<String, PageSnapshot>>>,
+    /// SSE streams of agent runs in progress, by run id.
+    pub run_listeners: Arc<RwLock<HashMap<String, mpsc::UnboundedSender<StreamEvent>>>>,
+    /// Agent runs that can still be cancelled, by run id.
+    pub runs: Arc<RwLock<HashMap<String, ActiveRun>>>,
+    /// Background agent runs (`/tasks`).
+    pub tasks: TaskQueue,
+    /// Replays of recorded actions (`/sessions/{id}/replay`).
+    pub replays: ReplayStore,
+    /// Deployment-wide navigation rules.
+    pub navigation_policy: NavigationPolicy,
+    /// Extra navigation rules set for individual sessions.
+    pub navigation_policies: Arc<RwLock<HashMap<String, Arc<NavigationPolicy>>>>,
+    /// Most recent refused navigations, oldest first.
+    pub navigation_audit: Arc<RwLock<VecDeque<NavigationAuditEntry>>>,
+    pub auth: AuthConfig,
+    /// Origins allowed by CORS; empty allows no cross-origin browser calls.
+    pub cors_origins: Vec<String>,
+    pub agent_limits: AgentLimits,
+    /// Configured models, used to validate per-request overrides.
+    pub model_settings: ModelSettings,
+    /// Localized preamble templates and error messages, hot-reloaded.
+    pub locales: Arc<LocaleStore>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(llm: Arc<dyn LlmProvider>) -> Self {
+        let model_settings = ModelSettings::default().with_provider_defaults(llm.as_ref());
         Self {
-            llm: GeminiProvider::new(gemini::Client::from_env()),
+            llm,
             active_connections: Arc::new(RwLock::new(HashMap::new())),
             pending_actions: Arc::new(RwLock::new(HashMap::new())),
+            conversations: ConversationStore::in_memory(),
+            instructions: InstructionStore::in_memory(),
+            traces: TraceStore::in_memory(),
+            macros: MacroStore::in_memory(),
+            recordings: Arc::new(RwLock::new(HashMap::new())),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            resume_grace: DEFAULT_RESUME_GRACE,
+            approval_policy: ApprovalPolicy::default(),
+            pending_approvals: Arc::new(RwLock::new(HashMap::new())),
+            pages: Arc::new(RwLock::new(HashMap::new())),
+            run_listeners: Arc::new(RwLock::new(HashMap::new())),
+            runs: Arc::new(RwLock::new(HashMap::new())),
+            tasks: TaskQueue::default(),
+            replays: ReplayStore::default(),
+            navigation_policy: NavigationPolicy::default(),
+            navigation_policies: Arc::new(RwLock::new(HashMap::new())),
+            navigation_audit: Arc::new(RwLock::new(VecDeque::new())),
+            auth: AuthConfig::default(),
+            cors_origins: Vec::new(),
+            agent_limits: AgentLimits::default(),
+            model_settings,
+            locales: Arc::new(LocaleStore::default()),
         }
     }
 
+    pub fn with_conversation_store(mut self, store: ConversationStore) -> Self {
+        self.conversations = store;
+        self
+    }
+
+    pub fn with_instruction_store(mut self, store: InstructionStore) -> Self {
+        self.instructions = store;
+        self
+    }
+
+    pub fn with_trace_store(mut self, store: TraceStore) -> Self {
+        self.traces = store;
+        self
+    }
+
+    pub fn with_macro_store(mut self, store: MacroStore) -> Self {
+        self.macros = store;
+        self
+    }
+
+    pub fn with_resume_grace(mut self, grace: Duration) -> Self {
+        self.resume_grace = grace;
+        self
+    }
+
+    pub fn with_approval_policy(mut self, policy: ApprovalPolicy) -> Self {
+        self.approval_policy = policy;
+        self
+    }
+
+    pub fn with_navigation_policy(mut self, policy: NavigationPolicy) -> Self {
+        self.navigation_policy = policy;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn with_cors_origins(mut self, origins: Vec<String>) -> Self {
+        self.cors_origins = origins;
+        self
+    }
+
+    pub fn with_agent_limits(mut self, limits: AgentLimits) -> Self {
+        self.agent_limits = limits;
+        self
+    }
+
+    pub fn with_task_limits(mut self, limits: TaskLimits) -> Self {
+        self.tasks = TaskQueue::new(limits);
+        self
+    }
+
+    pub fn with_model_settings(mut self, settings: ModelSettings) -> Self {
+        self.model_settings = settings.with_provider_defaults(self.llm.as_ref());
+        self
+    }
+
+    pub fn with_locales(mut self, locales: LocaleStore) -> Self {
+        self.locales = Arc::new(locales);
+        self
+    }
+
     pub async fn register_connection(
         &self,
         session_id: String,
@@ -53,6 +199,7 @@ impl AppState {
     }
 
     pub async fn complete_pending_action(&self, request_id: &str, result: ActionResult) -> bool {
+        self.untrack_action(request_id).await;
         let mut pending = self.pending_actions.write().await;
         if let Some(sender) = pending.remove(request_id) {
             sender.send(result).is_ok()
@@ -60,4 +207,722 @@ impl AppState {
             false
         }
     }
+
+    /// Completes an action with a result from `session_id`'s socket.
+    /// Results for actions that weren't sent to the session are ignored.
+    pub async fn complete_session_action(&self, session_id: &str, result: ActionResult) -> bool {
+        let sent_here = {
+            let sessions = self.sessions.read().await;
+            sessions.get(session_id).is_some_and(|record| {
+                record
+                    .unacked
+                    .iter()
+                    .any(|action| action.request_id == result.request_id)
+            })
+        };
+        if !sent_here {
+            tracing::warn!(
+                "Ignoring ActionResult[{}] from session {}: not sent there",
+                result.request_id,
+                session_id
+            );
+            return false;
+        }
+        let request_id = result.request_id.clone();
+        self.complete_pending_action(&request_id, result).await
+    }
+
+    /// Drops a pending action without completing it (e.g. after a timeout).
+    pub async fn discard_pending_action(&self, request_id: &str) {
+        self.untrack_action(request_id).await;
+        self.pending_actions.write().await.remove(request_id);
+    }
+
+    // --- Session resumption ---
+
+    /// Binds a new socket to a session: resumes `resume` when its token
+    /// matches and `principal` owns it, otherwise creates a fresh session.
+    pub async fn bind_session(
+        &self,
+        connection_id: &str,
+        principal: &Principal,
+        resume: Option<(&str, &str)>,
+        sender: mpsc::UnboundedSender<WsMessage>,
+    ) -> BoundSession {
+        let bound = {
+            let mut sessions = self.sessions.write().await;
+            let resumable = resume.and_then(|(session_id, token)| {
+                sessions
+                    .get_mut(session_id)
+                    .filter(|record| {
+                        constant_time_eq(record.resume_token.as_bytes(), token.as_bytes())
+                            && record.principal == principal.name()
+                    })
+                    .map(|record| (session_id, record))
+            });
+
+            match resumable {
+                Some((session_id, record)) => {
+                    record.resume_token = new_resume_token();
+                    record.connection_id = connection_id.to_string();
+                    record.connected_at = now_millis();
+                    record.disconnected_at = None;
+                    BoundSession {
+                        session_id: session_id.to_string(),
+                        resume_token: record.resume_token.clone(),
+                        resumed: true,
+                        redeliver: record.unacked.clone(),
+                        close: record.close.clone(),
+                    }
+                }
+                None => {
+                    if let Some((session_id, _)) = resume {
+                        tracing::warn!("Rejected resume for session_id={}", session_id);
+                    }
+                    let session_id = Uuid::new_v4().to_string();
+                    let record =
+                        SessionRecord::new(connection_id.to_string(), principal.name().to_string());
+                    let bound = BoundSession {
+                        session_id: session_id.clone(),
+                        resume_token: record.resume_token.clone(),
+                        resumed: false,
+                        redeliver: Vec::new(),
+                        close: record.close.clone(),
+                    };
+                    sessions.insert(session_id, record);
+                    bound
+                }
+            }
+        };
+
+        self.register_connection(bound.session_id.clone(), sender)
+            .await;
+        bound
+    }
+
+    /// Marks the session disconnected if `connection_id` is still the bound
+    /// socket. Returns `false` when a newer socket already took over.
+    pub async fn release_session(&self, session_id: &str, connection_id: &str) -> bool {
+        let released = {
+            let mut sessions = self.sessions.write().await;
+            match sessions.get_mut(session_id) {
+                Some(record) if record.connection_id == connection_id => {
+                    record.disconnected_at = Some(Instant::now());
+                    true
+                }
+                Some(_) => false,
+                None => true,
+            }
+        };
+        if released {
+            self.unregister_connection(session_id).await;
+        }
+        released
+    }
+
+    /// Forgets a session whose grace period ran out without a resume and
+    /// fails its unanswered actions.
+    pub async fn expire_session(&self, session_id: &str, connection_id: &str) {
+        let unacked = {
+            let mut sessions = self.sessions.write().await;
+            let expired = sessions.get(session_id).is_some_and(|record| {
+                record.connection_id == connection_id && record.disconnected_at.is_some()
+            });
+            if !expired {
+                return;
+            }
+            sessions
+                .remove(session_id)
+                .map(|record| record.unacked)
+                .unwrap_or_default()
+        };
+
+        tracing::info!("Session expired: session_id={}", session_id);
+        self.forget_session(session_id).await;
+        self.fail_actions(unacked, "Browser session disconnected")
+            .await;
+    }
+
+    /// Drops everything kept per session besides its record. Waiting
+    /// approvals are rejected as dropped.
+    async fn forget_session(&self, session_id: &str) {
+        self.navigation_policies.write().await.remove(session_id);
+        self.recordings.write().await.remove(session_id);
+        self.pages.write().await.remove(session_id);
+        self.pending_approvals
+            .write()
+            .await
+            .retain(|_, p| p.request.session_id != session_id);
+    }
+
+    /// Fails each action so the waiting tool call returns `error`.
+    async fn fail_actions(&self, actions: Vec<InFlightAction>, error: &str) {
+        for action in actions {
+            self.complete_pending_action(
+                &action.request_id,
+                ActionResult {
+                    request_id: action.request_id.clone(),
+                    success: false,
+                    error: Some(error.to_string()),
+                    data: None,
+                },
+            )
+            .await;
+        }
+    }
+
+    // --- Administration ---
+
+    pub async fn record_ping(&self, session_id: &str) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(record) = sessions.get_mut(session_id) {
+            record.last_ping = Some(now_millis());
+        }
+    }
+
+    /// Every known session, connected or waiting to be resumed, oldest
+    /// connection first.
+    pub async fn session_summaries(&self) -> Vec<SessionSummary> {
+        let ids: Vec<String> = self.sessions.read().await.keys().cloned().collect();
+        let mut summaries = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(summary) = self.session_summary(&id).await {
+                summaries.push(summary);
+            }
+        }
+        summaries.sort_by_key(|s| s.connected_at);
+        summaries
+    }
+
+    pub async fn session_summary(&self, session_id: &str) -> Option<SessionSummary> {
+        let page = self.page_snapshot(session_id).await;
+        let sessions = self.sessions.read().await;
+        let record = sessions.get(session_id)?;
+        Some(SessionSummary {
+            session_id: session_id.to_string(),
+            principal: record.principal.clone(),
+            connected: record.disconnected_at.is_none(),
+            connected_at: record.connected_at,
+            last_ping: record.last_ping,
+            url: page.url,
+            title: page.title,
+            in_flight: record.unacked.clone(),
+        })
+    }
+
+    /// Closes the session's socket and forgets it, so it can't be resumed.
+    /// Its unanswered actions fail with `reason`. Returns `false` for
+    /// unknown sessions.
+    pub async fn disconnect_session(&self, session_id: &str, reason: &str) -> bool {
+        let Some(record) = self.sessions.write().await.remove(session_id) else {
+            return false;
+        };
+        tracing::warn!(
+            "Session disconnected by operator: session_id={}: {}",
+            session_id,
+            reason
+        );
+        record.close.notify_one();
+        self.unregister_connection(session_id).await;
+        self.forget_session(session_id).await;
+        self.fail_actions(
+            record.unacked,
+            &format!("Browser session disconnected by an operator: {}", reason),
+        )
+        .await;
+        true
+    }
+
+    /// Fails one unanswered action of `session_id` with `reason`; the agent
+    /// gets it as a `tool_failed` error. Returns `false` when the action
+    /// isn't pending on that session.
+    pub async fn cancel_action(&self, session_id: &str, request_id: &str, reason: &str) -> bool {
+        let action = {
+            let sessions = self.sessions.read().await;
+            sessions.get(session_id).and_then(|record| {
+                record
+                    .unacked
+                    .iter()
+                    .find(|a| a.request_id == request_id)
+                    .cloned()
+            })
+        };
+        let Some(action) = action else {
+            return false;
+        };
+        tracing::warn!(
+            "Action cancelled by operator[{}] session={}: {}",
+            request_id,
+            session_id,
+            reason
+        );
+        self.fail_actions(
+            vec![action],
+            &format!("Action cancelled by an operator: {}", reason),
+        )
+        .await;
+        true
+    }
+
+    /// Whether a socket is bound to the session, or `None` for unknown
+    /// (or expired) sessions.
+    pub async fn session_connected(&self, session_id: &str) -> Option<bool> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .map(|record| record.disconnected_at.is_none())
+    }
+
+    /// Fails with `Forbidden` when `session_id` belongs to another
+    /// principal. Unknown sessions pass; tools on them fail with
+    /// `NoConnection` anyway.
+    pub async fn authorize_session(
+        &self,
+        session_id: &str,
+        principal: &Principal,
+    ) -> Result<(), AppError> {
+        let sessions = self.sessions.read().await;
+        match sessions.get(session_id) {
+            Some(record) if record.principal != principal.name() => Err(AppError::Forbidden(
+                format!("session {} belongs to another principal", session_id),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The trace summary, if `principal` started the run or is an admin.
+    pub async fn authorized_trace(
+        &self,
+        run_id: &str,
+        principal: &Principal,
+    ) -> Result<TraceSummary, AppError> {
+        let summary = self
+            .traces
+            .summary(run_id)
+            .await
+            .ok_or_else(|| AppError::NotFound(format!("Trace {}", run_id)))?;
+        if summary.principal != principal.name() && self.auth.require_admin(principal).is_err() {
+            return Err(AppError::Forbidden(format!(
+                "trace {} belongs to another principal",
+                run_id
+            )));
+        }
+        Ok(summary)
+    }
+
+    pub async fn session_principal(&self, session_id: &str) -> Option<String> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .map(|record| record.principal.clone())
+    }
+
+    /// True while the session is disconnected but can still be resumed.
+    pub async fn is_session_resumable(&self, session_id: &str) -> bool {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .is_some_and(|record| record.disconnected_at.is_some())
+    }
+
+    /// Remembers an action sent to a session until its result arrives.
+    pub async fn track_action(
+        &self,
+        session_id: &str,
+        run_id: Option<&str>,
+        request_id: &str,
+        command: ActionCommand,
+    ) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(record) = sessions.get_mut(session_id) {
+            record.unacked.push(InFlightAction {
+                request_id: request_id.to_string(),
+                command,
+                run_id: run_id.map(str::to_string),
+                sent_at: now_millis(),
+            });
+        }
+    }
+
+    async fn untrack_action(&self, request_id: &str) {
+        let mut sessions = self.sessions.write().await;
+        for record in sessions.values_mut() {
+            record
+                .unacked
+                .retain(|action| action.request_id != request_id);
+        }
+    }
+
+    // --- Page snapshots ---
+
+    pub async fn page_snapshot(&self, session_id: &str) -> PageSnapshot {
+        let pages = self.pages.read().await;
+        pages.get(session_id).cloned().unwrap_or_default()
+    }
+
+    pub async fn record_page_url(&self, session_id: &str, url: &str) {
+        self.record_page_visit(session_id, url, None, None).await;
+    }
+
+    pub async fn record_page_visit(
+        &self,
+        session_id: &str,
+        url: &str,
+        title: Option<String>,
+        tab_id: Option<i64>,
+    ) {
+        let mut pages = self.pages.write().await;
+        pages
+            .entry(session_id.to_string())
+            .or_default()
+            .visit(url, title, tab_id);
+    }
+
+    /// Drops the known element refs after a navigation; returns whether
+    /// there were any, so the model can be told its refs are stale.
+    pub async fn invalidate_page_elements(&self, session_id: &str) -> bool {
+        let mut pages = self.pages.write().await;
+        pages
+            .get_mut(session_id)
+            .map(|page| std::mem::take(&mut page.elements))
+            .is_some_and(|elements| !elements.is_empty())
+    }
+
+    pub async fn record_page_elements(&self, session_id: &str, elements: Vec<InteractiveElement>) {
+        let mut pages = self.pages.write().await;
+        pages.entry(session_id.to_string()).or_default().elements = elements;
+    }
+
+    // --- Navigation policy ---
+
+    pub async fn session_navigation_policy(
+        &self,
+        session_id: &str,
+    ) -> Option<Arc<NavigationPolicy>> {
+        self.navigation_policies
+            .read()
+            .await
+            .get(session_id)
+            .cloned()
+    }
+
+    pub async fn set_session_navigation_policy(&self, session_id: &str, policy: NavigationPolicy) {
+        self.navigation_policies
+            .write()
+            .await
+            .insert(session_id.to_string(), Arc::new(policy));
+    }
+
+    /// Returns `false` when the session had no policy of its own.
+    pub async fn clear_session_navigation_policy(&self, session_id: &str) -> bool {
+        self.navigation_policies
+            .write()
+            .await
+            .remove(session_id)
+            .is_some()
+    }
+
+    pub async fn record_navigation_refusal(&self, entry: NavigationAuditEntry) {
+        let mut audit = self.navigation_audit.write().await;
+        if audit.len() == NAVIGATION_AUDIT_CAPACITY {
+            audit.pop_front();
+        }
+        audit.push_back(entry);
+    }
+
+    /// Refused navigations, optionally only for one session, oldest first.
+    pub async fn navigation_audit(&self, session_id: Option<&str>) -> Vec<NavigationAuditEntry> {
+        let audit = self.navigation_audit.read().await;
+        audit
+            .iter()
+            .filter(|e| session_id.is_none_or(|s| e.session_id == s))
+            .cloned()
+            .collect()
+    }
+
+    // --- Agent run listeners ---
+
+    /// Subscribes the SSE stream of run `run_id` to out-of-band events
+    /// (approvals) raised by its tool calls.
+    pub async fn register_run_listener(
+        &self,
+        run_id: &str,
+    ) -> mpsc::UnboundedReceiver<StreamEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.run_listeners
+            .write()
+            .await
+            .insert(run_id.to_string(), tx);
+        rx
+    }
+
+    pub async fn unregister_run_listener(&self, run_id: &str) {
+        self.run_listeners.write().await.remove(run_id);
+    }
+
+    pub async fn notify_run(&self, run_id: &str, event: StreamEvent) {
+        let listeners = self.run_listeners.read().await;
+        if let Some(listener) = listeners.get(run_id) {
+            let _ = listener.send(event);
+        }
+    }
+
+    // --- Macro recording ---
+
+    /// Starts (or restarts) recording on `session_id` from its current page.
+    pub async fn start_recording(&self, session_id: &str) {
+        let page_url = self.page_snapshot(session_id).await.url;
+        self.recordings
+            .write()
+            .await
+            .insert(session_id.to_string(), Recording::new(page_url.as_deref()));
+    }
+
+    /// Adds a user action to the session's recording, if one is running.
+    pub async fn record_user_action(
+        &self,
+        session_id: &str,
+        action: UserAction,
+    ) -> Result<(), AppError> {
+        let mut recordings = self.recordings.write().await;
+        let recording = recordings.get_mut(session_id).ok_or_else(|| {
+            AppError::InvalidRequest(format!("session {} is not recording", session_id))
+        })?;
+        recording.push(action)
+    }
+
+    pub async fn take_recording(&self, session_id: &str) -> Option<Recording> {
+        self.recordings.write().await.remove(session_id)
+    }
+
+    // --- Run cancellation ---
+
+    /// Registers a run on `session_id`. The receiver yields the reason once
+    /// the run is cancelled.
+    pub async fn start_run(
+        &self,
+        session_id: &str,
+        principal: &Principal,
+    ) -> (String, oneshot::Receiver<String>) {
+        let run_id = Uuid::new_v4().to_string();
+        let (cancel, cancelled) = oneshot::channel();
+        self.runs.write().await.insert(
+            run_id.clone(),
+            ActiveRun {
+                session_id: session_id.to_string(),
+                principal: principal.name().to_string(),
+                started_at: now_millis(),
+                cancel,
+            },
+        );
+        (run_id, cancelled)
+    }
+
+    pub async fn finish_run(&self, run_id: &str) {
+        self.runs.write().await.remove(run_id);
+    }
+
+    /// Whether any agent run is in progress on `session_id`.
+    pub async fn has_active_run(&self, session_id: &str) -> bool {
+        let runs = self.runs.read().await;
+        runs.values().any(|run| run.session_id == session_id)
+    }
+
+    pub async fn run_info(&self, run_id: &str) -> Option<RunInfo> {
+        let runs = self.runs.read().await;
+        runs.get(run_id).map(|run| RunInfo {
+            run_id: run_id.to_string(),
+            session_id: run.session_id.clone(),
+            principal: run.principal.clone(),
+            started_at: run.started_at,
+        })
+    }
+
+    /// Stops a run: wakes its stream with `reason`, fails the actions it is
+    /// still waiting on and drops its pending approvals. Other runs on the
+    /// same session are left alone. Returns `None` when the run already
+    /// ended.
+    pub async fn cancel_run(&self, run_id: &str, reason: &str) -> Option<RunInfo> {
+        let run = self.runs.write().await.remove(run_id)?;
+        tracing::info!(
+            "Run cancelled: run_id={} session_id={}: {}",
+            run_id,
+            run.session_id,
+            reason
+        );
+        let _ = run.cancel.send(reason.to_string());
+
+        let owned = |id: &Option<String>| id.as_deref() == Some(run_id);
+        let unacked = {
+            let mut sessions = self.sessions.write().await;
+            sessions
+                .get_mut(&run.session_id)
+                .map(|record| {
+                    let (run_actions, others) = std::mem::take(&mut record.unacked)
+                        .into_iter()
+                        .partition(|action| owned(&action.run_id));
+                    record.unacked = others;
+                    run_actions
+                })
+                .unwrap_or_default()
+        };
+        self.fail_actions(unacked, &format!("Run cancelled: {}", reason))
+            .await;
+        self.pending_approvals
+            .write()
+            .await
+            .retain(|_, p| !owned(&p.request.run_id));
+
+        Some(RunInfo {
+            run_id: run_id.to_string(),
+            session_id: run.session_id,
+            principal: run.principal,
+            started_at: run.started_at,
+        })
+    }
+
+    // --- Approvals ---
+
+    pub async fn register_approval(
+        &self,
+        request: ApprovalRequest,
+        responder: oneshot::Sender<ApprovalDecision>,
+    ) {
+        let mut approvals = self.pending_approvals.write().await;
+        approvals.insert(
+            request.approval_id.clone(),
+            PendingApproval { request, responder },
+        );
+    }
+
+    pub async fn take_approval(&self, approval_id: &str) -> Option<PendingApproval> {
+        self.pending_approvals.write().await.remove(approval_id)
+    }
+
+    /// Pending approvals, optionally only for one session, oldest first.
+    pub async fn list_approvals(&self, session_id: Option<&str>) -> Vec<ApprovalRequest> {
+        let approvals = self.pending_approvals.read().await;
+        let mut requests: Vec<ApprovalRequest> = approvals
+            .values()
+            .map(|p| p.request.clone())
+            .filter(|r| session_id.is_none_or(|s| r.session_id == s))
+            .collect();
+        requests.sort_by_key(|r| r.created_at);
+        requests
+    }
+
+    /// Delivers a decision. With `session_id`, the approval must belong to
+    /// that session (used for decisions arriving over a WebSocket).
+    pub async fn resolve_approval(
+        &self,
+        approval_id: &str,
+        session_id: Option<&str>,
+        decision: ApprovalDecision,
+    ) -> Result<(), AppError> {
+        let not_found = || AppError::NotFound(format!("Approval {}", approval_id));
+        let pending = {
+            let mut approvals = self.pending_approvals.write().await;
+            match approvals.get(approval_id) {
+                Some(p) if session_id.is_none_or(|s| p.request.session_id == s) => {
+                    validate_edit(&p.request.command, &decision)?;
+                }
+                _ => return Err(not_found()),
+            }
+            approvals.remove(approval_id).ok_or_else(not_found)?
+        };
+        // The tool call may have timed out in the meantime
+        pending.responder.send(decision).map_err(|_| not_found())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockProvider;
+
+    async fn bound_session(state: &AppState) -> (String, String) {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let bound = state
+            .bind_session("conn-1", &Principal::anonymous(), None, tx)
+            .await;
+        let session_id = bound.session_id;
+        state
+            .record_page_url(&session_id, "https://example.com/")
+            .await;
+        state
+            .recordings
+            .write()
+            .await
+            .insert(session_id.clone(), Recording::new(None));
+        let (responder, _) = oneshot::channel();
+        state
+            .register_approval(
+                ApprovalRequest {
+                    approval_id: "approval-1".to_string(),
+                    session_id: session_id.clone(),
+                    principal: None,
+                    run_id: None,
+                    command: ActionCommand::NavigateTo {
+                        url: "https://example.com/pay".to_string(),
+                    },
+                    reason: "test".to_string(),
+                    created_at: 0,
+                },
+                responder,
+            )
+            .await;
+        (session_id, "conn-1".to_string())
+    }
+
+    async fn assert_forgotten(state: &AppState, session_id: &str) {
+        assert!(!state.pages.read().await.contains_key(session_id));
+        assert!(!state.recordings.read().await.contains_key(session_id));
+        assert!(state.list_approvals(Some(session_id)).await.is_empty());
+        assert!(state.session_connected(session_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_disconnected_sessions_leave_nothing_behind() {
+        let provider = MockProvider::from_json(r#"{"turns": []}"#).unwrap();
+        let state = AppState::new(Arc::new(provider));
+
+        let (session_id, connection_id) = bound_session(&state).await;
+        state.release_session(&session_id, &connection_id).await;
+        state.expire_session(&session_id, &connection_id).await;
+        assert_forgotten(&state, &session_id).await;
+
+        let (session_id, _) = bound_session(&state).await;
+        assert!(state.disconnect_session(&session_id, "test").await);
+        assert_forgotten(&state, &session_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_results_only_complete_actions_sent_to_the_session() {
+        let provider = MockProvider::from_json(r#"{"turns": []}"#).unwrap();
+        let state = AppState::new(Arc::new(provider));
+        let (owner, _) = bound_session(&state).await;
+        let (other, _) = bound_session(&state).await;
+
+        let (tx, mut rx) = oneshot::channel();
+        state.register_pending_action("req-1".to_string(), tx).await;
+        state
+            .track_action(
+                &owner,
+                None,
+                "req-1",
+                ActionCommand::ClickElement { ref_id: 1 },
+            )
+            .await;
+        let result = |success| ActionResult {
+            request_id: "req-1".to_string(),
+            success,
+            error: None,
+            data: None,
+        };
+
+        assert!(!state.complete_session_action(&other, result(false)).await);
+        assert!(rx.try_recv().is_err());
+        assert!(state.complete_session_action(&owner, result(true)).await);
+        assert!(rx.try_recv().unwrap().success);
+    }
 }
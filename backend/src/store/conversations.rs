@@ -0,0 +1,275 @@
+//! File-backed conversation store.
+//!
+//! Each conversation is kept in memory and written through to
+//! `<dir>/<id>.json`. Without a directory the store is purely in-memory,
+//! which is what tests use. Ids are UUIDs generated here; anything else a
+//! client sends is rejected before it can reach a file name.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::conversation::{Conversation, ConversationSummary, StoredMessage};
+use crate::utils::time::now_millis;
+
+const TITLE_MAX_CHARS: usize = 60;
+
+fn validate_id(id: &str) -> Result<(), AppError> {
+    Uuid::parse_str(id)
+        .map(|_| ())
+        .map_err(|_| AppError::InvalidRequest(format!("Invalid conversation id: {}", id)))
+}
+
+pub struct ConversationStore {
+    dir: Option<PathBuf>,
+    conversations: RwLock<HashMap<String, Conversation>>,
+}
+
+impl ConversationStore {
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            conversations: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Opens (and creates if needed) a store directory, loading every
+    /// `*.json` conversation in it.
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut conversations = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|raw| {
+                    serde_json::from_str::<Conversation>(&raw).map_err(|e| e.to_string())
+                }) {
+                Ok(conversation) if validate_id(&conversation.id).is_ok() => {
+                    conversations.insert(conversation.id.clone(), conversation);
+                }
+                Ok(conversation) => tracing::warn!(
+                    "Skipping conversation file {}: invalid id {}",
+                    path.display(),
+                    conversation.id
+                ),
+                Err(e) => tracing::warn!("Skipping conversation file {}: {}", path.display(), e),
+            }
+        }
+        tracing::info!(
+            "Loaded {} conversations from {}",
+            conversations.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            dir: Some(dir),
+            conversations: RwLock::new(conversations),
+        })
+    }
+
+    /// File backing `id`; `None` when in-memory or `id` is not a UUID.
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        validate_id(id).ok()?;
+        Some(dir.join(format!("{}.json", id)))
+    }
+
+    async fn persist(&self, conversation: &Conversation) {
+        let Some(path) = self.path(&conversation.id) else {
+            return;
+        };
+        match serde_json::to_vec_pretty(conversation) {
+            Ok(bytes) => {
+                if let Err(e) = tokio::fs::write(&path, bytes).await {
+                    tracing::warn!("Failed to write {}: {}", path.display(), e);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to serialize conversation: {}", e),
+        }
+    }
+
+    /// Lists conversations, newest first, optionally only for one session.
+    pub async fn list(&self, session_id: Option<&str>) -> Vec<ConversationSummary> {
+        let conversations = self.conversations.read().await;
+        let mut summaries: Vec<ConversationSummary> = conversations
+            .values()
+            .filter(|c| session_id.is_none() || c.session_id.as_deref() == session_id)
+            .map(ConversationSummary::from)
+            .collect();
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        summaries
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Conversation> {
+        self.conversations.read().await.get(id).cloned()
+    }
+
+    /// Returns `principal`'s conversation with `id`, or a new empty one with
+    /// a generated id when `id` is `None`.
+    pub async fn get_or_create(
+        &self,
+        id: Option<&str>,
+        session_id: Option<&str>,
+        principal: &str,
+    ) -> Result<Conversation, AppError> {
+        if let Some(id) = id {
+            validate_id(id)?;
+            let existing = self
+                .get(id)
+                .await
+                .ok_or_else(|| AppError::NotFound(format!("Conversation {}", id)))?;
+            if existing.principal != principal {
+                return Err(AppError::Forbidden(format!(
+                    "conversation {} belongs to another principal",
+                    id
+                )));
+            }
+            return Ok(existing);
+        }
+
+        let now = now_millis();
+        let conversation = Conversation {
+            id: Uuid::new_v4().to_string(),
+            principal: principal.to_string(),
+            session_id: session_id.map(|s| s.to_string()),
+            title: String::new(),
+            forked_from: None,
+            created_at: now,
+            updated_at: now,
+            messages: Vec::new(),
+        };
+        self.conversations
+            .write()
+            .await
+            .insert(conversation.id.clone(), conversation.clone());
+        self.persist(&conversation).await;
+        Ok(conversation)
+    }
+
+    /// Appends messages as `(role, content)` pairs. The first user message
+    /// becomes the title.
+    pub async fn append(&self, id: &str, messages: Vec<(&str, String)>) -> Option<Conversation> {
+        let updated = {
+            let mut conversations = self.conversations.write().await;
+            let conversation = conversations.get_mut(id)?;
+            let now = now_millis();
+            for (role, content) in messages {
+                if conversation.title.is_empty() && role == "user" {
+                    conversation.title = content.chars().take(TITLE_MAX_CHARS).collect();
+                }
+                conversation.messages.push(StoredMessage {
+                    role: role.to_string(),
+                    content,
+                    created_at: now,
+                });
+            }
+            conversation.updated_at = now;
+            conversation.clone()
+        };
+        self.persist(&updated).await;
+        Some(updated)
+    }
+
+    /// Copies a conversation into a new one owned by `principal`, keeping
+    /// the first `at` messages (all of them when `None`).
+    pub async fn fork(&self, id: &str, at: Option<usize>, principal: &str) -> Option<Conversation> {
+        let source = self.get(id).await?;
+        let keep = at
+            .unwrap_or(source.messages.len())
+            .min(source.messages.len());
+        let now = now_millis();
+        let fork = Conversation {
+            id: Uuid::new_v4().to_string(),
+            principal: principal.to_string(),
+            session_id: source.session_id.clone(),
+            title: source.title.clone(),
+            forked_from: Some(source.id.clone()),
+            created_at: now,
+            updated_at: now,
+            messages: source.messages[..keep].to_vec(),
+        };
+        self.conversations
+            .write()
+            .await
+            .insert(fork.id.clone(), fork.clone());
+        self.persist(&fork).await;
+        Some(fork)
+    }
+
+    pub async fn delete(&self, id: &str) -> bool {
+        let removed = self.conversations.write().await.remove(id).is_some();
+        if removed
+            && let Some(path) = self.path(id)
+            && let Err(e) = tokio::fs::remove_file(&path).await
+        {
+            tracing::warn!("Failed to remove {}: {}", path.display(), e);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_append_sets_title_and_fork_truncates() {
+        let store = ConversationStore::in_memory();
+        let conversation = store
+            .get_or_create(None, Some("session-1"), "alice")
+            .await
+            .unwrap();
+        store
+            .append(
+                &conversation.id,
+                vec![
+                    ("user", "Open the pricing page".to_string()),
+                    ("assistant", "Done.".to_string()),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let stored = store.get(&conversation.id).await.unwrap();
+        assert_eq!(stored.title, "Open the pricing page");
+        assert_eq!(stored.messages.len(), 2);
+
+        let fork = store
+            .fork(&conversation.id, Some(1), "alice")
+            .await
+            .unwrap();
+        assert_eq!(fork.messages.len(), 1);
+        assert_eq!(fork.forked_from.as_deref(), Some(conversation.id.as_str()));
+        assert_eq!(store.list(Some("session-1")).await.len(), 2);
+
+        assert!(store.delete(&conversation.id).await);
+        assert!(store.get(&conversation.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_client_chosen_ids() {
+        let dir = std::env::temp_dir().join(format!("conversations-{}", Uuid::new_v4()));
+        let store = ConversationStore::open(&dir).unwrap();
+
+        let result = store.get_or_create(Some("../escape"), None, "alice").await;
+        assert!(matches!(result, Err(AppError::InvalidRequest(_))));
+        let unknown = Uuid::new_v4().to_string();
+        let result = store.get_or_create(Some(&unknown), None, "alice").await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        let owned = store.get_or_create(None, None, "alice").await.unwrap();
+        let result = store.get_or_create(Some(&owned.id), None, "bob").await;
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+        assert!(store.delete(&owned.id).await);
+        assert!(!store.delete("../escape").await);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+//! Custom instructions composed into the agent preamble.
+//!
+//! Three layers apply to a run, most general first:
+//! - workspace: the deployment's `instructions.workspace_file`,
+//! - user: set by each principal with `PUT /instructions` and written
+//!   through to `instructions.store_file`,
+//! - request: the `custom_instruction` of the request itself.
+//!
+//! Workspace and user sets may hold site instructions, which are added only
+//! while the session's page matches their host glob (and path prefix).
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+use url::Url;
+
+use crate::error::AppError;
+use crate::navigation::glob_match;
+
+/// Longest accepted instruction text, in characters.
+pub const MAX_INSTRUCTION_CHARS: usize = 4000;
+/// Most site instructions one set may hold.
+pub const MAX_SITE_INSTRUCTIONS: usize = 100;
+
+/// Instruction that only applies on matching pages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SiteInstruction {
+    /// Host glob, `*` matches any characters (e.g. `*.atlassian.net`).
+    pub host: String,
+    /// Restricts the instruction to paths starting with this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    pub instruction: String,
+}
+
+impl SiteInstruction {
+    fn matches(&self, url: &Url) -> bool {
+        url.host_str()
+            .is_some_and(|host| glob_match(&self.host.to_ascii_lowercase(), host))
+            && self
+                .path_prefix
+                .as_deref()
+                .is_none_or(|prefix| url.path().starts_with(prefix))
+    }
+}
+
+/// One layer: a general instruction plus site instructions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstructionSet {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instruction: Option<String>,
+    pub sites: Vec<SiteInstruction>,
+}
+
+impl InstructionSet {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let too_long = |text: &str| text.chars().count() > MAX_INSTRUCTION_CHARS;
+        if self.instruction.as_deref().is_some_and(too_long) {
+            return Err(AppError::InvalidRequest(format!(
+                "instruction is longer than {} characters",
+                MAX_INSTRUCTION_CHARS
+            )));
+        }
+        if self.sites.len() > MAX_SITE_INSTRUCTIONS {
+            return Err(AppError::InvalidRequest(format!(
+                "at most {} site instructions are allowed",
+                MAX_SITE_INSTRUCTIONS
+            )));
+        }
+        for site in &self.sites {
+            if site.host.trim().is_empty() {
+                return Err(AppError::InvalidRequest(
+                    "site instruction host must not be empty".to_string(),
+                ));
+            }
+            if site.instruction.trim().is_empty() || too_long(&site.instruction) {
+                return Err(AppError::InvalidRequest(format!(
+                    "site instruction for {} must have 1 to {} characters",
+                    site.host, MAX_INSTRUCTION_CHARS
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The general instruction, then every site instruction matching `url`.
+    fn applicable<'a>(&'a self, url: Option<&'a Url>) -> impl Iterator<Item = &'a str> {
+        let sites = self
+            .sites
+            .iter()
+            .filter(move |site| url.is_some_and(|url| site.matches(url)))
+            .map(|site| site.instruction.as_str());
+        self.instruction.as_deref().into_iter().chain(sites)
+    }
+}
+
+pub struct InstructionStore {
+    workspace: InstructionSet,
+    path: Option<PathBuf>,
+    /// User layer by principal name.
+    users: RwLock<HashMap<String, InstructionSet>>,
+}
+
+impl InstructionStore {
+    pub fn in_memory() -> Self {
+        Self {
+            workspace: InstructionSet::default(),
+            path: None,
+            users: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Opens the user instruction file, starting empty when it doesn't
+    /// exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let users = match std::fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            workspace: InstructionSet::default(),
+            path: Some(path),
+            users: RwLock::new(users),
+        })
+    }
+
+    pub fn with_workspace(mut self, workspace: InstructionSet) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
+    pub fn workspace(&self) -> &InstructionSet {
+        &self.workspace
+    }
+
+    pub async fn get(&self, principal: &str) -> Option<InstructionSet> {
+        self.users.read().await.get(principal).cloned()
+    }
+
+    pub async fn set(&self, principal: &str, set: InstructionSet) -> Result<(), AppError> {
+        set.validate()?;
+        let mut users = self.users.write().await;
+        let previous = users.insert(principal.to_string(), set);
+        if let Err(e) = self.persist(&users).await {
+            // Keep memory in line with the file
+            match previous {
+                Some(previous) => users.insert(principal.to_string(), previous),
+                None => users.remove(principal),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Returns `false` when `principal` has no instructions.
+    pub async fn remove(&self, principal: &str) -> Result<bool, AppError> {
+        let mut users = self.users.write().await;
+        let Some(previous) = users.remove(principal) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.persist(&users).await {
+            users.insert(principal.to_string(), previous);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    async fn persist(&self, users: &HashMap<String, InstructionSet>) -> Result<(), AppError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let bytes = serde_json::to_vec_pretty(users)
+            .map_err(|e| AppError::Internal(format!("Failed to serialize instructions: {}", e)))?;
+        tokio::fs::write(path, bytes).await.map_err(|e| {
+            tracing::warn!("Failed to write {}: {}", path.display(), e);
+            AppError::Internal(format!("Failed to save instructions: {}", e))
+        })
+    }
+
+    /// Joins every layer that applies to a run on `page_url`, or `None`
+    /// when there is nothing to add.
+    pub async fn compose(
+        &self,
+        principal: &str,
+        page_url: Option<&str>,
+        request: Option<&str>,
+    ) -> Option<String> {
+        let url = page_url.and_then(|u| Url::parse(u).ok());
+        let users = self.users.read().await;
+        let parts: Vec<&str> = self
+            .workspace
+            .applicable(url.as_ref())
+            .chain(
+                users
+                    .get(principal)
+                    .into_iter()
+                    .flat_map(|set| set.applicable(url.as_ref())),
+            )
+            .chain(request)
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(host: &str, path_prefix: Option<&str>, instruction: &str) -> SiteInstruction {
+        SiteInstruction {
+            host: host.into(),
+            path_prefix: path_prefix.map(Into::into),
+            instruction: instruction.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compose_layers_in_order() {
+        let store = InstructionStore::in_memory().with_workspace(InstructionSet {
+            instruction: Some("Never submit payments.".into()),
+            sites: vec![site(
+                "jira.company.com",
+                None,
+                "Always use the quick-create dialog.",
+            )],
+        });
+        store
+            .set(
+                "alice",
+                InstructionSet {
+                    instruction: Some("Be brief.".into()),
+                    sites: vec![site("*.github.com", Some("/settings"), "Ask first.")],
+                },
+            )
+            .await
+            .unwrap();
+
+        let composed = store
+            .compose(
+                "alice",
+                Some("https://jira.company.com/browse/X-1"),
+                Some("Answer in English."),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            composed,
+            "Never submit payments.\n\nAlways use the quick-create dialog.\n\nBe brief.\n\nAnswer in English."
+        );
+
+        let composed = store
+            .compose("alice", Some("https://gist.github.com/settings/keys"), None)
+            .await
+            .unwrap();
+        assert!(composed.ends_with("Be brief.\n\nAsk first."));
+
+        // Other principals only get the workspace layer
+        let composed = store.compose("bob", None, Some("  ")).await.unwrap();
+        assert_eq!(composed, "Never submit payments.");
+        assert!(
+            InstructionStore::in_memory()
+                .compose("bob", None, None)
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_sets_are_rejected() {
+        let store = InstructionStore::in_memory();
+        let invalid = [
+            InstructionSet {
+                instruction: Some("x".repeat(MAX_INSTRUCTION_CHARS + 1)),
+                sites: vec![],
+            },
+            InstructionSet {
+                instruction: None,
+                sites: vec![site(" ", None, "Hi")],
+            },
+            InstructionSet {
+                instruction: None,
+                sites: vec![site("example.com", None, "")],
+            },
+        ];
+        for set in invalid {
+            let error = store.set("alice", set).await.unwrap_err();
+            assert_eq!(error.code(), "invalid_request");
+        }
+        assert!(store.get("alice").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_are_reported_and_undone() {
+        let dir = std::env::temp_dir().join(format!("instructions-{}", uuid::Uuid::new_v4()));
+        let store = InstructionStore::open(dir.join("instructions.json")).unwrap();
+        let brief = InstructionSet {
+            instruction: Some("Be brief.".into()),
+            sites: vec![],
+        };
+        store.set("alice", brief.clone()).await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let error = store
+            .set("alice", InstructionSet::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "internal_error");
+        assert_eq!(store.get("alice").await, Some(brief));
+        assert!(store.remove("alice").await.is_err());
+        assert!(store.get("alice").await.is_some());
+    }
+}
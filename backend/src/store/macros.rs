@@ -0,0 +1,182 @@
+//! Saved macros, per principal, written through to `macros.store_file`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+use crate::error::AppError;
+use crate::macros::{Macro, params, validate};
+use crate::models::ws::ActionCommand;
+use crate::utils::time::now_millis;
+
+pub struct MacroStore {
+    path: Option<PathBuf>,
+    /// Macros by principal name, then by macro name.
+    users: RwLock<HashMap<String, BTreeMap<String, Macro>>>,
+}
+
+impl MacroStore {
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            users: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Opens the macro file, starting empty when it doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let users = match std::fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            path: Some(path),
+            users: RwLock::new(users),
+        })
+    }
+
+    /// The principal's macros, by name.
+    pub async fn list(&self, principal: &str) -> Vec<Macro> {
+        let users = self.users.read().await;
+        users
+            .get(principal)
+            .map(|macros| macros.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub async fn get(&self, principal: &str, name: &str) -> Option<Macro> {
+        let users = self.users.read().await;
+        users.get(principal)?.get(name).cloned()
+    }
+
+    /// Creates or replaces the principal's macro `name`.
+    pub async fn save(
+        &self,
+        principal: &str,
+        name: &str,
+        description: Option<String>,
+        steps: Vec<ActionCommand>,
+    ) -> Result<Macro, AppError> {
+        validate(name, description.as_deref(), &steps)?;
+        let mut users = self.users.write().await;
+        let macros = users.entry(principal.to_string()).or_default();
+        let now = now_millis();
+        let saved = Macro {
+            name: name.to_string(),
+            description,
+            params: params(&steps),
+            steps,
+            created_at: macros.get(name).map_or(now, |m| m.created_at),
+            updated_at: now,
+        };
+        let previous = macros.insert(name.to_string(), saved.clone());
+        if let Err(e) = self.persist(&users).await {
+            // Keep memory in line with the file
+            let macros = users.entry(principal.to_string()).or_default();
+            match previous {
+                Some(previous) => macros.insert(name.to_string(), previous),
+                None => macros.remove(name),
+            };
+            return Err(e);
+        }
+        Ok(saved)
+    }
+
+    /// Returns `false` when the principal has no macro `name`.
+    pub async fn remove(&self, principal: &str, name: &str) -> Result<bool, AppError> {
+        let mut users = self.users.write().await;
+        let Some(previous) = users
+            .get_mut(principal)
+            .and_then(|macros| macros.remove(name))
+        else {
+            return Ok(false);
+        };
+        if let Err(e) = self.persist(&users).await {
+            users
+                .entry(principal.to_string())
+                .or_default()
+                .insert(name.to_string(), previous);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    async fn persist(
+        &self,
+        users: &HashMap<String, BTreeMap<String, Macro>>,
+    ) -> Result<(), AppError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let bytes = serde_json::to_vec_pretty(users)
+            .map_err(|e| AppError::Internal(format!("Failed to serialize macros: {}", e)))?;
+        tokio::fs::write(path, bytes).await.map_err(|e| {
+            tracing::warn!("Failed to write {}: {}", path.display(), e);
+            AppError::Internal(format!("Failed to save macros: {}", e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_save_keeps_macros_per_principal() {
+        let store = MacroStore::in_memory();
+        let steps = vec![ActionCommand::TypeText {
+            ref_id: 1,
+            text: "{{email}}".into(),
+        }];
+        let first = store
+            .save("alice", "login", None, steps.clone())
+            .await
+            .unwrap();
+        assert_eq!(first.params, ["email"]);
+
+        let updated = store
+            .save("alice", "login", Some("Log in".into()), steps)
+            .await
+            .unwrap();
+        assert_eq!(updated.created_at, first.created_at);
+        assert!(store.get("bob", "login").await.is_none());
+        assert!(store.save("alice", "empty", None, vec![]).await.is_err());
+
+        assert!(store.remove("alice", "login").await.unwrap());
+        assert!(store.list("alice").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_are_reported_and_undone() {
+        let dir = std::env::temp_dir().join(format!("macros-{}", uuid::Uuid::new_v4()));
+        let store = MacroStore::open(dir.join("macros.json")).unwrap();
+        let steps = || {
+            vec![ActionCommand::NavigateTo {
+                url: "https://example.com/".into(),
+            }]
+        };
+        store
+            .save("alice", "home", Some("Home".into()), steps())
+            .await
+            .unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let error = store
+            .save("alice", "home", Some("Start page".into()), steps())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "internal_error");
+        let kept = store.get("alice", "home").await.unwrap();
+        assert_eq!(kept.description.as_deref(), Some("Home"));
+        assert!(store.save("alice", "other", None, steps()).await.is_err());
+        assert!(store.get("alice", "other").await.is_none());
+        assert!(store.remove("alice", "home").await.is_err());
+        assert!(store.get("alice", "home").await.is_some());
+    }
+}
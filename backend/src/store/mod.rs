@@ -0,0 +1,9 @@
+pub mod conversations;
+pub mod instructions;
+pub mod macros;
+pub mod traces;
+
+pub use conversations::ConversationStore;
+pub use instructions::InstructionStore;
+pub use macros::MacroStore;
+pub use traces::TraceStore;
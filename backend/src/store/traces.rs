@@ -0,0 +1,347 @@
+//! Run traces, one JSONL file per run.
+//!
+//! Lines are appended to `<dir>/<run_id>.jsonl` as the run goes; only the
+//! summaries are kept in memory. Without a directory the lines themselves
+//! are kept in memory, which is what tests use.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::models::trace::{TraceLine, TraceRecord};
+use crate::utils::time::now_millis;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TraceSummary {
+    pub run_id: String,
+    pub session_id: String,
+    pub principal: String,
+    pub query: String,
+    pub started_at: u64,
+    /// `None` while the run is still going (or if the server died mid-run).
+    pub finished_at: Option<u64>,
+    pub lines: usize,
+}
+
+#[derive(Default)]
+struct StoredTrace {
+    summary: TraceSummary,
+    /// Only used by the in-memory store.
+    lines: Vec<String>,
+    /// Lines waiting to be written to the run's file, oldest first.
+    pending: Vec<String>,
+    /// Held while writing or reading the run's file.
+    file: Arc<Mutex<()>>,
+}
+
+pub struct TraceStore {
+    dir: Option<PathBuf>,
+    traces: RwLock<HashMap<String, StoredTrace>>,
+}
+
+impl TraceStore {
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            traces: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Opens (and creates if needed) a trace directory, indexing every
+    /// `*.jsonl` trace in it.
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut traces = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+                continue;
+            }
+            match Self::index_file(&path) {
+                Ok(Some(summary)) => {
+                    traces.insert(
+                        summary.run_id.clone(),
+                        StoredTrace {
+                            summary,
+                            ..Default::default()
+                        },
+                    );
+                }
+                Ok(None) => tracing::warn!(
+                    "Skipping trace file {}: no run_started line",
+                    path.display()
+                ),
+                Err(e) => tracing::warn!("Skipping trace file {}: {}", path.display(), e),
+            }
+        }
+        tracing::info!("Loaded {} traces from {}", traces.len(), dir.display());
+
+        Ok(Self {
+            dir: Some(dir),
+            traces: RwLock::new(traces),
+        })
+    }
+
+    fn index_file(path: &std::path::Path) -> std::io::Result<Option<TraceSummary>> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut summary: Option<TraceSummary> = None;
+        for line in reader.lines() {
+            let line = line?;
+            let Ok(parsed) = serde_json::from_str::<TraceLine>(&line) else {
+                continue;
+            };
+            match (&mut summary, parsed.record) {
+                (
+                    None,
+                    TraceRecord::RunStarted {
+                        run_id,
+                        session_id,
+                        principal,
+                        query,
+                        ..
+                    },
+                ) => {
+                    summary = Some(TraceSummary {
+                        run_id,
+                        session_id,
+                        principal,
+                        query,
+                        started_at: parsed.at,
+                        finished_at: None,
+                        lines: 1,
+                    });
+                }
+                (Some(summary), record) => {
+                    summary.lines += 1;
+                    if matches!(record, TraceRecord::RunFinished { .. }) {
+                        summary.finished_at = Some(parsed.at);
+                    }
+                }
+                (None, _) => {}
+            }
+        }
+        Ok(summary)
+    }
+
+    fn path(&self, run_id: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.jsonl", run_id)))
+    }
+
+    /// Appends a line to a run's trace. A trace is started by its
+    /// `RunStarted` record; anything else for an unknown run is dropped.
+    pub async fn append(&self, run_id: &str, record: TraceRecord) {
+        let line = TraceLine {
+            at: now_millis(),
+            record,
+        };
+        let json = match serde_json::to_string(&line) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::warn!("Failed to serialize trace line: {}", e);
+                return;
+            }
+        };
+
+        let mut traces = self.traces.write().await;
+        let trace = match (traces.get_mut(run_id), &line.record) {
+            (Some(trace), _) => trace,
+            (
+                None,
+                TraceRecord::RunStarted {
+                    session_id,
+                    principal,
+                    query,
+                    ..
+                },
+            ) => traces.entry(run_id.to_string()).or_insert(StoredTrace {
+                summary: TraceSummary {
+                    run_id: run_id.to_string(),
+                    session_id: session_id.clone(),
+                    principal: principal.clone(),
+                    query: query.clone(),
+                    started_at: line.at,
+                    finished_at: None,
+                    lines: 0,
+                },
+                ..Default::default()
+            }),
+            (None, _) => return,
+        };
+        trace.summary.lines += 1;
+        if matches!(line.record, TraceRecord::RunFinished { .. }) {
+            trace.summary.finished_at = Some(line.at);
+        }
+
+        let Some(path) = self.path(run_id) else {
+            trace.lines.push(json);
+            return;
+        };
+        trace.pending.push(json);
+        let file = trace.file.clone();
+        drop(traces);
+
+        // Whoever holds the run's file writes everything queued so far, so
+        // lines land in order without the store lock held during I/O
+        let _file = file.lock().await;
+        let pending = match self.traces.write().await.get_mut(run_id) {
+            Some(trace) => std::mem::take(&mut trace.pending),
+            None => return,
+        };
+        if pending.is_empty() {
+            return;
+        }
+        let written = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            let text: String = pending.iter().map(|l| format!("{}\n", l)).collect();
+            file.write_all(text.as_bytes()).await
+        };
+        if let Err(e) = written.await {
+            tracing::warn!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    /// Lists traces, newest first, optionally only for one session.
+    pub async fn list(&self, session_id: Option<&str>) -> Vec<TraceSummary> {
+        let traces = self.traces.read().await;
+        let mut summaries: Vec<TraceSummary> = traces
+            .values()
+            .filter(|t| session_id.is_none_or(|id| t.summary.session_id == id))
+            .map(|t| t.summary.clone())
+            .collect();
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.started_at));
+        summaries
+    }
+
+    pub async fn summary(&self, run_id: &str) -> Option<TraceSummary> {
+        self.traces
+            .read()
+            .await
+            .get(run_id)
+            .map(|t| t.summary.clone())
+    }
+
+    /// The raw JSONL of a trace.
+    pub async fn read(&self, run_id: &str) -> Option<String> {
+        let file = {
+            let traces = self.traces.read().await;
+            let trace = traces.get(run_id)?;
+            if self.dir.is_none() {
+                return Some(trace.lines.iter().map(|l| format!("{}\n", l)).collect());
+            }
+            trace.file.clone()
+        };
+        let path = self.path(run_id)?;
+        let _file = file.lock().await;
+        match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => Some(raw),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// The parsed lines of a trace; unparseable lines are skipped.
+    pub async fn lines(&self, run_id: &str) -> Option<Vec<TraceLine>> {
+        let raw = self.read(run_id).await?;
+        Some(
+            raw.lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(run_id: &str) -> TraceRecord {
+        TraceRecord::RunStarted {
+            run_id: run_id.into(),
+            session_id: "session-1".into(),
+            principal: "alice".into(),
+            query: "Open the pricing page".into(),
+            model: "mock".into(),
+            preamble: String::new(),
+            page_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_append_and_reopen_from_disk() {
+        let dir = std::env::temp_dir().join(format!("traces-{}", uuid::Uuid::new_v4()));
+        let store = TraceStore::open(&dir).unwrap();
+
+        // Lines for a run that never started are dropped.
+        store
+            .append("run-1", TraceRecord::ModelText { text: "hi".into() })
+            .await;
+        assert!(store.read("run-1").await.is_none());
+
+        store.append("run-1", started("run-1")).await;
+        store
+            .append(
+                "run-1",
+                TraceRecord::ModelText {
+                    text: "Done.".into(),
+                },
+            )
+            .await;
+        store
+            .append("run-1", TraceRecord::RunFinished { duration_ms: 5 })
+            .await;
+
+        let lines = store.lines("run-1").await.unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(matches!(lines[1].record, TraceRecord::ModelText { .. }));
+
+        let reopened = TraceStore::open(&dir).unwrap();
+        let summary = reopened.summary("run-1").await.unwrap();
+        assert_eq!(summary.lines, 3);
+        assert!(summary.finished_at.is_some());
+        assert_eq!(reopened.list(Some("session-1")).await.len(), 1);
+        assert!(reopened.list(Some("session-2")).await.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_appends_all_reach_the_file() {
+        let dir = std::env::temp_dir().join(format!("traces-{}", uuid::Uuid::new_v4()));
+        let store = Arc::new(TraceStore::open(&dir).unwrap());
+        store.append("run-1", started("run-1")).await;
+
+        let appends: Vec<_> = (0..20)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let text = format!("turn {}", i);
+                    store.append("run-1", TraceRecord::ModelText { text }).await;
+                })
+            })
+            .collect();
+        for append in appends {
+            append.await.unwrap();
+        }
+
+        let lines = store.lines("run-1").await.unwrap();
+        assert_eq!(lines.len(), 21);
+        assert!(matches!(lines[0].record, TraceRecord::RunStarted { .. }));
+        assert_eq!(store.summary("run-1").await.unwrap().lines, 21);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
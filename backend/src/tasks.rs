@@ -0,0 +1,554 @@
+//! Background agent runs.
+//!
+//! `POST /tasks` queues an agent request against a session and returns at
+//! once. The run starts when a slot is free: at most
+//! `tasks.max_concurrent` tasks run at a time, and at most
+//! `tasks.max_per_session` on one session. Progress is kept in memory and
+//! can be polled with `GET /tasks/{id}` or followed with
+//! `GET /tasks/{id}/events`, which replays everything so far before going
+//! live.
+
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, RwLock, Semaphore, broadcast};
+use uuid::Uuid;
+
+use crate::agent::run::{CANCELLED_BY_USER, RunOutput, start_agent_run};
+use crate::auth::Principal;
+use crate::dtos::AgentRequest;
+use crate::dtos::events::{StreamEvent, ToolEvent};
+use crate::error::{AppError, ErrorPayload};
+use crate::i18n::Messages;
+use crate::llm::TokenUsage;
+use crate::state::AppState;
+use crate::utils::time::now_millis;
+
+/// Finished tasks kept for polling; the oldest are dropped first.
+pub const TASK_HISTORY_CAPACITY: usize = 500;
+/// Live events buffered per follower before it starts missing some.
+const TASK_EVENT_BUFFER: usize = 256;
+
+/// Concurrency limits (`[tasks]` config).
+#[derive(Debug, Clone, Copy)]
+pub struct TaskLimits {
+    pub max_concurrent: usize,
+    pub max_per_session: usize,
+}
+
+impl Default for TaskLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 4,
+            max_per_session: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl TaskStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled
+        )
+    }
+}
+
+/// Everything a task produced, in order. `/tasks/{id}/events` replays it.
+#[derive(Debug, Clone)]
+pub enum TaskLogEntry {
+    Text(String),
+    Event(StreamEvent),
+    Error(ErrorPayload),
+    /// Last entry of every task.
+    Finished(TaskStatus),
+}
+
+/// `GET /tasks/{id}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Task {
+    pub task_id: String,
+    pub session_id: String,
+    pub principal: String,
+    pub query: String,
+    pub status: TaskStatus,
+    /// Unix milliseconds.
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    /// Set once running; cancels like any other run.
+    pub run_id: Option<String>,
+    pub conversation_id: Option<String>,
+    /// Finished tool calls.
+    pub steps: Vec<ToolEvent>,
+    /// Model text so far.
+    pub answer: String,
+    pub error: Option<ErrorPayload>,
+    pub usage: Option<TokenUsage>,
+}
+
+struct TaskEntry {
+    task: Task,
+    log: Vec<TaskLogEntry>,
+    events: broadcast::Sender<TaskLogEntry>,
+    /// Signalled by [`TaskQueue::cancel`]; stops the wait for a slot or the
+    /// run itself.
+    cancel: Arc<Notify>,
+}
+
+impl TaskEntry {
+    /// Applies `entry` to the summary, logs it and tells followers.
+    fn record(&mut self, entry: TaskLogEntry) {
+        let task = &mut self.task;
+        match &entry {
+            TaskLogEntry::Text(text) => task.answer.push_str(text),
+            TaskLogEntry::Event(StreamEvent::RunStarted { run_id }) => {
+                task.run_id = Some(run_id.clone());
+            }
+            TaskLogEntry::Event(StreamEvent::Conversation { conversation_id }) => {
+                task.conversation_id = Some(conversation_id.clone());
+            }
+            TaskLogEntry::Event(StreamEvent::Tool(tool)) if tool.duration_ms.is_some() => {
+                task.steps.push(tool.clone());
+            }
+            TaskLogEntry::Event(StreamEvent::Usage(usage)) => task.usage = Some(*usage),
+            TaskLogEntry::Event(StreamEvent::Cancelled { .. }) => {
+                task.status = TaskStatus::Cancelled;
+            }
+            TaskLogEntry::Event(_) => {}
+            TaskLogEntry::Error(error) => task.error = Some(error.clone()),
+            TaskLogEntry::Finished(status) => {
+                task.status = *status;
+                task.finished_at = Some(now_millis());
+            }
+        }
+        self.log.push(entry.clone());
+        // Nobody may be following
+        let _ = self.events.send(entry);
+    }
+}
+
+/// What a follower gets: the log so far, then live entries.
+pub struct TaskSubscription {
+    pub backlog: Vec<TaskLogEntry>,
+    pub live: broadcast::Receiver<TaskLogEntry>,
+}
+
+pub struct TaskQueue {
+    limits: TaskLimits,
+    global: Arc<Semaphore>,
+    sessions: Mutex<HashMap<String, Arc<Semaphore>>>,
+    tasks: RwLock<HashMap<String, TaskEntry>>,
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new(TaskLimits::default())
+    }
+}
+
+impl TaskQueue {
+    pub fn new(limits: TaskLimits) -> Self {
+        Self {
+            limits,
+            global: Arc::new(Semaphore::new(limits.max_concurrent)),
+            sessions: Mutex::new(HashMap::new()),
+            tasks: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn session_slots(&self, session_id: &str) -> Arc<Semaphore> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.limits.max_per_session)))
+            .clone()
+    }
+
+    /// Forgets the session's slots once no task holds them any more.
+    fn release_session_slots(&self, session_id: &str, slots: Arc<Semaphore>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        drop(slots);
+        if sessions
+            .get(session_id)
+            .is_some_and(|slots| Arc::strong_count(slots) == 1)
+        {
+            sessions.remove(session_id);
+        }
+    }
+
+    pub async fn get(&self, task_id: &str) -> Option<Task> {
+        self.tasks.read().await.get(task_id).map(|e| e.task.clone())
+    }
+
+    /// Tasks of `principal`, optionally on one session, newest first.
+    pub async fn list(&self, principal: &str, session_id: Option<&str>) -> Vec<Task> {
+        let tasks = self.tasks.read().await;
+        let mut list: Vec<Task> = tasks
+            .values()
+            .map(|e| &e.task)
+            .filter(|t| t.principal == principal)
+            .filter(|t| session_id.is_none_or(|s| t.session_id == s))
+            .cloned()
+            .collect();
+        list.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        list
+    }
+
+    pub async fn subscribe(&self, task_id: &str) -> Option<TaskSubscription> {
+        let tasks = self.tasks.read().await;
+        let entry = tasks.get(task_id)?;
+        Some(TaskSubscription {
+            backlog: entry.log.clone(),
+            live: entry.events.subscribe(),
+        })
+    }
+
+    /// Cancels a queued or running task. Returns `false` once it has
+    /// finished.
+    pub async fn cancel(&self, task_id: &str) -> bool {
+        let tasks = self.tasks.read().await;
+        match tasks.get(task_id) {
+            Some(entry) if !entry.task.status.is_finished() => {
+                entry.cancel.notify_one();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn record(&self, task_id: &str, entry: TaskLogEntry) {
+        if let Some(task) = self.tasks.write().await.get_mut(task_id) {
+            task.record(entry);
+        }
+    }
+
+    async fn set_running(&self, task_id: &str) {
+        if let Some(entry) = self.tasks.write().await.get_mut(task_id) {
+            entry.task.status = TaskStatus::Running;
+            entry.task.started_at = Some(now_millis());
+        }
+    }
+
+    /// Fails with `RateLimited` when the history is full of unfinished
+    /// tasks.
+    async fn insert(&self, task: Task) -> Result<Arc<Notify>, AppError> {
+        let cancel = Arc::new(Notify::new());
+        let (events, _) = broadcast::channel(TASK_EVENT_BUFFER);
+        let mut tasks = self.tasks.write().await;
+        if tasks.len() >= TASK_HISTORY_CAPACITY {
+            let oldest = tasks
+                .values()
+                .filter(|e| e.task.status.is_finished())
+                .min_by_key(|e| e.task.created_at)
+                .map(|e| e.task.task_id.clone());
+            let Some(id) = oldest else {
+                return Err(AppError::RateLimited(format!(
+                    "{} tasks are already queued or running",
+                    TASK_HISTORY_CAPACITY
+                )));
+            };
+            tasks.remove(&id);
+        }
+        tasks.insert(
+            task.task_id.clone(),
+            TaskEntry {
+                task,
+                log: Vec::new(),
+                events,
+                cancel: cancel.clone(),
+            },
+        );
+        Ok(cancel)
+    }
+}
+
+/// Queues `request` and returns the task right away. The request is
+/// checked here, so an invalid one fails without creating a task.
+pub async fn submit(
+    state: &Arc<AppState>,
+    principal: Principal,
+    request: AgentRequest,
+    messages: Arc<Messages>,
+) -> Result<Task, AppError> {
+    let Some(session_id) = request.session_id.clone() else {
+        return Err(AppError::InvalidRequest(
+            "session_id is required".to_string(),
+        ));
+    };
+    state
+        .model_settings
+        .check_overrides(&request.generation_overrides())?;
+    state.agent_limits.depth(request.max_depth)?;
+    state.authorize_session(&session_id, &principal).await?;
+
+    let task = Task {
+        task_id: Uuid::new_v4().to_string(),
+        session_id: session_id.clone(),
+        principal: principal.name().to_string(),
+        query: request.query.clone(),
+        status: TaskStatus::Queued,
+        created_at: now_millis(),
+        started_at: None,
+        finished_at: None,
+        run_id: None,
+        conversation_id: None,
+        steps: Vec::new(),
+        answer: String::new(),
+        error: None,
+        usage: None,
+    };
+    let cancel = state.tasks.insert(task.clone()).await?;
+    tracing::info!(
+        "Task queued: task_id={} session_id={}",
+        task.task_id,
+        session_id
+    );
+
+    tokio::spawn(run_task(
+        state.clone(),
+        task.task_id.clone(),
+        session_id,
+        principal,
+        request,
+        messages,
+        cancel,
+    ));
+    Ok(task)
+}
+
+/// Runs the task once it gets a slot and records how it ended.
+async fn run_task(
+    state: Arc<AppState>,
+    task_id: String,
+    session_id: String,
+    principal: Principal,
+    request: AgentRequest,
+    messages: Arc<Messages>,
+    cancel: Arc<Notify>,
+) {
+    let queue = &state.tasks;
+    let slots = queue.session_slots(&session_id);
+    let status = run_in_slot(
+        &state,
+        &task_id,
+        slots.clone(),
+        principal,
+        request,
+        messages,
+        cancel,
+    )
+    .await;
+    queue.release_session_slots(&session_id, slots);
+    tracing::info!("Task finished: task_id={} status={:?}", task_id, status);
+    queue.record(&task_id, TaskLogEntry::Finished(status)).await;
+}
+
+/// Waits for a slot, then drives the run and records what it produces.
+async fn run_in_slot(
+    state: &Arc<AppState>,
+    task_id: &str,
+    slots: Arc<Semaphore>,
+    principal: Principal,
+    request: AgentRequest,
+    messages: Arc<Messages>,
+    cancel: Arc<Notify>,
+) -> TaskStatus {
+    let queue = &state.tasks;
+    // Wait for a slot on the session, then a global one
+    let permits = tokio::select! {
+        permits = async {
+            let session = slots.acquire_owned().await;
+            let global = queue.global.clone().acquire_owned().await;
+            (session, global)
+        } => permits,
+        _ = cancel.notified() => return TaskStatus::Cancelled,
+    };
+    let (Ok(_session), Ok(_global)) = permits else {
+        return TaskStatus::Failed;
+    };
+
+    queue.set_running(task_id).await;
+    tracing::info!("Task started: task_id={}", task_id);
+    match start_agent_run(state.clone(), principal, request, messages.clone()).await {
+        Ok(mut outputs) => {
+            let mut status = TaskStatus::Completed;
+            let mut run_id: Option<String> = None;
+            let mut cancel_requested = false;
+            loop {
+                let output = tokio::select! {
+                    output = outputs.next() => match output {
+                        Some(output) => output,
+                        None => break,
+                    },
+                    _ = cancel.notified(), if !cancel_requested => {
+                        cancel_requested = true;
+                        if let Some(run_id) = &run_id {
+                            state.cancel_run(run_id, CANCELLED_BY_USER).await;
+                        }
+                        continue;
+                    }
+                };
+                let entry = match output {
+                    RunOutput::Text(text) => TaskLogEntry::Text(text),
+                    RunOutput::Event(event) => {
+                        match &event {
+                            // The first event; a cancel may already wait
+                            StreamEvent::RunStarted { run_id: id } => {
+                                if cancel_requested {
+                                    state.cancel_run(id, CANCELLED_BY_USER).await;
+                                }
+                                run_id = Some(id.clone());
+                            }
+                            StreamEvent::Cancelled { .. } => {
+                                status = TaskStatus::Cancelled;
+                            }
+                            _ => {}
+                        }
+                        TaskLogEntry::Event(event)
+                    }
+                    RunOutput::Error(error) => {
+                        status = TaskStatus::Failed;
+                        TaskLogEntry::Error(messages.error_payload(&error))
+                    }
+                };
+                queue.record(task_id, entry).await;
+            }
+            status
+        }
+        Err(error) => {
+            let payload = messages.error_payload(&error);
+            queue.record(task_id, TaskLogEntry::Error(payload)).await;
+            TaskStatus::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtos::events::ToolStatus;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_record_summarizes_the_log() {
+        let queue = TaskQueue::default();
+        queue
+            .insert(Task {
+                task_id: "t1".into(),
+                session_id: "s1".into(),
+                principal: "alice".into(),
+                query: "click login".into(),
+                status: TaskStatus::Running,
+                created_at: 1,
+                started_at: Some(1),
+                finished_at: None,
+                run_id: None,
+                conversation_id: None,
+                steps: vec![],
+                answer: String::new(),
+                error: None,
+                usage: None,
+            })
+            .await
+            .unwrap();
+        let mut follower = queue.subscribe("t1").await.unwrap().live;
+
+        let entries = [
+            TaskLogEntry::Event(StreamEvent::RunStarted {
+                run_id: "r1".into(),
+            }),
+            TaskLogEntry::Text("Clicked ".into()),
+            TaskLogEntry::Event(StreamEvent::Tool(ToolEvent::calling(
+                "c1".into(),
+                "click_element".into(),
+                json!({"ref": 1}),
+            ))),
+            TaskLogEntry::Event(StreamEvent::Tool(ToolEvent::finished(
+                "c1".into(),
+                "click_element".into(),
+                r#"{"clicked":1}"#.into(),
+                Duration::from_millis(5),
+            ))),
+            TaskLogEntry::Text("it.".into()),
+            TaskLogEntry::Finished(TaskStatus::Completed),
+        ];
+        for entry in entries {
+            queue.record("t1", entry).await;
+        }
+
+        let task = queue.get("t1").await.unwrap();
+        assert_eq!(task.status, TaskStatus::Completed);
+        assert_eq!(task.run_id.as_deref(), Some("r1"));
+        assert_eq!(task.answer, "Clicked it.");
+        assert_eq!(task.steps.len(), 1);
+        assert_eq!(task.steps[0].status, ToolStatus::Completed);
+        assert!(task.finished_at.is_some());
+
+        // Followers see the same entries, and late ones get the backlog
+        assert!(matches!(
+            follower.recv().await.unwrap(),
+            TaskLogEntry::Event(StreamEvent::RunStarted { .. })
+        ));
+        assert_eq!(queue.subscribe("t1").await.unwrap().backlog.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_history_full_of_unfinished_tasks_rejects_more() {
+        let queue = TaskQueue::default();
+        let task = |i: usize, status| Task {
+            task_id: format!("t{}", i),
+            session_id: "s1".into(),
+            principal: "alice".into(),
+            query: "click login".into(),
+            status,
+            created_at: i as u64,
+            started_at: None,
+            finished_at: None,
+            run_id: None,
+            conversation_id: None,
+            steps: vec![],
+            answer: String::new(),
+            error: None,
+            usage: None,
+        };
+        queue.insert(task(0, TaskStatus::Completed)).await.unwrap();
+        for i in 1..TASK_HISTORY_CAPACITY {
+            queue.insert(task(i, TaskStatus::Queued)).await.unwrap();
+        }
+
+        // The finished task makes room once, then nothing can be dropped
+        let next = TASK_HISTORY_CAPACITY;
+        queue.insert(task(next, TaskStatus::Queued)).await.unwrap();
+        assert!(queue.get("t0").await.is_none());
+        assert!(matches!(
+            queue.insert(task(next + 1, TaskStatus::Queued)).await,
+            Err(AppError::RateLimited(_))
+        ));
+    }
+
+    #[test]
+    fn test_session_slots_go_with_the_last_task() {
+        let queue = TaskQueue::default();
+        let first = queue.session_slots("s1");
+        let second = queue.session_slots("s1");
+
+        queue.release_session_slots("s1", first);
+        assert!(queue.sessions.lock().unwrap().contains_key("s1"));
+        queue.release_session_slots("s1", second);
+        assert!(queue.sessions.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+//! Headless stand-in for the Chrome extension.
+//!
+//! Connects to `/ws`, waits for `session_init` and answers every
+//! `action_request` from a declarative [`MockSite`], returning the same
+//! `ActionResult` payloads as `extension/content.js`. Tests can also send
+//! their own messages, e.g. the `user_action`s of record mode.
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::approval::ApprovalDecision;
+use crate::models::ws::{ActionCommand, ActionResult, WsMessage};
+
+/// Declarative page model the mock extension "browses".
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MockSite {
+    /// URL of the page shown right after connecting.
+    pub start_url: String,
+    #[serde(default)]
+    pub pages: Vec<MockPage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MockPage {
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub elements: Vec<MockElement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockElement {
+    pub id: i32,
+    pub role: String,
+    pub name: String,
+    #[serde(default)]
+    pub tag: String,
+    /// Clicking the element navigates here.
+    pub href: Option<String>,
+    /// Current value of a text input.
+    #[serde(default)]
+    pub value: String,
+    /// `type` of `<input>` elements, reported like content.js does.
+    #[serde(default)]
+    pub input_type: Option<String>,
+}
+
+impl MockSite {
+    pub fn from_json(raw: &str) -> Result<Self, String> {
+        serde_json::from_str(raw).map_err(|e| e.to_string())
+    }
+}
+
+/// Mutable browser state behind the mock connection.
+#[derive(Debug, Clone)]
+pub struct MockBrowser {
+    site: MockSite,
+    current: MockPage,
+    scroll: (i32, i32),
+    /// Every command executed, in order.
+    pub actions: Vec<ActionCommand>,
+    /// While set, action requests are silently dropped (like a service
+    /// worker that died mid-action).
+    pub paused: bool,
+    /// Every `approval_required` received, as `(approval_id, command)`.
+    pub approval_requests: Vec<(String, ActionCommand)>,
+    /// Decision sent back automatically for approval requests; none means
+    /// the request is left pending.
+    pub auto_approval: Option<ApprovalDecision>,
+}
+
+impl MockBrowser {
+    pub fn new(site: MockSite) -> Self {
+        let current = Self::page_for(&site, &site.start_url);
+        Self {
+            site,
+            current,
+            scroll: (0, 0),
+            actions: Vec::new(),
+            paused: false,
+            approval_requests: Vec::new(),
+            auto_approval: None,
+        }
+    }
+
+    fn page_for(site: &MockSite, url: &str) -> MockPage {
+        site.pages
+            .iter()
+            .find(|p| p.url == url)
+            .cloned()
+            .unwrap_or_else(|| MockPage {
+                url: url.to_string(),
+                ..Default::default()
+            })
+    }
+
+    pub fn current_page(&self) -> &MockPage {
+        &self.current
+    }
+
+    pub fn scroll_position(&self) -> (i32, i32) {
+        self.scroll
+    }
+
+    /// Executes one command the way `content.js` would.
+    pub fn execute(&mut self, request_id: &str, command: ActionCommand) -> ActionResult {
+        self.actions.push(command.clone());
+
+        let outcome: Result<Option<serde_json::Value>, String> = match command {
+            ActionCommand::NavigateTo { url } => {
+                self.current = Self::page_for(&self.site, &url);
+                self.scroll = (0, 0);
+                Ok(None)
+            }
+            ActionCommand::ClickElement { ref_id } => match self.element(ref_id) {
+                Some(element) => {
+                    if let Some(href) = element.href.clone() {
+                        self.current = Self::page_for(&self.site, &href);
+                        self.scroll = (0, 0);
+                    }
+                    Ok(None)
+                }
+                None => Err(format!("Element with ref {} not found", ref_id)),
+            },
+            ActionCommand::TypeText { ref_id, text } => {
+                match self.current.elements.iter_mut().find(|e| e.id == ref_id) {
+                    Some(element) => {
+                        element.value = text;
+                        Ok(None)
+                    }
+                    None => Err(format!("Element with ref {} not found", ref_id)),
+                }
+            }
+            ActionCommand::ScrollTo { x, y } => {
+                self.scroll = (x, y);
+                Ok(None)
+            }
+            ActionCommand::GetPageContent { max_length } => {
+                let max_length = max_length.unwrap_or(15000);
+                let text: String = self.current.text.chars().take(max_length).collect();
+                Ok(Some(json!({
+                    "title": self.current.title,
+                    "url": self.current.url,
+                    "text": text,
+                })))
+            }
+            ActionCommand::GetInteractiveElements { limit } => {
+                let limit = limit.unwrap_or(300);
+                let elements: Vec<_> = self
+                    .current
+                    .elements
+                    .iter()
+                    .take(limit)
+                    .map(|e| {
+                        let mut element = json!({
+                            "id": e.id,
+                            "role": e.role,
+                            "name": e.name,
+                            "tag": e.tag,
+                            "bounds": {"x": 0, "y": 0, "width": 0, "height": 0},
+                        });
+                        if let Some(input_type) = &e.input_type {
+                            element["input_type"] = json!(input_type);
+                        }
+                        element
+                    })
+                    .collect();
+                Ok(Some(json!({ "elements": elements })))
+            }
+        };
+
+        match outcome {
+            Ok(data) => ActionResult {
+                request_id: request_id.to_string(),
+                success: true,
+                error: None,
+                data,
+            },
+            Err(error) => ActionResult {
+                request_id: request_id.to_string(),
+                success: false,
+                error: Some(error),
+                data: None,
+            },
+        }
+    }
+
+    fn element(&self, ref_id: i32) -> Option<&MockElement> {
+        self.current.elements.iter().find(|e| e.id == ref_id)
+    }
+}
+
+/// A live mock extension connection.
+pub struct MockExtension {
+    pub session_id: String,
+    pub resume_token: String,
+    pub resumed: bool,
+    browser: Arc<Mutex<MockBrowser>>,
+    outgoing: mpsc::UnboundedSender<WsMessage>,
+    task: JoinHandle<()>,
+}
+
+impl MockExtension {
+    /// Connects to `ws_url` (e.g. `ws://127.0.0.1:3000/ws`) and starts
+    /// answering action requests in the background.
+    pub async fn connect(ws_url: &str, site: MockSite) -> Result<Self, String> {
+        Self::open(
+            ws_url.to_string(),
+            Arc::new(Mutex::new(MockBrowser::new(site))),
+        )
+        .await
+    }
+
+    /// Reconnects like a restarted service worker, presenting the previous
+    /// session id and resume token. Browser state carries over.
+    pub async fn resume(self, ws_url: &str) -> Result<Self, String> {
+        let url = format!(
+            "{}{}session_id={}&resume_token={}",
+            ws_url,
+            if ws_url.contains('?') { '&' } else { '?' },
+            self.session_id,
+            self.resume_token
+        );
+        let browser = self.browser.clone();
+        self.disconnect().await;
+        browser.lock().unwrap().paused = false;
+        Self::open(url, browser).await
+    }
+
+    async fn open(ws_url: String, browser: Arc<Mutex<MockBrowser>>) -> Result<Self, String> {
+        let (socket, _) = connect_async(ws_url.as_str())
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", ws_url, e))?;
+        let (mut sink, mut stream) = socket.split();
+
+        // The server always greets with session_init first
+        let (session_id, resume_token, resumed) = loop {
+            match stream.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(WsMessage::SessionInit {
+                        session_id,
+                        resume_token,
+                        resumed,
+                    }) = serde_json::from_str::<WsMessage>(text.as_str())
+                    {
+                        break (session_id, resume_token, resumed);
+                    }
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(format!("WebSocket error: {}", e)),
+                None => return Err("Connection closed before session_init".to_string()),
+            }
+        };
+
+        let task_browser = browser.clone();
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<WsMessage>();
+        let task = tokio::spawn(async move {
+            loop {
+                let msg = tokio::select! {
+                    msg = stream.next() => match msg {
+                        Some(Ok(msg)) => msg,
+                        _ => break,
+                    },
+                    Some(message) = outgoing_rx.recv() => {
+                        let text = serde_json::to_string(&message).expect("WsMessage serializes");
+                        if sink.send(Message::Text(text.into())).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let Message::Text(text) = msg else { continue };
+                let reply = match serde_json::from_str::<WsMessage>(text.as_str()) {
+                    Ok(WsMessage::ActionRequest {
+                        request_id,
+                        command,
+                    }) => {
+                        let mut browser = task_browser.lock().unwrap();
+                        if browser.paused {
+                            continue;
+                        }
+                        WsMessage::ActionResult(browser.execute(&request_id, command))
+                    }
+                    Ok(WsMessage::ApprovalRequired {
+                        approval_id,
+                        command,
+                        ..
+                    }) => {
+                        let mut browser = task_browser.lock().unwrap();
+                        browser
+                            .approval_requests
+                            .push((approval_id.clone(), command));
+                        let Some(decision) = browser.auto_approval.clone() else {
+                            continue;
+                        };
+                        WsMessage::ApprovalResponse {
+                            approval_id,
+                            decision,
+                        }
+                    }
+                    _ => continue,
+                };
+                let reply = serde_json::to_string(&reply).expect("WsMessage serializes");
+                if sink.send(Message::Text(reply.into())).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            session_id,
+            resume_token,
+            resumed,
+            browser,
+            outgoing,
+            task,
+        })
+    }
+
+    /// Sends `message` to the server as the extension would.
+    pub fn send(&self, message: WsMessage) {
+        let _ = self.outgoing.send(message);
+    }
+
+    /// Stops answering action requests until the next `resume`.
+    pub fn pause(&self) {
+        self.browser.lock().unwrap().paused = true;
+    }
+
+    /// Answers future approval requests with `decision` (or leaves them
+    /// pending with `None`).
+    pub fn set_auto_approval(&self, decision: Option<ApprovalDecision>) {
+        self.browser.lock().unwrap().auto_approval = decision;
+    }
+
+    /// Snapshot of the mock browser state.
+    pub fn browser(&self) -> MockBrowser {
+        self.browser.lock().unwrap().clone()
+    }
+
+    /// Stops answering and drops the connection.
+    pub async fn disconnect(self) {
+        self.task.abort();
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site() -> MockSite {
+        MockSite::from_json(include_str!("../../tests/fixtures/mock_site.json")).unwrap()
+    }
+
+    #[test]
+    fn test_click_link_navigates() {
+        let mut browser = MockBrowser::new(site());
+        let result = browser.execute("1", ActionCommand::ClickElement { ref_id: 2 });
+        assert!(result.success);
+        assert_eq!(browser.current_page().url, "https://example.com/login");
+    }
+
+    #[test]
+    fn test_missing_element_fails() {
+        let mut browser = MockBrowser::new(site());
+        let result = browser.execute("1", ActionCommand::ClickElement { ref_id: 99 });
+        assert!(!result.success);
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Element with ref 99 not found")
+        );
+    }
+
+    #[test]
+    fn test_get_page_content_truncates() {
+        let mut browser = MockBrowser::new(site());
+        let result = browser.execute(
+            "1",
+            ActionCommand::GetPageContent {
+                max_length: Some(7),
+            },
+        );
+        assert_eq!(result.data.unwrap()["text"], "Welcome");
+    }
+}
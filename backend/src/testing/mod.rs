@@ -0,0 +1,5 @@
+//! Test support: offline stand-ins for the browser extension.
+
+pub mod mock_extension;
+
+pub use mock_extension::{MockBrowser, MockExtension, MockSite};
@@ -2,6 +2,7 @@ use rig::completion::ToolDefinition;
 use rig::tool::Tool;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 
@@ -253,6 +254,50 @@ impl Tool for GetInteractiveElementsTool {
     }
 }
 
+/// Tool to run a macro the user saved
+#[derive(Deserialize, Serialize)]
+pub struct RunMacroTool;
+
+#[derive(Deserialize, Serialize)]
+pub struct RunMacroArgs {
+    pub name: String,
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+}
+
+impl Tool for RunMacroTool {
+    const NAME: &'static str = "run_macro";
+    type Error = BrowserToolError;
+    type Args = RunMacroArgs;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Run a macro the user recorded: a saved sequence of browser actions. Use this when a saved macro does what the user asks, instead of repeating the steps one by one.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "description": "Name of the macro to run"
+                    },
+                    "params": {
+                        "type": "object",
+                        "additionalProperties": { "type": "string" },
+                        "description": "Values for the macro's parameters, by name"
+                    }
+                },
+                "required": ["name"]
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        Ok(format!("Running macro {}...", args.name))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -300,4 +345,15 @@ mod tests {
         let args: GetInteractiveElementsArgs = serde_json::from_value(args_json).unwrap();
         assert_eq!(args.limit, Some(50));
     }
+
+    #[tokio::test]
+    async fn test_run_macro_serialization() {
+        let args_json = json!({ "name": "search", "params": { "query": "rust" } });
+        let args: RunMacroArgs = serde_json::from_value(args_json).unwrap();
+        assert_eq!(args.name, "search");
+        assert_eq!(args.params["query"], "rust");
+
+        let args: RunMacroArgs = serde_json::from_value(json!({ "name": "home" })).unwrap();
+        assert!(args.params.is_empty());
+    }
 }
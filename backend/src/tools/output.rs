@@ -0,0 +1,226 @@
+//! Typed outputs of the browser tools.
+//!
+//! The extension answers with loosely shaped `ActionResult.data` JSON; each
+//! tool converts it into one of these structs so the model (and SSE clients)
+//! get clean, predictable JSON.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::AppError;
+
+/// Suffix `content.js` appends when it cuts page text at `max_length`.
+const TRUNCATION_MARKER: &str = "... [truncated]";
+
+pub const DEFAULT_PAGE_CONTENT_LENGTH: usize = 15000;
+pub const DEFAULT_ELEMENT_LIMIT: usize = 300;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NavigateOutput {
+    pub navigated_to: String,
+    /// Element Ref IDs known before navigating (from the request snapshot or
+    /// an earlier `get_interactive_elements`) no longer apply.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub elements_stale: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClickOutput {
+    pub clicked: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypeOutput {
+    #[serde(rename = "ref")]
+    pub ref_id: i32,
+    pub typed_chars: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScrollOutput {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageContent {
+    pub title: String,
+    pub url: String,
+    pub text: String,
+    /// Length of `text` in characters.
+    pub length: usize,
+    /// Whether the page had more text than `max_length`.
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunMacroOutput {
+    #[serde(rename = "macro")]
+    pub name: String,
+    pub steps_run: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InteractiveElement {
+    pub id: i32,
+    pub role: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tag: String,
+    /// `type` attribute of `<input>` elements (e.g. "password").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
+}
+
+/// Field names that mark a secret even without `type="password"`.
+const PASSWORD_KEYWORDS: &[&str] = &["password", "kata sandi", "passcode", "pin", "cvv"];
+
+impl InteractiveElement {
+    /// Password inputs and fields named like one (PIN, CVV, ...).
+    pub fn is_password_like(&self) -> bool {
+        let name = self.name.to_lowercase();
+        self.input_type.as_deref() == Some("password")
+            || PASSWORD_KEYWORDS.iter().any(|k| name.contains(k))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InteractiveElements {
+    pub elements: Vec<InteractiveElement>,
+    pub count: usize,
+    /// True when `limit` was reached, so more elements may exist.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct RawPageContent {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    text: String,
+}
+
+fn invalid_data(tool: &str, error: impl std::fmt::Display) -> AppError {
+    AppError::ToolFailed(format!("Invalid {} data from extension: {}", tool, error))
+}
+
+impl NavigateOutput {
+    /// The extension reports `{"navigated_to": url}`; fall back to the
+    /// requested url when it doesn't.
+    pub fn from_data(url: String, data: Option<Value>) -> Self {
+        let navigated_to = data
+            .as_ref()
+            .and_then(|d| d.get("navigated_to"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or(url);
+        Self {
+            navigated_to,
+            elements_stale: false,
+        }
+    }
+}
+
+impl PageContent {
+    pub fn from_data(data: Option<Value>, max_length: Option<usize>) -> Result<Self, AppError> {
+        let data = data.ok_or_else(|| invalid_data("page content", "missing data"))?;
+        let raw: RawPageContent =
+            serde_json::from_value(data).map_err(|e| invalid_data("page content", e))?;
+
+        let max_length = max_length.unwrap_or(DEFAULT_PAGE_CONTENT_LENGTH);
+        let (text, truncated) = match raw.text.strip_suffix(TRUNCATION_MARKER) {
+            Some(text) => (text.to_string(), true),
+            None => {
+                let truncated = raw.text.chars().count() >= max_length;
+                (raw.text, truncated)
+            }
+        };
+
+        Ok(Self {
+            title: raw.title,
+            url: raw.url,
+            length: text.chars().count(),
+            text,
+            truncated,
+        })
+    }
+}
+
+impl InteractiveElements {
+    /// Accepts both `{"elements": [...]}` (content.js) and a bare array.
+    pub fn from_data(data: Option<Value>, limit: Option<usize>) -> Result<Self, AppError> {
+        let elements = match data {
+            Some(Value::Object(mut map)) => map.remove("elements").unwrap_or(Value::Array(vec![])),
+            Some(array @ Value::Array(_)) => array,
+            Some(other) => return Err(invalid_data("elements", other)),
+            None => Value::Array(vec![]),
+        };
+        let elements: Vec<InteractiveElement> =
+            serde_json::from_value(elements).map_err(|e| invalid_data("elements", e))?;
+
+        let limit = limit.unwrap_or(DEFAULT_ELEMENT_LIMIT);
+        Ok(Self {
+            count: elements.len(),
+            truncated: elements.len() >= limit,
+            elements,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_page_content_strips_truncation_marker() {
+        let data = json!({
+            "title": "Docs",
+            "url": "https://example.com",
+            "text": "Hello... [truncated]",
+        });
+        let content = PageContent::from_data(Some(data), Some(5)).unwrap();
+        assert_eq!(content.text, "Hello");
+        assert_eq!(content.length, 5);
+        assert!(content.truncated);
+    }
+
+    #[test]
+    fn test_interactive_elements_from_content_script() {
+        let data = json!({"elements": [{
+            "id": 1,
+            "role": "button",
+            "name": "Login",
+            "tag": "BUTTON",
+            "bounds": {"x": 10, "y": 20, "width": 80, "height": 24},
+        }]});
+        let output = InteractiveElements::from_data(Some(data), Some(10)).unwrap();
+        assert_eq!(output.count, 1);
+        assert!(!output.truncated);
+        assert_eq!(output.elements[0].bounds.unwrap().width, 80);
+
+        let json = serde_json::to_string(&output.elements[0]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":1,"role":"button","name":"Login","tag":"BUTTON","bounds":{"x":10,"y":20,"width":80,"height":24}}"#
+        );
+    }
+
+    #[test]
+    fn test_interactive_elements_rejects_malformed_data() {
+        let err = InteractiveElements::from_data(Some(json!("oops")), None).unwrap_err();
+        assert_eq!(err.code(), "tool_failed");
+    }
+}
@@ -4,77 +4,135 @@
 //! connections to the browser extension.
 
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::oneshot;
-use tokio::time::{Duration, timeout};
+use tokio::time::timeout;
 use uuid::Uuid;
 
 use rig::completion::ToolDefinition;
-use rig::tool::Tool;
-
-use crate::models::ws::{ActionCommand, WsMessage};
+use rig::tool::{Tool, ToolDyn};
+
+use crate::approval::review;
+use crate::error::AppError;
+use crate::macros::instantiate;
+use crate::models::trace::TraceRecord;
+use crate::models::ws::{ActionCommand, ActionResult, WsMessage};
+use crate::navigation::{enforce, resolve};
+use crate::replay::execute_step;
 use crate::state::AppState;
 use crate::tools::browser::{
     ClickArgs, ClickTool, GetInteractiveElementsArgs, GetInteractiveElementsTool,
-    GetPageContentArgs, GetPageContentTool, NavigateArgs, NavigateTool, ScrollArgs, ScrollTool,
-    TypeArgs, TypeTool,
+    GetPageContentArgs, GetPageContentTool, NavigateArgs, NavigateTool, RunMacroArgs, RunMacroTool,
+    ScrollArgs, ScrollTool, TypeArgs, TypeTool,
+};
+use crate::tools::output::{
+    ClickOutput, InteractiveElements, NavigateOutput, PageContent, RunMacroOutput, ScrollOutput,
+    TypeOutput,
 };
-
-// --- Error Type ---
-#[derive(Debug)]
-pub struct ToolError(pub String);
-
-impl std::fmt::Display for ToolError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
-impl std::error::Error for ToolError {}
 
 // --- Helper function to execute tools via WebSocket ---
 pub(crate) async fn execute_tool(
     state: &Arc<AppState>,
     session_id: &str,
+    run_id: Option<&str>,
     command: ActionCommand,
-) -> Result<String, String> {
-    // 1. Get connection
-    let tx = state
-        .get_connection(session_id)
-        .await
-        .ok_or("No active WebSocket connection for this session")?;
+) -> Result<Option<serde_json::Value>, AppError> {
+    // 1. Get connection. A session that is waiting to be resumed still
+    // accepts actions; they are delivered once the client reconnects.
+    let tx = state.get_connection(session_id).await;
+    if tx.is_none() && !state.is_session_resumable(session_id).await {
+        return Err(AppError::NoConnection);
+    }
 
     // 2. Register pending action
     let request_id = Uuid::new_v4().to_string();
-    let (tx_result, rx_result) = oneshot::channel();
+    let (tx_result, mut rx_result) = oneshot::channel();
     state
         .register_pending_action(request_id.clone(), tx_result)
         .await;
+    state
+        .track_action(session_id, run_id, &request_id, command.clone())
+        .await;
 
-    // 3. Send command
+    // 3. Send command, traced on the run that issued it
+    if let Some(run_id) = run_id {
+        let page = state.page_snapshot(session_id).await;
+        state
+            .traces
+            .append(
+                run_id,
+                TraceRecord::action_request(request_id.clone(), command.clone(), &page),
+            )
+            .await;
+    }
+    let sent_at = Instant::now();
     let msg = WsMessage::ActionRequest {
         request_id: request_id.clone(),
         command,
     };
 
-    tx.send(msg)
-        .map_err(|e| format!("Failed to send WebSocket message: {}", e))?;
-    tracing::info!(
-        "Sent ActionRequest[{}] to session {}",
-        request_id,
-        session_id
-    );
-
-    // 4. Wait for result
-    let result = timeout(Duration::from_secs(30), rx_result)
-        .await
-        .map_err(|_| "Tool execution timed out after 30 seconds")?
-        .map_err(|_| "Response channel closed unexpectedly")?;
-
-    // 5. Return result
+    match tx.map(|tx| tx.send(msg)) {
+        Some(Ok(())) => tracing::info!(
+            "Sent ActionRequest[{}] to session {}",
+            request_id,
+            session_id
+        ),
+        _ => tracing::info!(
+            "Queued ActionRequest[{}] until session {} resumes",
+            request_id,
+            session_id
+        ),
+    }
+
+    // 4. Wait for result. The timeout restarts while the session is
+    // disconnected but still resumable; expiry fails the action instead.
+    let tool_timeout = state.agent_limits.tool_timeout;
+    let outcome = loop {
+        match timeout(tool_timeout, &mut rx_result).await {
+            Ok(Ok(result)) => break Ok(result),
+            Ok(Err(_)) => {
+                state.discard_pending_action(&request_id).await;
+                break Err(AppError::ChannelClosed);
+            }
+            Err(_) if state.is_session_resumable(session_id).await => continue,
+            Err(_) => {
+                state.discard_pending_action(&request_id).await;
+                break Err(AppError::ToolTimeout {
+                    seconds: tool_timeout.as_secs(),
+                });
+            }
+        }
+    };
+    if let Some(run_id) = run_id {
+        let result = match &outcome {
+            Ok(result) => result.clone(),
+            Err(error) => ActionResult {
+                request_id: request_id.clone(),
+                success: false,
+                error: Some(error.to_string()),
+                data: None,
+            },
+        };
+        state
+            .traces
+            .append(
+                run_id,
+                TraceRecord::ActionResult {
+                    result,
+                    duration_ms: sent_at.elapsed().as_millis() as u64,
+                },
+            )
+            .await;
+    }
+    let result = outcome?;
+
+    // 5. Return the raw data; each tool converts it into its typed output
     if result.success {
-        Ok(format!("Success. Data: {:?}", result.data))
+        Ok(result.data)
     } else {
-        Err(format!("Error: {:?}", result.error))
+        Err(AppError::ToolFailed(
+            result.error.unwrap_or_else(|| "unknown error".to_string()),
+        ))
     }
 }
 
@@ -83,212 +141,421 @@ pub(crate) async fn execute_tool(
 pub struct WsNavigateTool {
     state: Arc<AppState>,
     session_id: String,
+    run_id: String,
 }
 
 impl WsNavigateTool {
-    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
-        Self { state, session_id }
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
 
 impl Tool for WsNavigateTool {
     const NAME: &'static str = NavigateTool::NAME;
-    type Error = ToolError;
+    type Error = AppError;
     type Args = NavigateArgs;
-    type Output = String;
+    type Output = NavigateOutput;
 
     async fn definition(&self, prompt: String) -> ToolDefinition {
-        NavigateTool.definition(prompt).await
+        Tool::definition(&NavigateTool, prompt).await
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        // Validate URL - reject system/restricted URLs
-        let url_lower = args.url.to_lowercase();
-        if url_lower.starts_with("chrome://")
-            || url_lower.starts_with("about:")
-            || url_lower.starts_with("file://")
-        {
-            return Err(ToolError(
-                "Navigation to system pages (chrome://, about://, file://) is not allowed".into(),
-            ));
-        }
-
-        execute_tool(
+        let page_url = self.state.page_snapshot(&self.session_id).await.url;
+        let url = resolve(page_url.as_deref(), &args.url);
+        enforce(&self.state, &self.session_id, &url).await?;
+        let command = review(
             &self.state,
             &self.session_id,
-            ActionCommand::NavigateTo { url: args.url },
+            Some(&self.run_id),
+            ActionCommand::NavigateTo { url },
         )
-        .await
-        .map_err(ToolError)
+        .await?;
+        let ActionCommand::NavigateTo { url } = &command else {
+            unreachable!("approval edits keep the action type");
+        };
+        // The user may have edited the URL
+        let url = resolve(page_url.as_deref(), url);
+        enforce(&self.state, &self.session_id, &url).await?;
+        let command = ActionCommand::NavigateTo { url: url.clone() };
+
+        let data = execute_tool(&self.state, &self.session_id, Some(&self.run_id), command).await?;
+        let mut output = NavigateOutput::from_data(url, data);
+        output.elements_stale = self.state.invalidate_page_elements(&self.session_id).await;
+        self.state
+            .record_page_url(&self.session_id, &output.navigated_to)
+            .await;
+        Ok(output)
     }
 }
 
 pub struct WsClickTool {
     state: Arc<AppState>,
     session_id: String,
+    run_id: String,
 }
 
 impl WsClickTool {
-    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
-        Self { state, session_id }
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
 
 impl Tool for WsClickTool {
     const NAME: &'static str = ClickTool::NAME;
-    type Error = ToolError;
+    type Error = AppError;
     type Args = ClickArgs;
-    type Output = String;
+    type Output = ClickOutput;
 
     async fn definition(&self, prompt: String) -> ToolDefinition {
-        ClickTool.definition(prompt).await
+        Tool::definition(&ClickTool, prompt).await
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        execute_tool(
+        let command = review(
             &self.state,
             &self.session_id,
+            Some(&self.run_id),
             ActionCommand::ClickElement {
                 ref_id: args.ref_id,
             },
         )
-        .await
-        .map_err(ToolError)
+        .await?;
+        let ActionCommand::ClickElement { ref_id } = command else {
+            unreachable!("approval edits keep the action type");
+        };
+
+        execute_tool(&self.state, &self.session_id, Some(&self.run_id), command).await?;
+        // The click may have changed the page under the known refs
+        self.state.invalidate_page_elements(&self.session_id).await;
+        Ok(ClickOutput { clicked: ref_id })
     }
 }
 
 pub struct WsTypeTool {
     state: Arc<AppState>,
     session_id: String,
+    run_id: String,
 }
 
 impl WsTypeTool {
-    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
-        Self { state, session_id }
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
 
 impl Tool for WsTypeTool {
     const NAME: &'static str = TypeTool::NAME;
-    type Error = ToolError;
+    type Error = AppError;
     type Args = TypeArgs;
-    type Output = String;
+    type Output = TypeOutput;
 
     async fn definition(&self, prompt: String) -> ToolDefinition {
-        TypeTool.definition(prompt).await
+        Tool::definition(&TypeTool, prompt).await
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        execute_tool(
+        let command = review(
             &self.state,
             &self.session_id,
+            Some(&self.run_id),
             ActionCommand::TypeText {
                 ref_id: args.ref_id,
                 text: args.text,
             },
         )
-        .await
-        .map_err(ToolError)
+        .await?;
+        let ActionCommand::TypeText { ref_id, text } = &command else {
+            unreachable!("approval edits keep the action type");
+        };
+        let output = TypeOutput {
+            ref_id: *ref_id,
+            typed_chars: text.chars().count(),
+        };
+
+        execute_tool(&self.state, &self.session_id, Some(&self.run_id), command).await?;
+        Ok(output)
     }
 }
 
 pub struct WsScrollTool {
     state: Arc<AppState>,
     session_id: String,
+    run_id: String,
 }
 
 impl WsScrollTool {
-    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
-        Self { state, session_id }
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
 
 impl Tool for WsScrollTool {
     const NAME: &'static str = ScrollTool::NAME;
-    type Error = ToolError;
+    type Error = AppError;
     type Args = ScrollArgs;
-    type Output = String;
+    type Output = ScrollOutput;
 
     async fn definition(&self, prompt: String) -> ToolDefinition {
-        ScrollTool.definition(prompt).await
+        Tool::definition(&ScrollTool, prompt).await
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
         execute_tool(
             &self.state,
             &self.session_id,
+            Some(&self.run_id),
             ActionCommand::ScrollTo {
                 x: args.x,
                 y: args.y,
             },
         )
-        .await
-        .map_err(ToolError)
+        .await?;
+        Ok(ScrollOutput {
+            x: args.x,
+            y: args.y,
+        })
     }
 }
 
 pub struct WsGetPageContentTool {
     state: Arc<AppState>,
     session_id: String,
+    run_id: String,
 }
 
 impl WsGetPageContentTool {
-    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
-        Self { state, session_id }
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
 
 impl Tool for WsGetPageContentTool {
     const NAME: &'static str = GetPageContentTool::NAME;
-    type Error = ToolError;
+    type Error = AppError;
     type Args = GetPageContentArgs;
-    type Output = String;
+    type Output = PageContent;
 
     async fn definition(&self, prompt: String) -> ToolDefinition {
-        GetPageContentTool.definition(prompt).await
+        Tool::definition(&GetPageContentTool, prompt).await
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        execute_tool(
+        let data = execute_tool(
             &self.state,
             &self.session_id,
+            Some(&self.run_id),
             ActionCommand::GetPageContent {
                 max_length: args.max_length,
             },
         )
-        .await
-        .map_err(ToolError)
+        .await?;
+        let content = PageContent::from_data(data, args.max_length)?;
+        if !content.url.is_empty() {
+            let title = Some(content.title.clone()).filter(|t| !t.is_empty());
+            self.state
+                .record_page_visit(&self.session_id, &content.url, title, None)
+                .await;
+        }
+        Ok(content)
     }
 }
 
 pub struct WsGetInteractiveElementsTool {
     state: Arc<AppState>,
     session_id: String,
+    run_id: String,
 }
 
 impl WsGetInteractiveElementsTool {
-    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
-        Self { state, session_id }
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
 
 impl Tool for WsGetInteractiveElementsTool {
     const NAME: &'static str = GetInteractiveElementsTool::NAME;
-    type Error = ToolError;
+    type Error = AppError;
     type Args = GetInteractiveElementsArgs;
-    type Output = String;
+    type Output = InteractiveElements;
 
     async fn definition(&self, prompt: String) -> ToolDefinition {
-        GetInteractiveElementsTool.definition(prompt).await
+        Tool::definition(&GetInteractiveElementsTool, prompt).await
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        execute_tool(
+        let data = execute_tool(
             &self.state,
             &self.session_id,
+            Some(&self.run_id),
             ActionCommand::GetInteractiveElements { limit: args.limit },
         )
-        .await
-        .map_err(ToolError)
+        .await?;
+        let output = InteractiveElements::from_data(data, args.limit)?;
+        self.state
+            .record_page_elements(&self.session_id, output.elements.clone())
+            .await;
+        Ok(output)
+    }
+}
+
+pub struct WsRunMacroTool {
+    state: Arc<AppState>,
+    session_id: String,
+    run_id: String,
+}
+
+impl WsRunMacroTool {
+    pub fn new(state: Arc<AppState>, session_id: String, run_id: String) -> Self {
+        Self {
+            state,
+            session_id,
+            run_id,
+        }
     }
 }
+
+impl Tool for WsRunMacroTool {
+    const NAME: &'static str = RunMacroTool::NAME;
+    type Error = AppError;
+    type Args = RunMacroArgs;
+    type Output = RunMacroOutput;
+
+    /// The base definition plus the macros the session's user has saved.
+    async fn definition(&self, prompt: String) -> ToolDefinition {
+        let mut definition = Tool::definition(&RunMacroTool, prompt).await;
+        let macros = match self.state.session_principal(&self.session_id).await {
+            Some(principal) => self.state.macros.list(&principal).await,
+            None => Vec::new(),
+        };
+        if macros.is_empty() {
+            definition
+                .description
+                .push_str(" The user has no saved macros yet.");
+            return definition;
+        }
+        let listed: Vec<String> = macros
+            .iter()
+            .map(|saved| {
+                let signature = format!("{}({})", saved.name, saved.params.join(", "));
+                match &saved.description {
+                    Some(description) => format!("{} - {}", signature, description),
+                    None => signature,
+                }
+            })
+            .collect();
+        definition
+            .description
+            .push_str(&format!(" Saved macros: {}.", listed.join("; ")));
+        definition
+    }
+
+    /// Runs every step in order, each through the navigation and approval
+    /// policies, and stops at the first failure.
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let principal = self
+            .state
+            .session_principal(&self.session_id)
+            .await
+            .ok_or(AppError::NoConnection)?;
+        let saved = self
+            .state
+            .macros
+            .get(&principal, &args.name)
+            .await
+            .ok_or_else(|| AppError::NotFound(format!("Macro {}", args.name)))?;
+        let steps = instantiate(&saved, &args.params)?;
+        let total = steps.len();
+        for (index, step) in steps.into_iter().enumerate() {
+            execute_step(
+                &self.state,
+                &self.session_id,
+                Some(&self.run_id),
+                step,
+                true,
+            )
+            .await
+            .map_err(|e| match e {
+                AppError::ToolFailed(message) => AppError::ToolFailed(format!(
+                    "macro {} failed at step {} of {}: {}",
+                    saved.name,
+                    index + 1,
+                    total,
+                    message
+                )),
+                other => other,
+            })?;
+        }
+        Ok(RunMacroOutput {
+            name: saved.name,
+            steps_run: total,
+        })
+    }
+}
+
+/// All browser tools bound to one WebSocket session, ready for agent run
+/// `run_id`.
+pub fn browser_tools(
+    state: &Arc<AppState>,
+    session_id: &str,
+    run_id: &str,
+) -> Vec<Box<dyn ToolDyn>> {
+    let (session_id, run_id) = (session_id.to_string(), run_id.to_string());
+    vec![
+        Box::new(WsNavigateTool::new(
+            state.clone(),
+            session_id.clone(),
+            run_id.clone(),
+        )),
+        Box::new(WsClickTool::new(
+            state.clone(),
+            session_id.clone(),
+            run_id.clone(),
+        )),
+        Box::new(WsTypeTool::new(
+            state.clone(),
+            session_id.clone(),
+            run_id.clone(),
+        )),
+        Box::new(WsScrollTool::new(
+            state.clone(),
+            session_id.clone(),
+            run_id.clone(),
+        )),
+        Box::new(WsGetPageContentTool::new(
+            state.clone(),
+            session_id.clone(),
+            run_id.clone(),
+        )),
+        Box::new(WsGetInteractiveElementsTool::new(
+            state.clone(),
+            session_id.clone(),
+            run_id.clone(),
+        )),
+        Box::new(WsRunMacroTool::new(state.clone(), session_id, run_id)),
+    ]
+}
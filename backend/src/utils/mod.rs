@@ -1 +1,2 @@
 pub mod streaming;
+pub mod time;
@@ -1,14 +1,5 @@
-#[path = "../src/dtos/agent.rs"]
-mod agent_dto;
-use agent_dto::{AgentRequest, InteractiveElementDto};
-
-pub fn format_interactive_elements(elements: &[InteractiveElementDto]) -> String {
-    elements
-        .iter()
-        .map(|e| format!("- Ref {}: {} ({})", e.id, e.name, e.role))
-        .collect::<Vec<_>>()
-        .join("\n")
-}
+use backend_rig::agent::context::format_interactive_elements;
+use backend_rig::dtos::agent::{AgentRequest, InteractiveElementDto};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,1211 @@
+//! End-to-end agent runs: mock LLM -> `/agent/run` -> `/ws` -> mock extension.
+
+use axum::body::{Body, to_bytes};
+use futures::StreamExt;
+use http::{Request, StatusCode};
+use serde_json::json;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use backend_rig::approval::{ApprovalDecision, ApprovalMode, ApprovalPolicy};
+use backend_rig::auth::AuthConfig;
+use backend_rig::llm::mock::MockProvider;
+use backend_rig::models::ws::{ActionCommand, UserAction, WsMessage};
+use backend_rig::routes::app_router;
+use backend_rig::state::AppState;
+use backend_rig::testing::{MockExtension, MockSite};
+
+fn mock_state(fixture: &str) -> AppState {
+    let provider = MockProvider::from_json(fixture).expect("valid mock fixture");
+    AppState::new(Arc::new(provider))
+}
+
+async fn spawn_server(fixture: &str) -> (axum::Router, String) {
+    serve(mock_state(fixture)).await
+}
+
+async fn serve(state: AppState) -> (axum::Router, String) {
+    let app = app_router(Arc::new(state));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move {
+        axum::serve(listener, server).await.unwrap();
+    });
+
+    (app, format!("ws://{}/ws", addr))
+}
+
+async fn run_agent(app: axum::Router, body: serde_json::Value) -> String {
+    run_agent_as(app, None, body).await.1
+}
+
+/// Id from the stream's `conversation` event.
+fn conversation_id(stream: &str) -> String {
+    stream
+        .split(r#""conversation_id":""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .to_string()
+}
+
+async fn run_agent_as(
+    app: axum::Router,
+    api_key: Option<&str>,
+    body: serde_json::Value,
+) -> (StatusCode, String) {
+    let mut request = Request::builder()
+        .method("POST")
+        .uri("/agent/run")
+        .header("Content-Type", "application/json");
+    if let Some(key) = api_key {
+        request = request.header("X-API-Key", key);
+    }
+    let response = app
+        .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_agent_clicks_through_mock_site() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let body = run_agent(
+        app,
+        json!({"query": "click login", "session_id": extension.session_id}),
+    )
+    .await;
+
+    assert!(body.contains("Clicked the "));
+    assert!(body.contains(r#""name":"Login""#));
+    assert!(body.contains(r#""result":{"clicked":1}"#));
+    assert!(body.contains(
+        r#""id":"mock_call_2","name":"click_element","status":"calling","arguments":{"ref":1}"#
+    ));
+    assert!(body.contains(r#""duration_ms":"#));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+
+    let browser = extension.browser();
+    assert!(matches!(
+        browser.actions.as_slice(),
+        [
+            ActionCommand::GetInteractiveElements { limit: Some(10) },
+            ActionCommand::ClickElement { ref_id: 1 },
+        ]
+    ));
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_agent_tool_fails_without_extension() {
+    let (app, _) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+
+    let body = run_agent(
+        app,
+        json!({"query": "click login", "session_id": "missing-session"}),
+    )
+    .await;
+
+    // Tool failures are fed back to the model, the run itself still completes
+    assert!(body.contains(r#""status":"failed""#));
+    assert!(body.contains("No active WebSocket connection"));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+}
+
+#[tokio::test]
+async fn test_agent_provider_error_is_structured() {
+    let fixture = json!({"turns": [{"text": ["Hmm"], "error": "429 Too Many Requests"}]});
+    let (app, _) = spawn_server(&fixture.to_string()).await;
+
+    let body = run_agent(app, json!({"query": "hi", "session_id": "s"})).await;
+
+    assert!(body.contains("event: error"));
+    assert!(body.contains(r#""code":"rate_limited""#));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+}
+
+#[tokio::test]
+async fn test_rejected_approval_reaches_the_model() {
+    let state =
+        mock_state(include_str!("fixtures/mock_llm.json")).with_approval_policy(ApprovalPolicy {
+            mode: ApprovalMode::Always,
+            ..Default::default()
+        });
+    let (app, ws_url) = serve(state).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    extension.set_auto_approval(Some(ApprovalDecision::Reject {
+        reason: Some("not now".into()),
+    }));
+
+    let body = run_agent(
+        app,
+        json!({"query": "click login", "session_id": extension.session_id}),
+    )
+    .await;
+
+    assert!(body.contains("event: approval_required"));
+    assert!(body.contains(r#""decision":"rejected""#));
+    assert!(body.contains("Action rejected: not now"));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+
+    let browser = extension.browser();
+    assert_eq!(browser.approval_requests.len(), 1);
+    assert!(matches!(
+        browser.actions.as_slice(),
+        [ActionCommand::GetInteractiveElements { .. }]
+    ));
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_blocked_navigation_is_refused_and_audited() {
+    let fixture = json!({"turns": [
+        {"tool_calls": [{"name": "navigate_to", "arguments": {"url": "javascript:alert(1)"}}]},
+        {"text": ["That URL is not allowed."]}
+    ]});
+    let (app, ws_url) = spawn_server(&fixture.to_string()).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let body = run_agent(
+        app.clone(),
+        json!({"query": "open it", "session_id": extension.session_id}),
+    )
+    .await;
+
+    assert!(body.contains(r#""status":"failed""#));
+    assert!(body.contains("Navigation blocked: the javascript: scheme is not allowed"));
+    assert!(extension.browser().actions.is_empty());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!(
+                    "/navigation/audit?session_id={}",
+                    extension.session_id
+                ))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let audit: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(audit[0]["rule"], "scheme");
+    assert_eq!(audit[0]["scope"], "deployment");
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_sessions_are_bound_to_their_principal() {
+    let state = mock_state(include_str!("fixtures/mock_llm.json")).with_auth(AuthConfig {
+        api_keys: AuthConfig::parse_api_keys("alice:key-a,bob:key-b"),
+        ..Default::default()
+    });
+    let (app, ws_url) = serve(state).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+
+    // The WebSocket handshake itself needs credentials
+    assert!(MockExtension::connect(&ws_url, site.clone()).await.is_err());
+    let extension = MockExtension::connect(&format!("{}?access_token=key-a", ws_url), site)
+        .await
+        .unwrap();
+    let body = json!({"query": "click login", "session_id": extension.session_id});
+
+    let (status, _) = run_agent_as(app.clone(), None, body.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, error) = run_agent_as(app.clone(), Some("key-b"), body.clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(error.contains(r#""code":"forbidden""#));
+
+    let (status, stream) = run_agent_as(app, Some("key-a"), body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(stream.contains(r#""result":{"clicked":1}"#));
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_agent_run_persists_conversation() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (status, _) = run_agent_as(
+        app.clone(),
+        None,
+        json!({
+            "query": "click login",
+            "session_id": extension.session_id,
+            "conversation_id": "../conv-1"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = run_agent(
+        app.clone(),
+        json!({"query": "click login", "session_id": extension.session_id}),
+    )
+    .await;
+    let conversation_id = conversation_id(&body);
+    assert!(uuid::Uuid::parse_str(&conversation_id).is_ok());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/conversations/{}", conversation_id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let conversation: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+    assert_eq!(conversation["title"], "click login");
+    assert_eq!(conversation["messages"][0]["role"], "user");
+    assert_eq!(
+        conversation["messages"][1]["content"],
+        "Let me look. Clicked the Login button."
+    );
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_session_resume_redelivers_pending_action() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    let session_id = extension.session_id.clone();
+
+    // The first action request is lost when the "service worker" dies
+    extension.pause();
+    let run = tokio::spawn(run_agent(
+        app,
+        json!({"query": "click login", "session_id": session_id}),
+    ));
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let extension = extension.resume(&ws_url).await.unwrap();
+    assert!(extension.resumed);
+    assert_eq!(extension.session_id, session_id);
+
+    let body = run.await.unwrap();
+    assert!(body.contains("Clicked the "));
+    assert!(matches!(
+        extension.browser().actions.first(),
+        Some(ActionCommand::GetInteractiveElements { .. })
+    ));
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_conversations_are_bound_to_their_principal() {
+    let state = mock_state(include_str!("fixtures/mock_llm.json")).with_auth(AuthConfig {
+        api_keys: AuthConfig::parse_api_keys("alice:key-a,bob:key-b"),
+        ..Default::default()
+    });
+    let (app, ws_url) = serve(state).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let alice = MockExtension::connect(&format!("{}?access_token=key-a", ws_url), site.clone())
+        .await
+        .unwrap();
+    let bob = MockExtension::connect(&format!("{}?access_token=key-b", ws_url), site)
+        .await
+        .unwrap();
+
+    let (_, stream) = run_agent_as(
+        app.clone(),
+        Some("key-a"),
+        json!({"query": "click login", "session_id": alice.session_id}),
+    )
+    .await;
+    let id = conversation_id(&stream);
+    let uri = format!("/conversations/{}", id);
+
+    let (status, _) = admin_request(&app, "key-b", &uri, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = admin_request(&app, "key-b", &format!("{}/fork", uri), Some(json!({}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, listed) = admin_request(&app, "key-b", "/conversations", None).await;
+    assert_eq!(listed, json!([]));
+
+    // Continuing someone else's conversation fails before the model runs
+    let (status, error) = run_agent_as(
+        app.clone(),
+        Some("key-b"),
+        json!({"query": "click login", "session_id": bob.session_id, "conversation_id": id}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(error.contains(r#""code":"forbidden""#));
+
+    let (status, conversation) = admin_request(&app, "key-a", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(conversation["principal"], "alice");
+    alice.disconnect().await;
+    bob.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_session_resume_rejects_wrong_token() {
+    let (_, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let mut extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    let session_id = extension.session_id.clone();
+
+    extension.resume_token = "forged".to_string();
+    let extension = extension.resume(&ws_url).await.unwrap();
+    assert!(!extension.resumed);
+    assert_ne!(extension.session_id, session_id);
+    extension.disconnect().await;
+}
+
+async fn admin_request(
+    app: &axum::Router,
+    api_key: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(if body.is_some() { "POST" } else { "GET" })
+        .uri(uri)
+        .header("X-API-Key", api_key)
+        .header("Content-Type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn test_admin_cancels_stuck_action_and_disconnects_session() {
+    let mut auth = AuthConfig {
+        api_keys: AuthConfig::parse_api_keys("alice:key-a,ops:key-ops"),
+        ..Default::default()
+    };
+    auth.admins.insert("ops".to_string());
+    let state = mock_state(include_str!("fixtures/mock_llm.json")).with_auth(auth);
+    let (app, ws_url) = serve(state).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&format!("{}?access_token=key-a", ws_url), site)
+        .await
+        .unwrap();
+    let session_id = extension.session_id.clone();
+
+    // The extension never answers, so the first action stays in flight
+    extension.pause();
+    let run = tokio::spawn(run_agent_as(
+        app.clone(),
+        Some("key-a"),
+        json!({"query": "click login", "session_id": session_id}),
+    ));
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let (status, _) = admin_request(&app, "key-a", "/admin/sessions", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, sessions) = admin_request(&app, "key-ops", "/admin/sessions", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sessions[0]["session_id"], session_id.as_str());
+    assert_eq!(sessions[0]["principal"], "alice");
+    assert_eq!(sessions[0]["connected"], true);
+    let action = &sessions[0]["in_flight"][0];
+    assert_eq!(action["command"]["type"], "get_interactive_elements");
+    let request_id = action["request_id"].as_str().unwrap();
+
+    let cancel = format!(
+        "/admin/sessions/{}/actions/{}/cancel",
+        session_id, request_id
+    );
+    let (status, _) =
+        admin_request(&app, "key-ops", &cancel, Some(json!({"reason": "stuck"}))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) =
+        admin_request(&app, "key-ops", &cancel, Some(json!({"reason": "stuck"}))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The model moves on to the next (also unanswered) action until the
+    // session is closed for good
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let disconnect = format!("/admin/sessions/{}/disconnect", session_id);
+    let (status, _) = admin_request(
+        &app,
+        "key-ops",
+        &disconnect,
+        Some(json!({"reason": "maintenance"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, body) = run.await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Action cancelled by an operator: stuck"));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+
+    let (_, sessions) = admin_request(&app, "key-ops", "/admin/sessions", None).await;
+    assert_eq!(sessions, json!([]));
+    let detail = format!("/admin/sessions/{}", session_id);
+    let (status, _) = admin_request(&app, "key-ops", &detail, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_audit_stays_with_the_owner_after_the_session_ends() {
+    let fixture = json!({"turns": [
+        {"tool_calls": [{"name": "navigate_to", "arguments": {"url": "javascript:alert(1)"}}]},
+        {"text": ["That URL is not allowed."]}
+    ]});
+    let mut auth = AuthConfig {
+        api_keys: AuthConfig::parse_api_keys("alice:key-a,bob:key-b,ops:key-ops"),
+        ..Default::default()
+    };
+    auth.admins.insert("ops".to_string());
+    let (app, ws_url) = serve(mock_state(&fixture.to_string()).with_auth(auth)).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&format!("{}?access_token=key-a", ws_url), site)
+        .await
+        .unwrap();
+    let session_id = extension.session_id.clone();
+    run_agent_as(
+        app.clone(),
+        Some("key-a"),
+        json!({"query": "open it", "session_id": session_id}),
+    )
+    .await;
+
+    // Once the session is gone, only its owner still sees the refusal
+    let disconnect = format!("/admin/sessions/{}/disconnect", session_id);
+    let (status, _) = admin_request(
+        &app,
+        "key-ops",
+        &disconnect,
+        Some(json!({"reason": "maintenance"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let audit = format!("/navigation/audit?session_id={}", session_id);
+    let (_, entries) = admin_request(&app, "key-b", &audit, None).await;
+    assert_eq!(entries, json!([]));
+    let (_, entries) = admin_request(&app, "key-a", &audit, None).await;
+    assert_eq!(entries[0]["principal"], "alice");
+    assert_eq!(entries[0]["url"], "javascript:alert(1)");
+    extension.disconnect().await;
+}
+
+/// Reads SSE chunks until `needle` shows up, returning everything read.
+async fn read_until(body: &mut axum::body::BodyDataStream, needle: &str) -> String {
+    let mut text = String::new();
+    while !text.contains(needle) {
+        let chunk = body.next().await.expect("stream ended early").unwrap();
+        text.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    text
+}
+
+async fn start_run(app: &axum::Router, session_id: &str) -> (String, axum::body::BodyDataStream) {
+    let request = Request::builder()
+        .method("POST")
+        .uri("/agent/run")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({"query": "click login", "session_id": session_id}).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let mut body = response.into_body().into_data_stream();
+    let started = read_until(&mut body, "event: conversation").await;
+    let run_id = started
+        .split(r#""run_id":""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("run_started carries the run id")
+        .to_string();
+    (run_id, body)
+}
+
+#[tokio::test]
+async fn test_cancel_run_stops_agent_and_fails_pending_action() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    extension.pause();
+
+    let (run_id, mut body) = start_run(&app, &extension.session_id).await;
+    read_until(&mut body, r#""status":"calling""#).await;
+
+    let cancel = Request::builder()
+        .method("POST")
+        .uri(format!("/agent/runs/{}/cancel", run_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(cancel).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let rest = read_until(&mut body, "data: [DONE]").await;
+    assert!(rest.contains("event: cancelled"));
+    assert!(rest.contains(r#""reason":"cancelled by user""#));
+    // The model never got to its second tool call
+    assert!(!rest.contains("click_element"));
+
+    let (status, sessions) = admin_request(&app, "", "/admin/sessions", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sessions[0]["in_flight"], json!([]));
+
+    // The run is over, cancelling again finds nothing
+    let cancel = Request::builder()
+        .method("POST")
+        .uri(format!("/agent/runs/{}/cancel", run_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(cancel).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_cancel_run_leaves_other_runs_on_session() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    extension.pause();
+
+    let (first, mut first_body) = start_run(&app, &extension.session_id).await;
+    let (second, mut second_body) = start_run(&app, &extension.session_id).await;
+    for body in [&mut first_body, &mut second_body] {
+        read_until(body, r#""status":"calling""#).await;
+        // Each run only advances while its body is read
+        let waiting = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            read_until(body, "data: [DONE]"),
+        )
+        .await;
+        assert!(waiting.is_err());
+    }
+
+    let cancel = Request::builder()
+        .method("POST")
+        .uri(format!("/agent/runs/{}/cancel", first))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(cancel).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let rest = read_until(&mut first_body, "data: [DONE]").await;
+    assert!(rest.contains("event: cancelled"));
+
+    // Only the cancelled run's action failed
+    let (_, sessions) = admin_request(&app, "", "/admin/sessions", None).await;
+    let in_flight = sessions[0]["in_flight"].as_array().unwrap();
+    assert_eq!(in_flight.len(), 1);
+    assert_eq!(in_flight[0]["run_id"], second);
+
+    // Each run traced its own action request, not the other's
+    for run_id in [&first, &second] {
+        let requests = trace_lines(&app, run_id)
+            .await
+            .into_iter()
+            .filter(|line| line["type"] == "action_request")
+            .count();
+        assert_eq!(requests, 1);
+    }
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_sse_disconnect_cancels_run() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    extension.pause();
+
+    let (_, mut body) = start_run(&app, &extension.session_id).await;
+    read_until(&mut body, r#""status":"calling""#).await;
+    // The run only advances while the body is read; let it send the action
+    let waiting = tokio::time::timeout(
+        std::time::Duration::from_millis(100),
+        read_until(&mut body, "data: [DONE]"),
+    )
+    .await;
+    assert!(waiting.is_err());
+    let (_, sessions) = admin_request(&app, "", "/admin/sessions", None).await;
+    assert_eq!(sessions[0]["in_flight"].as_array().unwrap().len(), 1);
+
+    // Closing the side panel drops the response body
+    drop(body);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let (_, sessions) = admin_request(&app, "", "/admin/sessions", None).await;
+    assert_eq!(sessions[0]["in_flight"], json!([]));
+    extension.disconnect().await;
+}
+
+async fn request_json(
+    app: &axum::Router,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+    )
+}
+
+/// Polls `GET /tasks/{id}` until it reaches `status`.
+async fn wait_for_task(app: &axum::Router, task_id: &str, status: &str) -> serde_json::Value {
+    for _ in 0..100 {
+        let (_, task) = request_json(app, "GET", &format!("/tasks/{}", task_id), None).await;
+        if task["status"] == status {
+            return task;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("task {} never became {}", task_id, status);
+}
+
+#[tokio::test]
+async fn test_background_task_runs_and_replays_events() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (status, task) = request_json(
+        &app,
+        "POST",
+        "/tasks",
+        Some(json!({"query": "click login", "session_id": extension.session_id})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let task_id = task["task_id"].as_str().unwrap().to_string();
+
+    let task = wait_for_task(&app, &task_id, "completed").await;
+    assert_eq!(task["answer"], "Let me look. Clicked the Login button.");
+    assert_eq!(task["steps"][1]["name"], "click_element");
+    assert_eq!(task["steps"][1]["result"], json!({"clicked": 1}));
+    assert_eq!(task["usage"]["total_tokens"], 132);
+
+    // Reattaching after the fact replays the whole run
+    let request = Request::builder()
+        .uri(format!("/tasks/{}/events", task_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let events = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(events.contains("event: run_started"));
+    assert!(events.contains(r#""result":{"clicked":1}"#));
+    assert!(events.contains("event: task"));
+    assert!(events.contains(r#""status":"completed""#));
+    assert!(events.trim_end().ends_with("data: [DONE]"));
+
+    let (_, tasks) = request_json(
+        &app,
+        "GET",
+        &format!("/tasks?session_id={}", extension.session_id),
+        None,
+    )
+    .await;
+    assert_eq!(tasks[0]["task_id"], task_id.as_str());
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_tasks_queue_per_session_and_cancel() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+    extension.pause();
+
+    let body = json!({"query": "click login", "session_id": extension.session_id});
+    let (_, first) = request_json(&app, "POST", "/tasks", Some(body.clone())).await;
+    let (_, second) = request_json(&app, "POST", "/tasks", Some(body)).await;
+    let first = first["task_id"].as_str().unwrap().to_string();
+    let second = second["task_id"].as_str().unwrap().to_string();
+
+    // One task per session by default: the second waits for the first
+    wait_for_task(&app, &first, "running").await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let (_, task) = request_json(&app, "GET", &format!("/tasks/{}", second), None).await;
+    assert_eq!(task["status"], "queued");
+
+    let (status, _) = request_json(&app, "POST", &format!("/tasks/{}/cancel", second), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    wait_for_task(&app, &second, "cancelled").await;
+
+    let (status, _) = request_json(&app, "POST", &format!("/tasks/{}/cancel", first), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let task = wait_for_task(&app, &first, "cancelled").await;
+    assert!(task["started_at"].is_u64());
+    assert!(task["finished_at"].is_u64());
+
+    // Finished tasks can't be cancelled again
+    let (status, _) = request_json(&app, "POST", &format!("/tasks/{}/cancel", first), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    extension.disconnect().await;
+}
+
+/// Downloads a run's trace as parsed JSONL lines.
+async fn trace_lines(app: &axum::Router, run_id: &str) -> Vec<serde_json::Value> {
+    let request = Request::builder()
+        .uri(format!("/traces/{}", run_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_agent_run_is_traced_and_downloadable() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (run_id, mut body) = start_run(&app, &extension.session_id).await;
+    read_until(&mut body, "data: [DONE]").await;
+
+    let request = Request::builder()
+        .uri(format!("/traces/{}", run_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(bytes.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let types: Vec<&str> = lines.iter().map(|l| l["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        types,
+        [
+            "run_started",
+            "model_text",
+            "tool_call",
+            "action_request",
+            "action_result",
+            "tool_result",
+            "tool_call",
+            "action_request",
+            "action_result",
+            "tool_result",
+            "model_text",
+            "usage",
+            "run_finished",
+        ]
+    );
+    assert_eq!(lines[0]["query"], "click login");
+    assert_eq!(lines[0]["session_id"], extension.session_id.as_str());
+    assert_eq!(
+        lines[7]["command"],
+        json!({"type": "click_element", "ref": 1})
+    );
+    assert_eq!(lines[8]["success"], true);
+    assert_eq!(lines[9]["output"], r#"{"clicked":1}"#);
+    assert!(lines[8]["duration_ms"].is_u64());
+    assert_eq!(lines[11]["total_tokens"], 132);
+
+    let (_, traces) = request_json(
+        &app,
+        "GET",
+        &format!("/traces?session_id={}", extension.session_id),
+        None,
+    )
+    .await;
+    assert_eq!(traces[0]["run_id"], run_id.as_str());
+    assert!(traces[0]["finished_at"].is_u64());
+
+    let (status, _) = request_json(&app, "GET", "/traces/unknown", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_chat_completions_run_as_agent_runs() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (status, error) = request_json(
+        &app,
+        "POST",
+        "/v1/chat/completions",
+        Some(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "click login"}],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+
+    let body = json!({
+        "temperature": 0.5,
+        "messages": [
+            {"role": "system", "content": "Be brief"},
+            {"role": "user", "content": "click login"},
+        ],
+    });
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("Content-Type", "application/json")
+        .header("X-Session-Id", extension.session_id.as_str())
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let run_id = response.headers()["x-run-id"].to_str().unwrap().to_string();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let completion: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(completion["model"], "mock");
+    assert_eq!(
+        completion["choices"][0]["message"]["content"],
+        "Let me look. Clicked the Login button."
+    );
+    assert_eq!(completion["usage"]["total_tokens"], 132);
+
+    let lines = trace_lines(&app, &run_id).await;
+    assert_eq!(lines[0]["query"], "click login");
+    assert_eq!(lines[0]["model"], "mock");
+    assert_eq!(lines.last().unwrap()["type"], "run_finished");
+
+    let (_, conversations) = request_json(
+        &app,
+        "GET",
+        &format!("/conversations?session_id={}", extension.session_id),
+        None,
+    )
+    .await;
+    assert_eq!(conversations.as_array().unwrap().len(), 1);
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_chat_completions_stream_ends_after_an_error() {
+    let fixture = json!({"turns": [{"text": ["Hmm"], "error": "429 Too Many Requests"}]});
+    let (app, _) = spawn_server(&fixture.to_string()).await;
+
+    let body = json!({
+        "stream": true,
+        "stream_options": {"include_usage": true},
+        "messages": [{"role": "user", "content": "hi"}],
+    });
+    let request = Request::builder()
+        .method("POST")
+        .uri("/v1/chat/completions")
+        .header("Content-Type", "application/json")
+        .header("X-Session-Id", "s")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+    assert!(body.contains(r#""type":"rate_limit_error""#));
+    assert!(!body.contains(r#""finish_reason":"stop""#));
+    assert!(!body.contains(r#""usage""#));
+    assert!(body.trim_end().ends_with("data: [DONE]"));
+}
+
+#[tokio::test]
+async fn test_typing_after_a_click_is_redacted_from_the_trace() {
+    // The click drops the element snapshot, so the password field typed
+    // into next is unknown to the backend
+    let fixture = r#"{
+        "turns": [
+            { "tool_calls": [{ "name": "get_interactive_elements", "arguments": {} }] },
+            { "tool_calls": [{ "name": "click_element", "arguments": { "ref": 2 } }] },
+            { "tool_calls": [{ "name": "type_text", "arguments": { "ref": 3, "text": "hunter2" } }] },
+            { "text": ["Done."] }
+        ]
+    }"#;
+    let (app, ws_url) = spawn_server(fixture).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (run_id, mut body) = start_run(&app, &extension.session_id).await;
+    read_until(&mut body, "data: [DONE]").await;
+
+    let browser = extension.browser();
+    assert!(matches!(
+        browser.actions.last(),
+        Some(ActionCommand::TypeText { ref_id: 3, text }) if text == "hunter2"
+    ));
+    let lines = trace_lines(&app, &run_id).await;
+    let typed: Vec<&serde_json::Value> = lines
+        .iter()
+        .filter(|l| l["type"] == "action_request" && l["command"]["type"] == "type_text")
+        .collect();
+    assert_eq!(typed.len(), 1);
+    assert_eq!(typed[0]["command"]["text"], "[redacted]");
+    assert!(lines.iter().all(|l| !l.to_string().contains("hunter2")));
+    extension.disconnect().await;
+}
+
+/// Polls `GET /replays/{id}` until `done` holds.
+async fn wait_for_replay(
+    app: &axum::Router,
+    replay_id: &str,
+    done: impl Fn(&serde_json::Value) -> bool,
+) -> serde_json::Value {
+    for _ in 0..100 {
+        let (_, replay) = request_json(app, "GET", &format!("/replays/{}", replay_id), None).await;
+        if done(&replay) {
+            return replay;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("replay {} never got there", replay_id);
+}
+
+#[tokio::test]
+async fn test_replay_repeats_a_traced_run_without_the_model() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (run_id, mut body) = start_run(&app, &extension.session_id).await;
+    read_until(&mut body, "data: [DONE]").await;
+
+    let (status, replay) = request_json(
+        &app,
+        "POST",
+        &format!("/sessions/{}/replay", extension.session_id),
+        Some(json!({"run_id": run_id})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(replay["steps"].as_array().unwrap().len(), 2);
+    let replay_id = replay["replay_id"].as_str().unwrap().to_string();
+
+    let replay = wait_for_replay(&app, &replay_id, |r| r["status"] == "completed").await;
+    assert_eq!(replay["steps"][1]["status"], "completed");
+    assert!(replay["finished_at"].is_u64());
+
+    // The same two actions again, and no new trace: the model wasn't involved
+    let browser = extension.browser();
+    assert!(matches!(
+        browser.actions.as_slice(),
+        [
+            ActionCommand::GetInteractiveElements { limit: Some(10) },
+            ActionCommand::ClickElement { ref_id: 1 },
+            ActionCommand::GetInteractiveElements { limit: Some(10) },
+            ActionCommand::ClickElement { ref_id: 1 },
+        ]
+    ));
+    let (_, traces) = request_json(&app, "GET", "/traces", None).await;
+    assert_eq!(traces.as_array().unwrap().len(), 1);
+
+    let (status, _) = request_json(
+        &app,
+        "POST",
+        &format!("/sessions/{}/replay", extension.session_id),
+        Some(json!({"run_id": "unknown"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    extension.disconnect().await;
+}
+
+#[tokio::test]
+async fn test_replay_steps_and_pauses_on_failure() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    let (_, replay) = request_json(
+        &app,
+        "POST",
+        &format!("/sessions/{}/replay", extension.session_id),
+        Some(json!({
+            "mode": "step",
+            "commands": [
+                {"type": "click_element", "ref": 2},
+                {"type": "click_element", "ref": 9},
+                {"type": "type_text", "ref": 1, "text": "qa@example.com"},
+            ],
+        })),
+    )
+    .await;
+    assert_eq!(replay["status"], "paused");
+    let replay_id = replay["replay_id"].as_str().unwrap().to_string();
+    assert!(extension.browser().actions.is_empty());
+
+    // One step at a time
+    let (status, replay) =
+        request_json(&app, "POST", &format!("/replays/{}/step", replay_id), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(replay["status"], "paused");
+    assert_eq!(replay["cursor"], 1);
+    assert_eq!(
+        extension.browser().current_page().url,
+        "https://example.com/login"
+    );
+
+    // Resuming runs into the missing element and pauses there
+    let (status, _) = request_json(
+        &app,
+        "POST",
+        &format!("/replays/{}/resume", replay_id),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let replay = wait_for_replay(&app, &replay_id, |r| r["steps"][1]["status"] == "failed").await;
+    assert_eq!(replay["status"], "paused");
+    assert_eq!(replay["cursor"], 1);
+    assert_eq!(replay["steps"][1]["error"]["code"], "tool_failed");
+
+    let (_, replay) =
+        request_json(&app, "POST", &format!("/replays/{}/skip", replay_id), None).await;
+    assert_eq!(replay["steps"][1]["status"], "skipped");
+    assert_eq!(replay["cursor"], 2);
+
+    let (_, replay) =
+        request_json(&app, "POST", &format!("/replays/{}/step", replay_id), None).await;
+    assert_eq!(replay["status"], "completed");
+    assert_eq!(
+        extension.browser().current_page().elements[0].value,
+        "qa@example.com"
+    );
+
+    // Finished replays take no more controls
+    let (status, _) =
+        request_json(&app, "POST", &format!("/replays/{}/step", replay_id), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    extension.disconnect().await;
+}
+
+/// Polls `GET /macros/{name}` until the macro is saved.
+async fn wait_for_macro(app: &axum::Router, name: &str) -> serde_json::Value {
+    for _ in 0..100 {
+        let (status, saved) = request_json(app, "GET", &format!("/macros/{}", name), None).await;
+        if status == StatusCode::OK {
+            return saved;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("macro {} was never saved", name);
+}
+
+#[tokio::test]
+async fn test_recorded_macro_runs_as_agent_tool() {
+    let (app, ws_url) = spawn_server(include_str!("fixtures/mock_llm_macro.json")).await;
+    let site = MockSite::from_json(include_str!("fixtures/mock_site.json")).unwrap();
+    let extension = MockExtension::connect(&ws_url, site).await.unwrap();
+
+    // The user logs in by hand while record mode is on
+    extension.send(WsMessage::RecordStart {});
+    for action in [
+        UserAction::Navigate {
+            url: "https://example.com/".into(),
+        },
+        UserAction::Click { ref_id: 2 },
+        // An older extension may still send the value of a sensitive field
+        UserAction::Type {
+            ref_id: 1,
+            text: "typed-secret".into(),
+            sensitive: true,
+            name: Some("Email".into()),
+        },
+    ] {
+        extension.send(WsMessage::UserAction(action));
+    }
+    extension.send(WsMessage::RecordStop {
+        name: Some("login".into()),
+        description: Some("Log in to Example".into()),
+    });
+
+    let saved = wait_for_macro(&app, "login").await;
+    assert_eq!(saved["params"], json!(["email"]));
+    assert_eq!(
+        saved["steps"],
+        json!([
+            {"type": "navigate_to", "url": "https://example.com/"},
+            {"type": "get_interactive_elements", "limit": null},
+            {"type": "click_element", "ref": 2},
+            {"type": "get_interactive_elements", "limit": null},
+            {"type": "type_text", "ref": 1, "text": "{{email}}"},
+        ])
+    );
+    let (_, macros) = request_json(&app, "GET", "/macros", None).await;
+    assert_eq!(macros.as_array().unwrap().len(), 1);
+    assert!(!macros.to_string().contains("typed-secret"));
+
+    // Replays need every parameter
+    let (status, _) = request_json(
+        &app,
+        "POST",
+        &format!("/sessions/{}/replay", extension.session_id),
+        Some(json!({"macro": "login"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = run_agent(
+        app.clone(),
+        json!({"query": "log me in", "session_id": extension.session_id}),
+    )
+    .await;
+    assert!(body.contains(r#""name":"run_macro","status":"completed""#));
+    assert!(body.contains(r#""result":{"macro":"login","steps_run":5}"#));
+
+    let browser = extension.browser();
+    assert_eq!(browser.actions.len(), 5);
+    assert!(matches!(
+        browser.actions.last(),
+        Some(ActionCommand::TypeText { ref_id: 1, text }) if text == "qa@example.com"
+    ));
+    assert_eq!(browser.current_page().url, "https://example.com/login");
+
+    // Neither the recording nor the run that replayed it traced the value
+    let (_, traces) = request_json(&app, "GET", "/traces", None).await;
+    assert_eq!(traces.as_array().unwrap().len(), 1);
+    for trace in traces.as_array().unwrap() {
+        let lines = trace_lines(&app, trace["run_id"].as_str().unwrap()).await;
+        assert!(
+            lines
+                .iter()
+                .all(|l| !l.to_string().contains("typed-secret"))
+        );
+    }
+
+    let (status, _) = request_json(&app, "DELETE", "/macros/login", None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = request_json(&app, "GET", "/macros/login", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    extension.disconnect().await;
+}
@@ -0,0 +1,212 @@
+//! Handlers through the full router, backed by the mock LLM provider.
+
+use axum::body::{Body, to_bytes};
+use http::{Request, StatusCode};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+use backend_rig::auth::Principal;
+use backend_rig::llm::mock::MockProvider;
+use backend_rig::models::ws::{ActionResult, WsMessage};
+use backend_rig::routes::app_router;
+use backend_rig::state::AppState;
+
+fn mock_state() -> Arc<AppState> {
+    let provider = MockProvider::from_json(include_str!("fixtures/mock_llm.json")).unwrap();
+    Arc::new(AppState::new(Arc::new(provider)))
+}
+
+/// Registers a fake extension connection that answers every action.
+async fn fake_extension(state: &Arc<AppState>, session_id: &str) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    state.register_connection(session_id.to_string(), tx).await;
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            if let WsMessage::ActionRequest { request_id, .. } = msg {
+                let result = ActionResult {
+                    request_id: request_id.clone(),
+                    success: true,
+                    error: None,
+                    data: Some(json!([{"id": 1, "role": "button", "name": "Login"}])),
+                };
+                state.complete_pending_action(&request_id, result).await;
+            }
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_agent_run_rejects_unlisted_model_and_depth() {
+    let app = app_router(mock_state());
+    for body in [
+        json!({"query": "hi", "model": "not-allowed"}),
+        json!({"query": "hi", "session_id": "s", "max_depth": 500}),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/agent/run")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_agent_run_errors_follow_accept_language() {
+    let response = app_router(mock_state())
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/agent/run")
+                .header("Content-Type", "application/json")
+                .header("Accept-Language", "ja-JP,ja;q=0.9,en;q=0.8")
+                .body(Body::from(
+                    json!({"query": "hi", "model": "not-allowed"}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["code"], "invalid_request");
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("無効なリクエスト")
+    );
+}
+
+#[tokio::test]
+async fn test_agent_run_seeds_page_elements_from_request() {
+    let state = mock_state();
+    fake_extension(&state, "snapshot-session").await;
+
+    let response = app_router(state.clone())
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/agent/run")
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    json!({
+                        "query": "delete my account",
+                        "session_id": "snapshot-session",
+                        "interactive_elements": [
+                            {"id": 7, "role": "button", "name": "Delete account"}
+                        ],
+                        "page_content": "Account settings"
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Seeded before the stream starts, so the first tool call can use it
+    let page = state.page_snapshot("snapshot-session").await;
+    assert_eq!(page.element(7).unwrap().name, "Delete account");
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(
+        String::from_utf8_lossy(&body)
+            .trim_end()
+            .ends_with("data: [DONE]")
+    );
+
+    // The run's click dropped the refs; they go exactly once
+    assert!(
+        state
+            .page_snapshot("snapshot-session")
+            .await
+            .elements
+            .is_empty()
+    );
+    assert!(!state.invalidate_page_elements("snapshot-session").await);
+}
+
+#[tokio::test]
+async fn test_user_instructions_apply_to_session_page() {
+    let state = mock_state();
+    state
+        .record_page_url("jira-session", "https://jira.company.com/browse/X-1")
+        .await;
+    let app = app_router(state);
+
+    let set = json!({
+        "instruction": "Be brief.",
+        "sites": [{"host": "jira.company.com", "instruction": "Use quick-create."}]
+    });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PUT")
+                .uri("/instructions")
+                .header("Content-Type", "application/json")
+                .body(Body::from(set.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/sessions/jira-session/instructions")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["instruction"], "Be brief.\n\nUse quick-create.");
+}
+
+#[tokio::test]
+async fn test_get_session_reports_page_context() {
+    let state = mock_state();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let bound = state
+        .bind_session("conn-1", &Principal::anonymous(), None, tx)
+        .await;
+    let id = bound.session_id;
+    state
+        .record_page_visit(&id, "https://example.com/", Some("Example".into()), Some(3))
+        .await;
+    state.record_page_url(&id, "https://example.com/docs").await;
+
+    let response = app_router(state)
+        .oneshot(
+            Request::builder()
+                .uri(format!("/sessions/{}", id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["connected"], true);
+    assert_eq!(body["page"]["url"], "https://example.com/docs");
+    assert_eq!(body["page"]["title"], serde_json::Value::Null);
+    assert_eq!(body["page"]["tab_id"], 3);
+    assert_eq!(body["page"]["history"][0]["title"], "Example");
+    assert_eq!(body["page"]["history"].as_array().unwrap().len(), 2);
+}
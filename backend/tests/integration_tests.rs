@@ -8,10 +8,7 @@ use axum::{
 use serde_json::json;
 use tower::ServiceExt; // for `oneshot`
 
-// Include the actual DTO from the source to ensure we are testing the real implementation
-#[path = "../src/dtos/agent.rs"]
-mod agent_dto;
-use agent_dto::AgentRequest;
+use backend_rig::dtos::agent::AgentRequest;
 
 #[test]
 fn test_agent_request_deserialization_alias() {
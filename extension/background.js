@@ -9,20 +9,64 @@ let isConnected = false;
 let lastTabId = null;
 let lastUrl = null;
 let wsSessionId = null;
+let wsResumeToken = null;
+// Record mode: the user's own clicks, typing and navigations are streamed
+// to the backend, which saves them as a macro on record_stop
+let isRecording = false;
+chrome.storage.session.get('isRecording').then((stored) => {
+  isRecording = stored.isRecording || false;
+});
+
+// Navigations the user started directly; link clicks and form posts are
+// recorded as the click that caused them
+const RECORDED_TRANSITIONS = [
+  'typed',
+  'auto_bookmark',
+  'generated',
+  'keyword',
+  'start_page',
+  'reload',
+];
 
 // Setup side panel behavior
 chrome.sidePanel
   .setPanelBehavior({ openPanelOnActionClick: true })
   .catch((error) => console.error('[Background] Side panel error:', error));
 
+// Build the WebSocket URL, presenting the previous session so the backend
+// can resume it (survives service worker restarts via storage.session)
+async function buildWebSocketUrl() {
+  if (!wsSessionId || !wsResumeToken) {
+    const stored = await chrome.storage.session.get([
+      'wsSessionId',
+      'wsResumeToken',
+    ]);
+    wsSessionId = stored.wsSessionId || null;
+    wsResumeToken = stored.wsResumeToken || null;
+  }
+  const params = new URLSearchParams();
+  // Browsers can't set headers on WebSocket handshakes, so the key goes in
+  // the query string
+  const { apiKey } = await chrome.storage.local.get('apiKey');
+  if (apiKey) {
+    params.set('access_token', apiKey);
+  }
+  if (wsSessionId && wsResumeToken) {
+    params.set('session_id', wsSessionId);
+    params.set('resume_token', wsResumeToken);
+  }
+  const query = params.toString();
+  return query ? `${BACKEND_WS_URL}?${query}` : BACKEND_WS_URL;
+}
+
 // Initialize WebSocket connection
-function connectWebSocket() {
+async function connectWebSocket() {
   if (ws && ws.readyState === WebSocket.OPEN) {
     return;
   }
 
   try {
-    ws = new WebSocket(BACKEND_WS_URL);
+    ws = new WebSocket(await buildWebSocketUrl());
 
     ws.onopen = () => {
       isConnected = true;
@@ -31,7 +75,7 @@ function connectWebSocket() {
 
     ws.onclose = () => {
       isConnected = false;
-      wsSessionId = null;
+      // Keep wsSessionId/wsResumeToken so the next connection can resume
       stopContextUpdates();
       // Attempt reconnection after 5 seconds
       setTimeout(connectWebSocket, 5000);
@@ -47,6 +91,8 @@ function connectWebSocket() {
 
         if (message.type === 'session_init') {
           wsSessionId = message.data.session_id;
+          wsResumeToken = message.data.resume_token || null;
+          chrome.storage.session.set({ wsSessionId, wsResumeToken });
         } else if (message.type === 'action_request') {
           const { request_id, command } = message.data;
           // Forward action to sidepanel for UI display and execution
@@ -62,6 +108,20 @@ function connectWebSocket() {
             },
           });
           ws.send(response);
+        } else if (message.type === 'record_stopped') {
+          // { name, steps, error }
+          chrome.runtime
+            .sendMessage({ action: 'record_stopped', data: message.data })
+            .catch(() => {
+              console.log('[Background] Sidepanel not available for recording');
+            });
+        } else if (message.type === 'approval_required') {
+          // The backend paused a risky action; let the sidepanel ask the user
+          chrome.runtime
+            .sendMessage({ action: 'approval_required', data: message.data })
+            .catch(() => {
+              console.log('[Background] Sidepanel not available for approval');
+            });
         }
       } catch (e) {
         console.error('[Background] Error processing message:', e);
@@ -286,10 +346,10 @@ async function captureAndSendContext(options = {}) {
 
     // Build context object
     const context = {
-      type: 'context_update',
       timestamp: new Date().toISOString(),
       url: tab.url,
       title: tab.title,
+      tab_id: tab.id,
       content: pageContent ? pageContent.text : null,
       screenshot: screenshot,
     };
@@ -297,8 +357,8 @@ async function captureAndSendContext(options = {}) {
     // Apply client-side privacy filter
     const sanitizedContext = sanitizeContext(context);
 
-    // Send to backend
-    ws.send(JSON.stringify(sanitizedContext));
+    // Send to backend; it keeps the session's current page and history
+    ws.send(JSON.stringify({ type: 'session_update', data: sanitizedContext }));
   } catch (error) {
     console.error('[Background] Error capturing context:', error);
   }
@@ -362,6 +422,45 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
     sendResponse({ connected: isConnected });
   } else if (message.action === 'getWsSessionId') {
     sendResponse({ sessionId: wsSessionId });
+  } else if (message.action === 'approval_response') {
+    // { approval_id, decision: 'approve' | 'edit' | 'reject', command?, reason? }
+    if (ws && ws.readyState === WebSocket.OPEN) {
+      ws.send(JSON.stringify({ type: 'approval_response', data: message.data }));
+      sendResponse({ success: true });
+    } else {
+      sendResponse({ success: false, error: 'WebSocket not connected' });
+    }
+  } else if (message.action === 'cancel_run') {
+    // { run_id }
+    if (ws && ws.readyState === WebSocket.OPEN) {
+      ws.send(JSON.stringify({ type: 'cancel_run', data: message.data }));
+      sendResponse({ success: true });
+    } else {
+      sendResponse({ success: false, error: 'WebSocket not connected' });
+    }
+  } else if (message.action === 'record_start') {
+    if (ws && ws.readyState === WebSocket.OPEN) {
+      ws.send(JSON.stringify({ type: 'record_start', data: {} }));
+      setRecording(true);
+      sendResponse({ success: true });
+    } else {
+      sendResponse({ success: false, error: 'WebSocket not connected' });
+    }
+  } else if (message.action === 'record_stop') {
+    // { name, description }; without a name the recording is discarded
+    if (ws && ws.readyState === WebSocket.OPEN) {
+      ws.send(JSON.stringify({ type: 'record_stop', data: message.data || {} }));
+      sendResponse({ success: true });
+    } else {
+      sendResponse({ success: false, error: 'WebSocket not connected' });
+    }
+    setRecording(false);
+  } else if (message.action === 'getRecordingState') {
+    sendResponse({ recording: isRecording });
+  } else if (message.action === 'user_action') {
+    // { kind: 'click' | 'type', ref, text?, sensitive?, name? } from content.js
+    sendUserAction(message.data);
+    sendResponse({ success: true });
   } else if (message.action === 'forceContextUpdate') {
     const fullPage = message.fullPage || false;
     captureAndSendContext({ forceUpdate: true, fullPage }).then(() => {
@@ -379,6 +478,43 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
   return true;
 });
 
+// Turns record mode on or off here and in every tab's content script
+async function setRecording(recording) {
+  isRecording = recording;
+  chrome.storage.session.set({ isRecording });
+  const tabs = await chrome.tabs.query({});
+  for (const tab of tabs) {
+    chrome.tabs
+      .sendMessage(tab.id, { action: 'setRecording', value: recording })
+      .catch(() => {
+        // Content script not loaded (e.g. chrome:// pages)
+      });
+  }
+}
+
+function sendUserAction(action) {
+  if (!isRecording || !ws || ws.readyState !== WebSocket.OPEN) return;
+  ws.send(JSON.stringify({ type: 'user_action', data: action }));
+}
+
+chrome.webNavigation.onCommitted.addListener((details) => {
+  if (!isRecording || details.frameId !== 0) return;
+  const direct =
+    RECORDED_TRANSITIONS.includes(details.transitionType) ||
+    details.transitionQualifiers.includes('forward_back') ||
+    details.transitionQualifiers.includes('from_address_bar');
+  if (direct && /^https?:/.test(details.url)) {
+    sendUserAction({ kind: 'navigate', url: details.url });
+  }
+});
+
+// Reconnect with the new credentials when the API key changes
+chrome.storage.onChanged.addListener((changes, area) => {
+  if (area === 'local' && changes.apiKey && ws) {
+    ws.close();
+  }
+});
+
 // Initialize connection when service worker starts
 connectWebSocket();
 
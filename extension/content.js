@@ -357,13 +357,18 @@ function generateSnapshot(limit = 300) {
     if (isInteractive(element) && isElementVisible(element)) {
       const id = refId++;
       refToElementMap.set(id, element);
-      tree.push({
+      const node = {
         id: id,
         role: getElementRole(element),
         name: getAccessibleName(element),
         tag: element.tagName,
         bounds: getElementBounds(element),
-      });
+      };
+      // Lets the backend recognise sensitive fields (e.g. passwords)
+      if (element.tagName === 'INPUT') {
+        node.input_type = element.type;
+      }
+      tree.push(node);
     }
 
     // Continue DFS even if current element is not interactive
@@ -666,6 +671,93 @@ function executeAction(command) {
   }
 }
 
+// --- Record Mode ---
+
+// While recording, the user's clicks and typing are reported by ref, taken
+// from a fresh snapshot so they match what get_interactive_elements returns
+// when the macro runs
+let isRecording = false;
+
+const TEXT_INPUT_TYPES = [
+  'text',
+  'email',
+  'password',
+  'search',
+  'tel',
+  'url',
+  'number',
+];
+
+function isTextField(el) {
+  if (el.tagName === 'TEXTAREA' || el.tagName === 'SELECT') return true;
+  return el.tagName === 'INPUT' && TEXT_INPUT_TYPES.includes(el.type || 'text');
+}
+
+/**
+ * Finds the ref of `target` or its closest interactive ancestor
+ */
+function refForElement(target) {
+  generateSnapshot();
+  const refs = new Map();
+  refToElementMap.forEach((element, id) => refs.set(element, id));
+  let el = target;
+  while (el && el !== document.body) {
+    if (refs.has(el)) return refs.get(el);
+    el = el.parentElement || el.getRootNode().host;
+  }
+  return null;
+}
+
+function sendUserAction(data) {
+  chrome.runtime.sendMessage({ action: 'user_action', data }).catch(() => {
+    // Background restarting; the action is lost
+  });
+}
+
+function onRecordedClick(event) {
+  // Focusing a field is implied by typing into it
+  if (!event.isTrusted || isTextField(event.target)) return;
+  const ref = refForElement(event.target);
+  if (ref !== null) {
+    sendUserAction({ kind: 'click', ref });
+  }
+}
+
+function onRecordedChange(event) {
+  const el = event.target;
+  if (!event.isTrusted || !isTextField(el)) return;
+  const ref = refForElement(el);
+  if (ref !== null) {
+    const sensitive = el.type === 'password';
+    sendUserAction({
+      kind: 'type',
+      ref,
+      // Passwords never leave the page; the macro gets a parameter instead
+      ...(sensitive ? {} : { text: el.value }),
+      sensitive,
+      name: getAccessibleName(el),
+    });
+  }
+}
+
+function setRecording(recording) {
+  if (recording === isRecording) return;
+  isRecording = recording;
+  // Capture phase: the click is seen before the page reacts to it
+  if (recording) {
+    document.addEventListener('click', onRecordedClick, true);
+    document.addEventListener('change', onRecordedChange, true);
+  } else {
+    document.removeEventListener('click', onRecordedClick, true);
+    document.removeEventListener('change', onRecordedChange, true);
+  }
+}
+
+chrome.runtime
+  .sendMessage({ action: 'getRecordingState' })
+  .then((response) => setRecording(response?.recording || false))
+  .catch(() => {});
+
 // Listen for messages from background script
 
 chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
@@ -711,6 +803,9 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
   } else if (message.action === 'execute') {
     const result = executeAction(message.command);
     sendResponse(result);
+  } else if (message.action === 'setRecording') {
+    setRecording(message.value);
+    sendResponse({ success: true });
   } else if (message.action === 'toggleDebug') {
     if (message.value) {
       showDebugBadges();
@@ -45,6 +45,7 @@ async function sendMessage() {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
+          ...(await authHeaders()),
         },
         body: JSON.stringify({ message, stream: true }),
       }
@@ -68,7 +69,7 @@ async function sendMessage() {
         messageDiv.textContent = fullText;
         chatContainer.scrollTop = chatContainer.scrollHeight;
       } else if (event.type === 'error') {
-        addMessage('Error: ' + event.value);
+        addMessage('Error: ' + window.parseStreamError(event.value).message);
       } else if (event.type === 'done') {
         updateStatus(true);
       }
@@ -92,6 +93,12 @@ messageInput.addEventListener('keypress', (e) => {
   }
 });
 
+// API key set in the sidepanel settings
+async function authHeaders() {
+  const { apiKey } = await chrome.storage.local.get('apiKey');
+  return apiKey ? { 'X-API-Key': apiKey } : {};
+}
+
 // Check backend connection on load
 async function checkConnection() {
   try {
@@ -357,6 +357,7 @@ document.addEventListener('DOMContentLoaded', () => {
   const screenshotModeLabel = document.getElementById('screenshot-mode-label');
   const confirmModeToggle = document.getElementById('confirm-mode-toggle');
   const debugModeToggle = document.getElementById('debug-mode-toggle');
+  const recordModeToggle = document.getElementById('record-mode-toggle');
 
   // Modals
   const settingsModal = document.getElementById('settings-modal');
@@ -366,6 +367,7 @@ document.addEventListener('DOMContentLoaded', () => {
   const closeSessionsBtn = document.getElementById('close-sessions');
   const closeDeleteBtn = document.getElementById('close-delete');
   const saveSettingsBtn = document.getElementById('save-settings-btn');
+  const apiKeyInput = document.getElementById('api-key');
   const confirmDeleteBtn = document.getElementById('confirm-delete-btn');
   const cancelDeleteBtn = document.getElementById('cancel-delete-btn');
   const sessionListEl = document.getElementById('session-list');
@@ -395,6 +397,7 @@ document.addEventListener('DOMContentLoaded', () => {
   let currentSession = null;
   let currentImage = null; // Base64 string
   let wsSessionId = null;
+  let currentRunId = null; // Set by run_started while an agent run streams
 
   // Theme management
   function getTheme() {
@@ -420,6 +423,12 @@ document.addEventListener('DOMContentLoaded', () => {
   }
 
   // Fetch WebSocket session ID from background script
+  // Credentials for protected backend endpoints
+  async function authHeaders() {
+    const { apiKey } = await chrome.storage.local.get('apiKey');
+    return apiKey ? { 'X-API-Key': apiKey } : {};
+  }
+
   async function fetchWsSessionId(retries = 3, delay = 500) {
     for (let attempt = 1; attempt <= retries; attempt++) {
       try {
@@ -655,6 +664,51 @@ document.addEventListener('DOMContentLoaded', () => {
     chatContainer.scrollTop = chatContainer.scrollHeight;
   }
 
+  // Create the collapsible step list shown above the assistant reply
+  function createToolTrace() {
+    const messageDiv = document.createElement('div');
+    messageDiv.className = 'message assistant';
+    const details = document.createElement('details');
+    details.className = 'tool-trace';
+    const summary = document.createElement('summary');
+    summary.textContent = '🔧 Langkah agent';
+    details.appendChild(summary);
+    messageDiv.appendChild(details);
+
+    const typingEl = document.getElementById('typing-indicator');
+    if (typingEl) {
+      chatContainer.insertBefore(messageDiv, typingEl);
+    } else {
+      chatContainer.appendChild(messageDiv);
+    }
+    return details;
+  }
+
+  // Add or update one tool step; calling/completed/failed events share an id
+  function renderToolStep(trace, toolInfo) {
+    let step = trace.querySelector(`[data-tool-id="${CSS.escape(toolInfo.id)}"]`);
+    if (!step) {
+      step = document.createElement('div');
+      step.className = 'tool-step';
+      step.dataset.toolId = toolInfo.id;
+      step.dataset.name = toolInfo.name;
+      step.dataset.args = toolInfo.arguments ? JSON.stringify(toolInfo.arguments) : '';
+      trace.appendChild(step);
+    }
+
+    const label = `${step.dataset.name || toolInfo.name}(${step.dataset.args})`;
+    const duration = toolInfo.duration_ms != null ? ` · ${toolInfo.duration_ms}ms` : '';
+    if (toolInfo.status === 'calling') {
+      step.textContent = `⏳ ${label}`;
+    } else if (toolInfo.status === 'completed') {
+      step.textContent = `✅ ${label}${duration}`;
+      step.title = JSON.stringify(toolInfo.result);
+    } else {
+      step.classList.add('failed');
+      step.textContent = `❌ ${label}${duration} — ${toolInfo.error}`;
+    }
+  }
+
   // Remove typing indicator
   function hideTyping() {
     const typing = document.getElementById('typing-indicator');
@@ -762,6 +816,7 @@ document.addEventListener('DOMContentLoaded', () => {
           method: 'POST',
           headers: {
             'Content-Type': 'application/json',
+            ...(await authHeaders()),
           },
           body: JSON.stringify({
             query: text,
@@ -786,6 +841,7 @@ document.addEventListener('DOMContentLoaded', () => {
       let renderTimeout = null;
       let isFirstToken = true;
       let tokenUsage = null;
+      let toolTrace = null;
 
       for await (const event of window.readSSEStream(response)) {
         if (event.type === 'data') {
@@ -815,7 +871,10 @@ document.addEventListener('DOMContentLoaded', () => {
           // Tool call notification from backend
           try {
             const toolInfo = JSON.parse(event.value);
-            console.log('[Tool]', toolInfo.name, toolInfo.status);
+            console.log('[Tool]', toolInfo.id, toolInfo.name, toolInfo.status);
+
+            if (!toolTrace) toolTrace = createToolTrace();
+            renderToolStep(toolTrace, toolInfo);
 
             // Show tool indicator in the typing area or create a status element
             if (toolInfo.status === 'calling' && toolInfo.name) {
@@ -835,7 +894,7 @@ document.addEventListener('DOMContentLoaded', () => {
                   typingEl.appendChild(newLabel);
                 }
               }
-            } else if (toolInfo.status === 'completed') {
+            } else {
               // Remove tool label when done
               const typingEl = document.getElementById('typing-indicator');
               if (typingEl) {
@@ -846,6 +905,31 @@ document.addEventListener('DOMContentLoaded', () => {
           } catch (e) {
             console.log('[Tool]', event.value);
           }
+        } else if (event.type === 'run_started') {
+          try {
+            currentRunId = JSON.parse(event.value).run_id;
+          } catch (e) {
+            console.log('[Run]', event.value);
+          }
+        } else if (event.type === 'cancelled') {
+          currentRunId = null;
+          if (bubbleDiv) {
+            fullText += '\n\n⏹️ Dihentikan';
+            updateAssistantBubble(bubbleDiv, fullText);
+          }
+        } else if (event.type === 'approval_required') {
+          try {
+            showApprovalRequest(JSON.parse(event.value));
+          } catch (e) {
+            console.log('[Approval]', event.value);
+          }
+        } else if (event.type === 'approval_resolved') {
+          try {
+            const resolved = JSON.parse(event.value);
+            dismissApprovalRequest(resolved.approval_id);
+          } catch (e) {
+            console.log('[Approval]', event.value);
+          }
         } else if (event.type === 'usage') {
           // Parse token usage from backend
           try {
@@ -859,10 +943,11 @@ document.addEventListener('DOMContentLoaded', () => {
             console.warn('Failed to parse token usage:', e);
           }
         } else if (event.type === 'error') {
-          console.error('Stream error:', event.value);
+          const streamError = parseStreamError(event.value);
+          console.error('Stream error:', streamError.code, streamError.message);
           // Show error in bubble if we have one
           if (bubbleDiv) {
-            fullText += `\n\n⚠️ ${event.value}`;
+            fullText += `\n\n⚠️ ${streamError.message}`;
             updateAssistantBubble(bubbleDiv, fullText);
           }
         } else if (event.type === 'done') {
@@ -935,6 +1020,7 @@ document.addEventListener('DOMContentLoaded', () => {
     } finally {
       sendBtn.disabled = false;
       isProcessing = false;
+      currentRunId = null;
       messageInput.focus();
     }
   }
@@ -1046,6 +1132,10 @@ document.addEventListener('DOMContentLoaded', () => {
           currentSession && currentSession.customInstruction
             ? currentSession.customInstruction
             : '';
+        // Stored in chrome.storage so the background WebSocket can use it
+        chrome.storage.local.get('apiKey').then(({ apiKey }) => {
+          apiKeyInput.value = apiKey || '';
+        });
         openModal(settingsModal);
       } catch (e) {
         console.error('Error opening settings:', e);
@@ -1061,6 +1151,7 @@ document.addEventListener('DOMContentLoaded', () => {
       screenshotDefault: settingScreenshotDefault.checked,
     };
     SettingsManager.saveSettings(settings);
+    chrome.storage.local.set({ apiKey: apiKeyInput.value.trim() });
 
     // Sync dropdown toggle with the new default setting
     screenshotToggle.checked = settingScreenshotDefault.checked;
@@ -1263,9 +1354,25 @@ document.addEventListener('DOMContentLoaded', () => {
     if (this.value === '') this.style.height = '';
   });
 
-  // Handle Enter key for textarea
+  // Stop the running agent; the backend ends the stream with `cancelled`
+  function cancelCurrentRun() {
+    if (!currentRunId) return;
+    chrome.runtime.sendMessage(
+      { action: 'cancel_run', data: { run_id: currentRunId } },
+      (response) => {
+        if (!response || !response.success) {
+          console.error('[Run] Failed to cancel:', response?.error);
+        }
+      }
+    );
+  }
+
+  // Handle Enter key for textarea, Escape stops a running agent
   messageInput.addEventListener('keydown', (e) => {
-    if (e.key === 'Enter' && !e.shiftKey) {
+    if (e.key === 'Escape' && isProcessing) {
+      e.preventDefault();
+      cancelCurrentRun();
+    } else if (e.key === 'Enter' && !e.shiftKey) {
       e.preventDefault();
       sendMessage();
       // Reset height
@@ -1378,6 +1485,48 @@ document.addEventListener('DOMContentLoaded', () => {
     debugModeToggle.addEventListener('click', (e) => e.stopPropagation());
   }
 
+  // Record Mode Logic: the background streams the user's own actions to the
+  // backend, which saves them as a macro the agent can run
+  if (recordModeToggle) {
+    chrome.runtime.sendMessage({ action: 'getRecordingState' }, (response) => {
+      recordModeToggle.checked = response?.recording || false;
+    });
+
+    recordModeToggle.addEventListener('change', (e) => {
+      e.stopPropagation();
+      if (e.target.checked) {
+        chrome.runtime.sendMessage({ action: 'record_start' }, (response) => {
+          if (!response || !response.success) {
+            recordModeToggle.checked = false;
+            console.error('[Record] Failed to start:', response?.error);
+          }
+        });
+        return;
+      }
+      // Cancelling the prompt discards the recording
+      const name = prompt('Nama makro (huruf, angka, _ atau -):');
+      const description = name ? prompt('Deskripsi (opsional):') : null;
+      chrome.runtime.sendMessage({
+        action: 'record_stop',
+        data: { name: name || null, description: description || null },
+      });
+    });
+
+    recordModeToggle.addEventListener('click', (e) => e.stopPropagation());
+  }
+
+  function showRecordResult(result) {
+    let text;
+    if (result.error) {
+      text = `❌ Makro tidak disimpan: ${result.error}`;
+    } else if (result.name) {
+      text = `⏺️ Makro **${result.name}** disimpan (${result.steps} langkah).`;
+    } else {
+      text = '⏹️ Rekaman dibuang.';
+    }
+    renderMessage({ role: 'assistant', text, timestamp: Date.now() });
+  }
+
   // SVG Icons for actions
   const ACTION_ICONS = {
     navigate: `<svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2"><circle cx="12" cy="12" r="10"/><line x1="2" y1="12" x2="22" y2="12"/><path d="M12 2a15.3 15.3 0 0 1 4 10 15.3 15.3 0 0 1-4 10 15.3 15.3 0 0 1-4-10 15.3 15.3 0 0 1 4-10z"/></svg>`,
@@ -1525,6 +1674,102 @@ document.addEventListener('DOMContentLoaded', () => {
     };
   }
 
+  // Backend approval requests arrive both over SSE and via the background
+  // WebSocket, so they are deduplicated by approval_id
+  const shownApprovals = new Set();
+
+  function editableField(action) {
+    if (action.type === 'type_text') return 'text';
+    if (action.type === 'navigate_to') return 'url';
+    return null;
+  }
+
+  function showApprovalRequest(request) {
+    if (!request || shownApprovals.has(request.approval_id)) return;
+    shownApprovals.add(request.approval_id);
+
+    const action = request.command;
+    const field = editableField(action);
+    const formatted = formatAction(action);
+    const messageDiv = document.createElement('div');
+    messageDiv.className = 'message assistant';
+    messageDiv.dataset.approvalId = request.approval_id;
+
+    const bubbleDiv = document.createElement('div');
+    bubbleDiv.className = 'bubble';
+    bubbleDiv.style.background = 'var(--bg-tertiary)';
+    bubbleDiv.style.border = '1px solid var(--accent)';
+
+    bubbleDiv.innerHTML = `
+      <div style="font-weight: 600; margin-bottom: 4px;">⚠️ Persetujuan Diperlukan</div>
+      <div class="approval-reason" style="font-size: 12px; color: var(--text-secondary); margin-bottom: 8px;"></div>
+      <div class="action-status executing" style="margin-bottom: 12px;">
+        <span class="action-icon">${formatted.icon}</span>
+        <div class="action-content">
+          <div class="action-type">${formatted.label}</div>
+          <div class="action-detail"></div>
+        </div>
+      </div>
+      ${field ? '<input class="approval-edit" type="text" style="width: 100%; margin-bottom: 8px;" />' : ''}
+      <div style="display: flex; gap: 8px;">
+        <button class="primary-btn approve-btn" style="flex: 1;">Setuju</button>
+        <button class="secondary-btn cancel-btn" style="flex: 1;">Tolak</button>
+      </div>
+    `;
+    bubbleDiv.querySelector('.approval-reason').textContent = request.reason;
+    bubbleDiv.querySelector('.action-detail').textContent = formatted.detail;
+
+    const editInput = bubbleDiv.querySelector('.approval-edit');
+    if (editInput) editInput.value = action[field];
+
+    messageDiv.appendChild(bubbleDiv);
+    chatContainer.appendChild(messageDiv);
+    chatContainer.scrollTop = chatContainer.scrollHeight;
+
+    bubbleDiv.querySelector('.approve-btn').onclick = () => {
+      messageDiv.remove();
+      if (editInput && editInput.value !== action[field]) {
+        sendApprovalResponse({
+          approval_id: request.approval_id,
+          decision: 'edit',
+          command: { ...action, [field]: editInput.value },
+        });
+      } else {
+        sendApprovalResponse({ approval_id: request.approval_id, decision: 'approve' });
+      }
+    };
+
+    bubbleDiv.querySelector('.cancel-btn').onclick = () => {
+      messageDiv.remove();
+      sendApprovalResponse({
+        approval_id: request.approval_id,
+        decision: 'reject',
+        reason: 'Rejected by user',
+      });
+      renderMessage({
+        role: 'assistant',
+        text: '❌ Aksi ditolak oleh pengguna.',
+        timestamp: Date.now(),
+      });
+    };
+  }
+
+  // Removes the prompt once the backend resolved it (e.g. timeout)
+  function dismissApprovalRequest(approvalId) {
+    const pending = chatContainer.querySelector(
+      `[data-approval-id="${CSS.escape(approvalId)}"]`
+    );
+    if (pending) pending.remove();
+  }
+
+  function sendApprovalResponse(data) {
+    chrome.runtime.sendMessage({ action: 'approval_response', data }, (response) => {
+      if (!response || !response.success) {
+        console.error('[Approval] Failed to send response:', response?.error);
+      }
+    });
+  }
+
   async function performAction(action) {
     // Show executing status
     const statusMessage = renderActionStatus(action, 'executing');
@@ -1574,6 +1819,14 @@ document.addEventListener('DOMContentLoaded', () => {
 
   // Listener for actions from background/backend
   chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
+    if (message.action === 'approval_required') {
+      showApprovalRequest(message.data);
+      return;
+    }
+    if (message.action === 'record_stopped') {
+      showRecordResult(message.data);
+      return;
+    }
     if (message.action === 'propose_action') {
       const action = message.data;
 
@@ -10,6 +10,34 @@
  *   }
  * }
  */
+/**
+ * Named events (error, usage, tool, conversation, ...) keep their name as the
+ * type; unnamed events are text tokens.
+ */
+function toStreamEvent(eventName, data) {
+  if (eventName === 'data' || eventName === 'message') {
+    return { type: 'data', value: data };
+  }
+  return { type: eventName, value: data };
+}
+
+/**
+ * Extracts a displayable message from an `error` event. The backend sends
+ * `{"__type":"error","code":...,"message":...}`; older backends and
+ * connection failures send plain text.
+ */
+function parseStreamError(value) {
+  try {
+    const payload = JSON.parse(value);
+    if (payload && payload.__type === 'error') {
+      return { code: payload.code, message: payload.message, retryable: !!payload.retryable };
+    }
+  } catch (e) {
+    // Not JSON
+  }
+  return { code: 'unknown', message: value, retryable: false };
+}
+
 async function* readSSEStream(response) {
   const reader = response.body.getReader();
   const decoder = new TextDecoder('utf-8');
@@ -64,15 +92,7 @@ async function* readSSEStream(response) {
           return;
         }
 
-        if (currentEvent === 'error') {
-          yield { type: 'error', value: fullData };
-        } else if (currentEvent === 'usage') {
-          yield { type: 'usage', value: fullData };
-        } else if (currentEvent === 'tool') {
-          yield { type: 'tool', value: fullData };
-        } else {
-          yield { type: 'data', value: fullData };
-        }
+        yield toStreamEvent(currentEvent, fullData);
       }
     }
 
@@ -99,14 +119,8 @@ async function* readSSEStream(response) {
 
         if (fullData.trim() === '[DONE]') {
           yield { type: 'done', value: '' };
-        } else if (currentEvent === 'error') {
-          yield { type: 'error', value: fullData };
-        } else if (currentEvent === 'usage') {
-          yield { type: 'usage', value: fullData };
-        } else if (currentEvent === 'tool') {
-          yield { type: 'tool', value: fullData };
         } else {
-          yield { type: 'data', value: fullData };
+          yield toStreamEvent(currentEvent, fullData);
         }
       }
     }
@@ -119,3 +133,4 @@ async function* readSSEStream(response) {
 
 // Expose to window global for non-module extension environment
 window.readSSEStream = readSSEStream;
+window.parseStreamError = parseStreamError;
@@ -175,4 +175,15 @@ describe('Snapshot Generation', () => {
     expect(snapshot.tree[1].name).toBe('Username');
     expect(snapshot.tree[2].name).toBe('Profile Picture');
   });
+
+  test('should report input types', () => {
+    document.body.innerHTML = `
+      <input type="password" aria-label="Password">
+      <button>Sign in</button>
+    `;
+
+    const snapshot = generateSnapshot();
+    expect(snapshot.tree[0].input_type).toBe('password');
+    expect(snapshot.tree[1].input_type).toBeUndefined();
+  });
 });